@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use aigame::chunk::{
+    build_mesh, ChunkData, CompiledNoise, NoiseBiome, NoiseGenerator, NoiseNode, TerrainBlocks, TerrainGenerator, Voxel,
+    VoxelArray, WorldGenerator, CHUNK_SIZE,
+};
+use bevy::math::IVec3;
+use bevy::utils::HashMap;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// Chunks around the origin, where the terrain is, out to this many chunks.
+const RADIUS: i32 = 4;
+
+fn positions() -> Vec<IVec3> {
+    let mut positions = vec![];
+    for y in -RADIUS..=RADIUS {
+        for z in -RADIUS..=RADIUS {
+            for x in -RADIUS..=RADIUS {
+                let pos = IVec3::new(x, y, z);
+                if pos.dot(pos) <= RADIUS * RADIUS {
+                    positions.push(pos);
+                }
+            }
+        }
+    }
+    positions
+}
+
+fn vanilla() -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string("mods/vanilla.json").unwrap()).unwrap()
+}
+
+// Blocks of the vanilla mod, with the ids they get when it is the only mod loaded.
+fn vanilla_blocks() -> TerrainBlocks {
+    let json = vanilla();
+    let blocks = json["defs"]["blocks"].as_array().unwrap();
+    TerrainBlocks::find(|name| {
+        let id = name.strip_prefix("vanilla:")?;
+        let index = blocks.iter().position(|block| block["id"] == id)?;
+        Some(Voxel::new(index as u16 + 1, 0))
+    })
+    .unwrap()
+}
+
+fn terrain() -> TerrainGenerator {
+    TerrainGenerator {
+        blocks: vanilla_blocks(),
+        ..Default::default()
+    }
+}
+
+fn loaded() -> Vec<ChunkData> {
+    let generator = terrain();
+    positions().into_iter().map(|pos| generator.generate(pos, 1337)).collect()
+}
+
+// The hills of the vanilla mod, which should match the built-in terrain.
+fn vanilla_hills() -> NoiseGenerator {
+    let json = vanilla();
+    let graph = &json["defs"]["noise_graphs"][0];
+    let nodes: HashMap<String, NoiseNode> = serde_json::from_value(graph["nodes"].clone()).unwrap();
+    let blocks = vanilla_blocks();
+    NoiseGenerator {
+        biomes: vec![NoiseBiome {
+            density: Arc::new(CompiledNoise::compile(&nodes, graph["output"].as_str().unwrap()).unwrap()),
+            layers: blocks.layers,
+            stone: blocks.stone,
+            temperature: None,
+        }],
+        ores: blocks.ores,
+    }
+}
+
+// The same chunk as the dense array `ChunkData` used to be.
+fn dense(data: &ChunkData) -> Box<VoxelArray> {
+    let mut voxels = Box::new(VoxelArray::default());
+    for (i, voxel) in data.iter().enumerate() {
+        voxels[i / (CHUNK_SIZE * CHUNK_SIZE)][i / CHUNK_SIZE % CHUNK_SIZE][i % CHUNK_SIZE] = voxel;
+    }
+    voxels
+}
+
+fn memory(c: &mut Criterion) {
+    let chunks = loaded();
+    // Criterion only measures time, so the memory of the loaded radius is reported once here.
+    let uniform = chunks.iter().filter(|data| data.uniform().is_some()).count();
+    let paletted: usize = chunks.iter().map(|data| data.memory()).sum();
+    let dense_size = chunks.len() * std::mem::size_of::<VoxelArray>();
+    println!(
+        "{} chunks, {} uniform: {} KB paletted, {} KB dense",
+        chunks.len(),
+        uniform,
+        paletted / 1024,
+        dense_size / 1024
+    );
+
+    // What every mesh task copies.
+    c.bench_function("clone loaded chunks", |b| {
+        b.iter(|| {
+            for data in chunks.iter() {
+                black_box(data.clone());
+            }
+        })
+    });
+    let arrays: Vec<Box<VoxelArray>> = chunks.iter().map(dense).collect();
+    c.bench_function("clone loaded chunks dense", |b| {
+        b.iter(|| {
+            for voxels in arrays.iter() {
+                black_box(voxels.clone());
+            }
+        })
+    });
+}
+
+fn access(c: &mut Criterion) {
+    let chunks = loaded();
+    c.bench_function("get every voxel", |b| {
+        b.iter(|| {
+            let mut solid = 0;
+            for data in chunks.iter() {
+                for y in 0..CHUNK_SIZE {
+                    for z in 0..CHUNK_SIZE {
+                        for x in 0..CHUNK_SIZE {
+                            solid += !data.get(x, y, z).unwrap().is_empty() as usize;
+                        }
+                    }
+                }
+            }
+            black_box(solid)
+        })
+    });
+    let arrays: Vec<Box<VoxelArray>> = chunks.iter().map(dense).collect();
+    c.bench_function("get every voxel dense", |b| {
+        b.iter(|| {
+            let mut solid = 0;
+            for voxels in arrays.iter() {
+                for y in 0..CHUNK_SIZE {
+                    for z in 0..CHUNK_SIZE {
+                        for x in 0..CHUNK_SIZE {
+                            solid += !voxels[y][z][x].is_empty() as usize;
+                        }
+                    }
+                }
+            }
+            black_box(solid)
+        })
+    });
+}
+
+fn meshing(c: &mut Criterion) {
+    let chunks: Vec<ChunkData> = loaded().into_iter().filter(|data| data.num_voxels() > 0).collect();
+    c.bench_function("mesh loaded chunks", |b| {
+        b.iter(|| {
+            for data in chunks.iter() {
+                black_box(build_mesh(data, &[]));
+            }
+        })
+    });
+    let arrays: Vec<Box<VoxelArray>> = chunks.iter().map(dense).collect();
+    c.bench_function("mesh loaded chunks dense", |b| {
+        b.iter(|| {
+            for voxels in arrays.iter() {
+                black_box(build_mesh(&**voxels, &[]));
+            }
+        })
+    });
+}
+
+fn generation(c: &mut Criterion) {
+    let positions = positions();
+    let terrain = terrain();
+    c.bench_function("generate terrain", |b| {
+        b.iter(|| {
+            for pos in positions.iter() {
+                black_box(terrain.generate(*pos, 1337));
+            }
+        })
+    });
+    let hills = vanilla_hills();
+    c.bench_function("generate vanilla noise graph", |b| {
+        b.iter(|| {
+            for pos in positions.iter() {
+                black_box(hills.generate(*pos, 1337));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, memory, access, meshing, generation);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use aigame::stats::{BaseStat, BaseStats, Stat, Stats, STAT_ITER};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const ENTITIES: usize = 10_000;
+
+fn population() -> Vec<Stats> {
+    (0..ENTITIES)
+        .map(|i| {
+            let mut base = BaseStats::ones();
+            base[BaseStat::Strength] = 10.0 + (i % 7) as f32;
+            base[BaseStat::Dexterity] = 10.0 + (i % 5) as f32;
+            base[BaseStat::Vitality] = 10.0 + (i % 3) as f32;
+            base[BaseStat::Defence] = 5.0 + (i % 11) as f32;
+            Stats::new(base)
+        })
+        .collect()
+}
+
+const READ: [Stat; 6] = [
+    Stat::Speed,
+    Stat::PhysicalDamage,
+    Stat::PhysicalReduction,
+    Stat::PhysicalReductionFlat,
+    Stat::MaxHealth,
+    Stat::DodgeTime,
+];
+
+fn recalculate(c: &mut Criterion) {
+    let mut population = population();
+    c.bench_function("recalculate strength 10k", |b| {
+        b.iter(|| {
+            for stats in population.iter_mut() {
+                stats.add_base(BaseStat::Strength, 1.0);
+                stats.add_base(BaseStat::Strength, -1.0);
+            }
+        })
+    });
+
+    // The same two changes recalculating every stat, instead of only the ones depending on Strength.
+    c.bench_function("recalculate everything 10k", |b| {
+        b.iter(|| {
+            for stats in population.iter_mut() {
+                for _ in 0..2 {
+                    for stat in STAT_ITER.iter() {
+                        stats.update_stat(*stat);
+                    }
+                }
+            }
+        })
+    });
+}
+
+fn index(c: &mut Criterion) {
+    let population = population();
+    c.bench_function("index 10k", |b| {
+        b.iter(|| {
+            let mut sum = 0.0;
+            for stats in population.iter() {
+                for stat in READ.iter() {
+                    sum += stats[*stat];
+                }
+            }
+            black_box(sum)
+        })
+    });
+
+    // The same reads through the hash map layout `Stats` used to have.
+    let maps: Vec<HashMap<Stat, f32>> = population
+        .iter()
+        .map(|stats| STAT_ITER.iter().map(|stat| (*stat, stats[*stat])).collect())
+        .collect();
+    c.bench_function("index 10k hashmap", |b| {
+        b.iter(|| {
+            let mut sum = 0.0;
+            for stats in maps.iter() {
+                for stat in READ.iter() {
+                    sum += stats.get(stat).unwrap_or(&0.0);
+                }
+            }
+            black_box(sum)
+        })
+    });
+}
+
+fn update_resources(c: &mut Criterion) {
+    let mut population = population();
+    c.bench_function("update resources 10k", |b| {
+        b.iter(|| {
+            for stats in population.iter_mut() {
+                stats.update_resources(black_box(1.0 / 60.0));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, recalculate, index, update_resources);
+criterion_main!(benches);
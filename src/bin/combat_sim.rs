@@ -0,0 +1,586 @@
+// Headless duel simulator for balancing the stat and damage tables.
+//
+// Usage: combat_sim <scenario.json> [--duels N] [--seed S] [--csv FILE] [--mods DIR]
+//
+// A scenario lists combatants, each with base stats and/or a species from the mods folder,
+// which also gives the damage types and regeneration rules,
+// and the attacks they use:
+// {
+//     "duels": 1000,
+//     "seed": 1,
+//     "combatants": [
+//         {
+//             "name": "Knight",
+//             "species": "vanilla:human",
+//             "base_stats": { "Strength": 20, "Vitality": 15 },
+//             "attacks": [{ "dmg": { "Physical": 30 }, "speed": 1.0, "dodgeable": true, "cooldown": 1.5 }]
+//         }
+//     ]
+// }
+// Every pair of combatants fights `duels` times, and a report is printed per pair.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{read_dir, read_to_string, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use aigame::stats::{
+    BaseStat, BaseStats, CombatState, CombatStatus, Dmg, DmgResult, DmgTable, DmgType, DmgTypeInfo, RegenRule,
+    RegenTable, Resource, ResourceKey, SeededRng, Stats,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Attack {
+    dmg: BTreeMap<DmgType, f32>,
+    #[serde(default)]
+    speed: f32,
+    #[serde(default)]
+    dodgeable: bool,
+    cooldown: f32,
+}
+
+#[derive(Deserialize)]
+struct Combatant {
+    name: String,
+    #[serde(default)]
+    species: Option<String>,
+    #[serde(default)]
+    base_stats: HashMap<BaseStat, f32>,
+    attacks: Vec<Attack>,
+}
+
+fn default_duels() -> usize {
+    1000
+}
+
+fn default_max_time() -> f32 {
+    300.0
+}
+
+fn default_tick() -> f32 {
+    0.05
+}
+
+#[derive(Deserialize)]
+struct Scenario {
+    combatants: Vec<Combatant>,
+    #[serde(default = "default_duels")]
+    duels: usize,
+    #[serde(default)]
+    seed: u64,
+    #[serde(default = "default_max_time")]
+    max_time: f32,
+    #[serde(default = "default_tick")]
+    tick: f32,
+}
+
+struct Options {
+    scenario: PathBuf,
+    duels: Option<usize>,
+    seed: Option<u64>,
+    csv: Option<PathBuf>,
+    mods: PathBuf,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut scenario = None;
+    let mut options = Options {
+        scenario: PathBuf::new(),
+        duels: None,
+        seed: None,
+        csv: None,
+        mods: PathBuf::from("./mods/"),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--duels" => options.duels = Some(value()?.parse().map_err(|_| "Invalid --duels")?),
+            "--seed" => options.seed = Some(value()?.parse().map_err(|_| "Invalid --seed")?),
+            "--csv" => options.csv = Some(value()?.into()),
+            "--mods" => options.mods = value()?.into(),
+            _ => scenario = Some(PathBuf::from(arg)),
+        }
+    }
+    options.scenario = scenario.ok_or("Usage: combat_sim <scenario.json> [--duels N] [--seed S] [--csv FILE] [--mods DIR]")?;
+    Ok(options)
+}
+
+fn read_mods(mods: &PathBuf) -> Vec<serde_json::Value> {
+    let mut values = vec![];
+    if let Ok(paths) = read_dir(mods) {
+        for path in paths.filter_map(|p| p.ok()) {
+            if let Some(value) = read_to_string(path.path())
+                .ok()
+                .and_then(|s| serde_json::from_str(&s).ok())
+            {
+                values.push(value);
+            }
+        }
+    }
+    values
+}
+
+/// Reads every damage type in the mods folder.
+fn load_dmg_types(mods: &[serde_json::Value]) -> DmgTable {
+    let mut table = DmgTable::default();
+    for value in mods {
+        let namespace = match value["namespace"].as_str() {
+            Some(namespace) => namespace,
+            None => continue,
+        };
+        if let Some(defs) = value["defs"]["damage_types"].as_array() {
+            for def in defs {
+                if let (Some(id), Ok(info)) = (def["id"].as_str(), serde_json::from_value::<DmgTypeInfo>(def.clone())) {
+                    table.insert(DmgType::named(&format!("{}:{}", namespace, id)), info);
+                }
+            }
+        }
+    }
+    table
+}
+
+/// Reads the regeneration rules in the mods folder.
+fn load_regen(mods: &[serde_json::Value]) -> RegenTable {
+    let mut table = RegenTable::default();
+    for value in mods {
+        if let Some(defs) = value["defs"]["regenerations"].as_array() {
+            for def in defs {
+                if let Ok(resource) = serde_json::from_value::<ResourceKey>(def["resource"].clone()) {
+                    let get = |key: &str, default: f32| def[key].as_f64().map(|v| v as f32).unwrap_or(default);
+                    table.insert(
+                        resource,
+                        RegenRule {
+                            in_combat: get("in_combat", 1.0),
+                            out_of_combat: get("out_of_combat", 1.0),
+                            resting: get("resting", 1.0),
+                            sleeping: get("sleeping", 1.0),
+                            delay: get("delay", 0.0),
+                            rest_burst: get("rest_burst", 0.0),
+                        },
+                    );
+                }
+            }
+        }
+    }
+    table
+}
+
+/// Reads the base stats of every species in the mods folder, keyed by `namespace:id`. Every species needs them, a
+/// combatant with all-zero base stats would make the duels meaningless.
+fn load_species(mods: &[serde_json::Value]) -> Result<HashMap<String, HashMap<BaseStat, f32>>, String> {
+    let mut species = HashMap::new();
+    for value in mods {
+        let namespace = match value["namespace"].as_str() {
+            Some(namespace) => namespace,
+            None => continue,
+        };
+        if let Some(defs) = value["defs"]["species"].as_array() {
+            for def in defs {
+                if let Some(id) = def["id"].as_str() {
+                    let id = format!("{}:{}", namespace, id);
+                    let base_stats = match def.get("base_stats") {
+                        Some(base_stats) => serde_json::from_value(base_stats.clone())
+                            .map_err(|e| format!("Invalid base_stats of species {}: {}", id, e))?,
+                        None => return Err(format!("Species {} has no base_stats", id)),
+                    };
+                    species.insert(id, base_stats);
+                }
+            }
+        }
+    }
+    Ok(species)
+}
+
+fn create_stats(
+    combatant: &Combatant,
+    species: &HashMap<String, HashMap<BaseStat, f32>>,
+    regen: &Arc<RegenTable>,
+) -> Result<Stats, String> {
+    let mut base = BaseStats::ones();
+    if let Some(name) = &combatant.species {
+        let species = species
+            .get(name)
+            .ok_or(format!("Unknown species {} for {}", name, combatant.name))?;
+        for (stat, value) in species {
+            base[*stat] = *value;
+        }
+    }
+    for (stat, value) in &combatant.base_stats {
+        base[*stat] = *value;
+    }
+    let mut stats = Stats::new(base);
+    stats.set_regen_table(Some(regen.clone()));
+    stats.restore_resources();
+    if !stats.has_resource(Resource::HP) {
+        return Err(format!("{} has no HP", combatant.name));
+    }
+    Ok(stats)
+}
+
+#[derive(Default, Clone)]
+struct Tally {
+    attacks: u32,
+    missed: u32,
+    dodged: u32,
+    blocked: u32,
+    hits: u32,
+    crits: u32,
+    dmg: BTreeMap<DmgType, f32>,
+}
+
+impl Tally {
+    fn merge(&mut self, other: &Tally) {
+        self.attacks += other.attacks;
+        self.missed += other.missed;
+        self.dodged += other.dodged;
+        self.blocked += other.blocked;
+        self.hits += other.hits;
+        self.crits += other.crits;
+        for (ty, v) in &other.dmg {
+            *self.dmg.entry(*ty).or_default() += v;
+        }
+    }
+}
+
+struct Duel {
+    winner: Option<usize>,
+    time: f32,
+    tally: [Tally; 2],
+}
+
+fn attack(
+    attack: &Attack,
+    attacker: &Stats,
+    defender: &mut Stats,
+    types: &DmgTable,
+    tally: &mut Tally,
+    rng: &mut SeededRng,
+) {
+    let dmg = Dmg::new(attack.dmg.clone(), attack.dodgeable, attack.speed);
+    let dealt = dmg.calculate_dealt(attacker, types, rng);
+    let report = defender.apply_damage(&dealt, types, rng);
+    tally.attacks += 1;
+    match report.result {
+        DmgResult::Dodge => tally.dodged += 1,
+        DmgResult::Miss => tally.missed += 1,
+        DmgResult::Hit => {
+            tally.hits += 1;
+            if report.crit {
+                tally.crits += 1;
+            }
+            if report.blocked {
+                tally.blocked += 1;
+            }
+            for amount in &report.amounts {
+                *tally.dmg.entry(amount.ty).or_default() += amount.taken;
+            }
+        }
+    }
+}
+
+fn duel(combatants: [(&Combatant, &Stats); 2], scenario: &Scenario, types: &DmgTable, rng: &mut SeededRng) -> Duel {
+    let mut stats = [combatants[0].1.clone(), combatants[1].1.clone()];
+    let mut next: [Vec<f32>; 2] = [
+        vec![0.0; combatants[0].0.attacks.len()],
+        vec![0.0; combatants[1].0.attacks.len()],
+    ];
+    let mut tally = [Tally::default(), Tally::default()];
+    // Both sides are in combat for the whole duel, regeneration is delayed after every hit.
+    let mut since_hit = [f32::INFINITY; 2];
+    let mut time = 0.0;
+    let mut step = 0usize;
+    while time < scenario.max_time {
+        // Alternate who acts first so neither side gets an advantage on ties.
+        for i in 0..2 {
+            let side = (i + step) % 2;
+            for (a, t) in combatants[side].0.attacks.iter().zip(next[side].iter_mut()) {
+                if *t <= time {
+                    *t += a.cooldown.max(scenario.tick);
+                    let (left, right) = stats.split_at_mut(1);
+                    let (attacker, defender) = if side == 0 {
+                        (&left[0], &mut right[0])
+                    } else {
+                        (&right[0], &mut left[0])
+                    };
+                    let hp = defender[Resource::HP];
+                    attack(a, attacker, defender, types, &mut tally[side], rng);
+                    if defender[Resource::HP] < hp {
+                        since_hit[1 - side] = 0.0;
+                    }
+                    if defender[Resource::HP] <= 0.0 {
+                        return Duel {
+                            winner: Some(side),
+                            time,
+                            tally,
+                        };
+                    }
+                }
+            }
+        }
+        for (s, since_hit) in stats.iter_mut().zip(since_hit.iter_mut()) {
+            s.set_combat_status(CombatStatus {
+                state: CombatState::InCombat,
+                since_damage_taken: *since_hit,
+            });
+            let damage = s.update_resources(scenario.tick);
+            if damage > 0.0 {
+                s.apply_damage(&Dmg::create(vec![(DmgType::Pure, damage)].into_iter().collect()), types, rng);
+            }
+            *since_hit += scenario.tick;
+        }
+        time += scenario.tick;
+        step += 1;
+    }
+    Duel {
+        winner: None,
+        time,
+        tally,
+    }
+}
+
+fn percentile(sorted: &[f32], p: f32) -> f32 {
+    if sorted.is_empty() {
+        return f32::NAN;
+    }
+    let i = ((sorted.len() - 1) as f32 * p).round() as usize;
+    sorted[i]
+}
+
+// Times are shown as "-" when the side never won.
+fn fmt_time(time: f32) -> String {
+    if time.is_nan() {
+        "-".into()
+    } else {
+        format!("{:.2}", time)
+    }
+}
+
+struct SideReport {
+    name: String,
+    wins: usize,
+    ttk: Vec<f32>,
+    tally: Tally,
+}
+
+impl SideReport {
+    fn miss_rate(&self) -> f32 {
+        self.tally.missed as f32 / self.tally.attacks.max(1) as f32
+    }
+
+    fn dodge_rate(&self) -> f32 {
+        self.tally.dodged as f32 / self.tally.attacks.max(1) as f32
+    }
+
+    fn block_rate(&self) -> f32 {
+        self.tally.blocked as f32 / self.tally.hits.max(1) as f32
+    }
+
+    fn crit_rate(&self) -> f32 {
+        self.tally.crits as f32 / self.tally.hits.max(1) as f32
+    }
+
+    fn ttk_mean(&self) -> f32 {
+        if self.ttk.is_empty() {
+            f32::NAN
+        } else {
+            self.ttk.iter().sum::<f32>() / self.ttk.len() as f32
+        }
+    }
+}
+
+struct Matchup {
+    sides: [SideReport; 2],
+    timeouts: usize,
+    duels: usize,
+}
+
+fn simulate(
+    a: (&Combatant, &Stats),
+    b: (&Combatant, &Stats),
+    scenario: &Scenario,
+    types: &DmgTable,
+    rng: &mut SeededRng,
+) -> Matchup {
+    let mut sides = [
+        SideReport {
+            name: a.0.name.clone(),
+            wins: 0,
+            ttk: vec![],
+            tally: Default::default(),
+        },
+        SideReport {
+            name: b.0.name.clone(),
+            wins: 0,
+            ttk: vec![],
+            tally: Default::default(),
+        },
+    ];
+    let mut timeouts = 0;
+    for _ in 0..scenario.duels {
+        // Every duel gets its own stream so that changing one duel doesn't shift the rest.
+        let mut duel_rng = rng.fork();
+        let result = duel([a, b], scenario, types, &mut duel_rng);
+        match result.winner {
+            Some(w) => {
+                sides[w].wins += 1;
+                sides[w].ttk.push(result.time);
+            }
+            None => timeouts += 1,
+        }
+        for (side, tally) in sides.iter_mut().zip(result.tally.iter()) {
+            side.tally.merge(tally);
+        }
+    }
+    for side in sides.iter_mut() {
+        side.ttk.sort_by(|a, b| a.total_cmp(b));
+    }
+    Matchup {
+        sides,
+        timeouts,
+        duels: scenario.duels,
+    }
+}
+
+fn print_table(matchups: &[Matchup]) {
+    for m in matchups {
+        println!(
+            "\n{} vs {} ({} duels, {} timeouts)",
+            m.sides[0].name, m.sides[1].name, m.duels, m.timeouts
+        );
+        println!(
+            "{:<16} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>7} {:>7} {:>7} {:>7}",
+            "side", "wins", "ttk avg", "ttk p10", "ttk p50", "ttk p90", "ttk max", "missed", "dodged", "blocked", "crit"
+        );
+        for s in m.sides.iter() {
+            println!(
+                "{:<16} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>6.1}% {:>6.1}% {:>6.1}% {:>6.1}%",
+                s.name,
+                s.wins,
+                fmt_time(s.ttk_mean()),
+                fmt_time(percentile(&s.ttk, 0.1)),
+                fmt_time(percentile(&s.ttk, 0.5)),
+                fmt_time(percentile(&s.ttk, 0.9)),
+                fmt_time(percentile(&s.ttk, 1.0)),
+                s.miss_rate() * 100.0,
+                s.dodge_rate() * 100.0,
+                s.block_rate() * 100.0,
+                s.crit_rate() * 100.0,
+            );
+        }
+        println!("damage dealt per duel:");
+        for s in m.sides.iter() {
+            let dmg: Vec<String> = s
+                .tally
+                .dmg
+                .iter()
+                .map(|(ty, v)| format!("{:?} {:.1}", ty, v / m.duels as f32))
+                .collect();
+            println!("  {:<14} {}", s.name, dmg.join(", "));
+        }
+    }
+}
+
+fn write_csv(path: &PathBuf, matchups: &[Matchup]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    write!(
+        file,
+        "side,opponent,duels,wins,timeouts,ttk_mean,ttk_p10,ttk_p50,ttk_p90,ttk_max,miss_rate,dodge_rate,block_rate,crit_rate"
+    )?;
+    let types: BTreeSet<DmgType> = matchups
+        .iter()
+        .flat_map(|m| m.sides.iter())
+        .flat_map(|s| s.tally.dmg.keys().copied())
+        .collect();
+    for ty in &types {
+        write!(file, ",dmg_{}", ty)?;
+    }
+    writeln!(file)?;
+    for m in matchups {
+        for (i, s) in m.sides.iter().enumerate() {
+            write!(
+                file,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                s.name,
+                m.sides[1 - i].name,
+                m.duels,
+                s.wins,
+                m.timeouts,
+                s.ttk_mean(),
+                percentile(&s.ttk, 0.1),
+                percentile(&s.ttk, 0.5),
+                percentile(&s.ttk, 0.9),
+                percentile(&s.ttk, 1.0),
+                s.miss_rate(),
+                s.dodge_rate(),
+                s.block_rate(),
+                s.crit_rate(),
+            )?;
+            for ty in &types {
+                write!(
+                    file,
+                    ",{}",
+                    s.tally.dmg.get(ty).unwrap_or(&0.0) / m.duels as f32
+                )?;
+            }
+            writeln!(file)?;
+        }
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), String> {
+    let options = parse_args()?;
+    let mut scenario: Scenario = serde_json::from_str(
+        &read_to_string(&options.scenario)
+            .map_err(|e| format!("Unable to read {}: {}", options.scenario.display(), e))?,
+    )
+    .map_err(|e| format!("Invalid scenario: {}", e))?;
+    if let Some(duels) = options.duels {
+        scenario.duels = duels;
+    }
+    if let Some(seed) = options.seed {
+        scenario.seed = seed;
+    }
+    if scenario.combatants.len() < 2 {
+        return Err("A scenario needs at least two combatants".into());
+    }
+
+    let mods = read_mods(&options.mods);
+    let species = load_species(&mods)?;
+    let types = load_dmg_types(&mods);
+    let regen = Arc::new(load_regen(&mods));
+    let stats = scenario
+        .combatants
+        .iter()
+        .map(|c| create_stats(c, &species, &regen))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut rng = SeededRng::new(scenario.seed);
+    let mut matchups = vec![];
+    for i in 0..scenario.combatants.len() {
+        for j in i + 1..scenario.combatants.len() {
+            matchups.push(simulate(
+                (&scenario.combatants[i], &stats[i]),
+                (&scenario.combatants[j], &stats[j]),
+                &scenario,
+                &types,
+                &mut rng,
+            ));
+        }
+    }
+
+    print_table(&matchups);
+    if let Some(csv) = &options.csv {
+        write_csv(csv, &matchups).map_err(|e| format!("Unable to write csv: {}", e))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -4,7 +4,10 @@ use std::convert::TryInto;
 
 use crate::cmap;
 
-use super::voxel::{Voxel, VoxelArray, CHUNK_SIZE};
+use super::{
+    palette::{bits_for, PackedArray},
+    voxel::{Voxel, VoxelArray, CHUNK_SIZE},
+};
 
 #[derive(Debug)]
 pub struct ChunkPosition(pub IVec3);
@@ -41,26 +44,190 @@ impl ChunkPosition {
     }
 }
 
-#[derive(Clone, Copy)]
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+// Voxels are numbered `[y][z][x]`, as in `VoxelArray`.
+fn index(x: usize, y: usize, z: usize) -> Option<usize> {
+    if x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE {
+        Some((y * CHUNK_SIZE + z) * CHUNK_SIZE + x)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone)]
+enum Voxels {
+    // Every voxel is the same, like chunks of only air or only stone.
+    Uniform(Voxel),
+    Palette {
+        palette: Vec<Voxel>,
+        // How many voxels use each palette entry, entries that aren't used anymore are reused.
+        counts: Vec<u32>,
+        indices: PackedArray,
+    },
+}
+
+/// The voxels of a chunk, stored as a palette with bit packed indices so chunks with few kinds of voxels stay small.
+#[derive(Clone)]
 pub struct ChunkData {
-    pub voxels: VoxelArray,
-    pub num_voxels: usize,
+    voxels: Voxels,
+    num_voxels: usize,
 }
 
 impl ChunkData {
     pub fn get<T: TryInto<usize>>(&self, x: T, y: T, z: T) -> Option<&Voxel> {
-        self.voxels
-            .get(y.try_into().ok()?)?
-            .get(z.try_into().ok()?)?
-            .get(x.try_into().ok()?)
+        let i = index(x.try_into().ok()?, y.try_into().ok()?, z.try_into().ok()?)?;
+        Some(self.get_index(i))
+    }
+
+    fn get_index(&self, i: usize) -> &Voxel {
+        match &self.voxels {
+            Voxels::Uniform(voxel) => voxel,
+            Voxels::Palette { palette, indices, .. } => &palette[indices.get(i)],
+        }
     }
+
+    /// Sets a voxel and returns the one it replaced, `None` if the position is outside the chunk.
+    pub fn set<T: TryInto<usize>>(&mut self, x: T, y: T, z: T, voxel: Voxel) -> Option<Voxel> {
+        let i = index(x.try_into().ok()?, y.try_into().ok()?, z.try_into().ok()?)?;
+        let old = *self.get_index(i);
+        if old == voxel {
+            return Some(old);
+        }
+        if let Voxels::Uniform(uniform) = self.voxels {
+            self.voxels = Voxels::Palette {
+                palette: vec![uniform],
+                counts: vec![CHUNK_VOLUME as u32],
+                indices: PackedArray::new(1, CHUNK_VOLUME),
+            };
+        }
+        let uniform = match &mut self.voxels {
+            Voxels::Palette {
+                palette,
+                counts,
+                indices,
+            } => {
+                let new = match palette.iter().position(|p| *p == voxel) {
+                    Some(new) => new,
+                    None => match counts.iter().position(|count| *count == 0) {
+                        Some(unused) => {
+                            palette[unused] = voxel;
+                            unused
+                        }
+                        None => {
+                            palette.push(voxel);
+                            counts.push(0);
+                            let bits = bits_for(palette.len());
+                            if bits != indices.bits() {
+                                *indices = indices.with_bits(bits, CHUNK_VOLUME);
+                            }
+                            palette.len() - 1
+                        }
+                    },
+                };
+                counts[indices.get(i)] -= 1;
+                counts[new] += 1;
+                indices.set(i, new);
+                counts[new] as usize == CHUNK_VOLUME
+            }
+            Voxels::Uniform(_) => unreachable!(),
+        };
+        if uniform {
+            self.voxels = Voxels::Uniform(voxel);
+        }
+        if old.is_empty() {
+            self.num_voxels += 1;
+        } else if voxel.is_empty() {
+            self.num_voxels -= 1;
+        }
+        Some(old)
+    }
+
     pub fn all(voxel: &Voxel) -> Self {
         Self {
-            voxels: [[[*voxel; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
-            num_voxels: if voxel.is_empty() {  0 } else { CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE },
+            voxels: Voxels::Uniform(*voxel),
+            num_voxels: if voxel.is_empty() { 0 } else { CHUNK_VOLUME },
         }
     }
+
+    pub fn from_array(voxels: &VoxelArray) -> Self {
+        let mut palette: Vec<Voxel> = vec![];
+        let mut counts: Vec<u32> = vec![];
+        let mut unpacked = Vec::with_capacity(CHUNK_VOLUME);
+        for voxel in voxels.iter().flatten().flatten() {
+            let i = match palette.iter().position(|p| p == voxel) {
+                Some(i) => i,
+                None => {
+                    palette.push(*voxel);
+                    counts.push(0);
+                    palette.len() - 1
+                }
+            };
+            counts[i] += 1;
+            unpacked.push(i);
+        }
+        let num_voxels = palette
+            .iter()
+            .zip(&counts)
+            .filter(|(voxel, _)| !voxel.is_empty())
+            .map(|(_, count)| *count as usize)
+            .sum();
+        if palette.len() == 1 {
+            return Self {
+                voxels: Voxels::Uniform(palette[0]),
+                num_voxels,
+            };
+        }
+        let mut indices = PackedArray::new(bits_for(palette.len()), CHUNK_VOLUME);
+        for (i, index) in unpacked.into_iter().enumerate() {
+            indices.set(i, index);
+        }
+        Self {
+            voxels: Voxels::Palette {
+                palette,
+                counts,
+                indices,
+            },
+            num_voxels,
+        }
+    }
+
+    /// Voxels that aren't empty.
+    pub fn num_voxels(&self) -> usize {
+        self.num_voxels
+    }
+
+    /// The voxel every voxel is, if they are all the same.
+    pub fn uniform(&self) -> Option<Voxel> {
+        match self.voxels {
+            Voxels::Uniform(voxel) => Some(voxel),
+            Voxels::Palette { .. } => None,
+        }
+    }
+
+    /// Every voxel in `[y][z][x]` order.
+    pub fn iter(&self) -> impl Iterator<Item = Voxel> + '_ {
+        (0..CHUNK_VOLUME).map(move |i| *self.get_index(i))
+    }
+
+    /// Bytes used by the chunk, including its palette and indices.
+    pub fn memory(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + match &self.voxels {
+                Voxels::Uniform(_) => 0,
+                Voxels::Palette {
+                    palette,
+                    counts,
+                    indices,
+                } => {
+                    palette.capacity() * std::mem::size_of::<Voxel>()
+                        + counts.capacity() * std::mem::size_of::<u32>()
+                        + indices.heap_size()
+                }
+            }
+    }
 }
+
 pub struct Chunks {
     pub loaded: HashMap<IVec3, Entity>,
 }
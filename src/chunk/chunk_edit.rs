@@ -6,6 +6,7 @@ use bevy::{
 use super::{
     chunk::{ChunkData, Chunks},
     meshing::{ChunkMesh, MeshData},
+    storage::ChunkModified,
     voxel::{Voxel, CHUNK_SIZE},
 };
 
@@ -152,23 +153,18 @@ fn chunk_edit_system<E: ChunkEdit>(
                                     .remove::<ChunkMesh>()
                                     .remove_bundle::<MeshBundle>()
                                     .remove::<Task<ChunkData>>()
-                                    .remove::<Task<MeshData>>();
+                                    .remove::<Task<MeshData>>()
+                                    .insert(ChunkModified);
                             }
                             EditResult::Some(edits) => {
                                 for edit in edits {
-                                    let old = data.voxels[edit.1 as usize][edit.2 as usize]
-                                        [edit.0 as usize];
-
-                                    if !old.is_empty() {
-                                        data.num_voxels -= 1;
-                                    }
-                                    data.voxels[edit.1 as usize][edit.2 as usize]
-                                        [edit.0 as usize] = *e.get_voxel();
+                                    data.set(edit.0, edit.1, edit.2, *e.get_voxel());
                                 }
                                 commands
                                     .entity(entity)
                                     .remove::<ChunkMesh>()
-                                    .remove::<Task<MeshData>>();
+                                    .remove::<Task<MeshData>>()
+                                    .insert(ChunkModified);
                             }
                         }
                     }
@@ -176,50 +172,37 @@ fn chunk_edit_system<E: ChunkEdit>(
                     if let Ok(mut data) = data_query.get_mut(entity) {
                         match res {
                             EditResult::All => {
-                                data.num_voxels = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
-                                data.voxels =
-                                    [[[*e.get_voxel(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+                                *data = ChunkData::all(e.get_voxel());
                             }
                             EditResult::Some(edits) => {
                                 for edit in edits {
-                                    let old = data.voxels[edit.1 as usize][edit.2 as usize]
-                                        [edit.0 as usize];
-
-                                    if old.is_empty() {
-                                        data.num_voxels += 1;
-                                    }
-                                    data.voxels[edit.1 as usize][edit.2 as usize]
-                                        [edit.0 as usize] = *e.get_voxel();
+                                    data.set(edit.0, edit.1, edit.2, *e.get_voxel());
                                 }
                             }
                         }
                         commands
                             .entity(entity)
                             .remove::<ChunkMesh>()
-                            .remove::<Task<MeshData>>();
+                            .remove::<Task<MeshData>>()
+                            .insert(ChunkModified);
                     } else {
                         match res {
                             EditResult::All => {
                                 commands
                                     .entity(entity)
-                                    .insert(ChunkData {
-                                        voxels: [[[*e.get_voxel(); CHUNK_SIZE]; CHUNK_SIZE];
-                                            CHUNK_SIZE],
-                                        num_voxels: CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE,
-                                    })
-                                    .remove::<Task<MeshData>>();
+                                    .insert(ChunkData::all(e.get_voxel()))
+                                    .remove::<Task<MeshData>>()
+                                    .insert(ChunkModified);
                             }
                             EditResult::Some(edits) => {
-                                let mut data =
-                                    [[[Voxel::default(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+                                let mut data = ChunkData::all(&Voxel::default());
                                 for edit in edits {
-                                    data[edit.1 as usize][edit.2 as usize][edit.0 as usize] =
-                                        *e.get_voxel();
+                                    data.set(edit.0, edit.1, edit.2, *e.get_voxel());
                                 }
-                                commands.entity(entity).insert(ChunkData {
-                                    voxels: data,
-                                    num_voxels: edits.len(),
-                                });
+                                commands
+                                    .entity(entity)
+                                    .insert(data)
+                                    .insert(ChunkModified);
                             }
                         }
                     }
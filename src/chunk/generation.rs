@@ -0,0 +1,446 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use simdnoise::NoiseBuilder;
+
+use crate::defs::{Blocks, Message};
+
+use super::{
+    chunk::ChunkData,
+    voxel::{Voxel, VoxelArray, CHUNK_SIZE},
+};
+
+/// Seed of the world, everything generated follows from it.
+pub struct Seed(pub u64);
+
+impl Default for Seed {
+    fn default() -> Self {
+        Self(1337)
+    }
+}
+
+/// Steps of generating a chunk, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GenerationStage {
+    // Which voxels are solid, and the height of the terrain.
+    Density,
+    // Layers below the surface, like grass on dirt.
+    Surface,
+    Caves,
+    Ores,
+    // Things placed on top, like trees. They have to fit in their chunk.
+    Features,
+}
+
+// A step of splitmix64. Generation has its own instead of sharing the rng of combat, a change there must never change
+// the terrain of saved worlds.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Mixes values into a seed, the same values always give the same seed.
+fn mix(seed: u64, values: &[i64]) -> u64 {
+    values.iter().fold(seed, |seed, value| splitmix64(&mut (seed ^ *value as u64)))
+}
+
+/// Deterministic rng of a generation stage, the same seed always gives the same rolls on every platform.
+#[derive(Debug, Clone)]
+pub struct GenerationRng {
+    state: u64,
+}
+
+impl GenerationRng {
+    pub fn next_u64(&mut self) -> u64 {
+        splitmix64(&mut self.state)
+    }
+
+    /// Uniform value in [0, 1).
+    pub fn roll(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Seed for the noise of a stage in the world with `seed`, see `ChunkContext::noise_seed`.
+pub fn noise_seed(seed: u64, stage: GenerationStage) -> i32 {
+    mix(seed, &[stage as i64]) as i32
+}
+
+/// A chunk being generated, handed to every stage in order.
+pub struct ChunkContext {
+    pub pos: IVec3,
+    pub seed: u64,
+    pub voxels: Box<VoxelArray>,
+    // Height of the terrain in world space for every column, `[z][x]`. Set by the density stage of generators that
+    // have a heightmap.
+    pub heights: Vec<f32>,
+    // Biome of every column, `[z][x]`, as an index into the biomes of the generator. Set by the density stage of
+    // generators that have biomes.
+    pub biomes: Vec<usize>,
+}
+
+impl ChunkContext {
+    pub fn new(pos: IVec3, seed: u64) -> Self {
+        Self {
+            pos,
+            seed,
+            voxels: Box::new(VoxelArray::default()),
+            heights: vec![],
+            biomes: vec![],
+        }
+    }
+
+    /// World position of the corner of the chunk.
+    pub fn origin(&self) -> Vec3 {
+        self.pos.as_f32() * CHUNK_SIZE as f32
+    }
+
+    pub fn world_pos(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        self.origin() + Vec3::new(x as f32, y as f32, z as f32)
+    }
+
+    /// Rng of a stage for this chunk, the same for the same seed, position and stage.
+    pub fn rng(&self, stage: GenerationStage) -> GenerationRng {
+        GenerationRng {
+            state: mix(
+                self.seed,
+                &[stage as i64, self.pos.x as i64, self.pos.y as i64, self.pos.z as i64],
+            ),
+        }
+    }
+
+    /// Seed for the noise of a stage. It is the same for every chunk, so noise lines up across chunks.
+    pub fn noise_seed(&self, stage: GenerationStage) -> i32 {
+        noise_seed(self.seed, stage)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Voxel {
+        self.voxels[y][z][x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+        self.voxels[y][z][x] = voxel;
+    }
+    /// Places the veins of ore, replacing only the voxels `host` accepts.
+    pub fn place_ores(&mut self, ores: &[OreVein], host: impl Fn(Voxel) -> bool) {
+        let mut rng = self.rng(GenerationStage::Ores);
+        let size = CHUNK_SIZE as f32;
+        for vein in ores {
+            for _ in 0..vein.per_chunk {
+                // Rolled even when the vein is too high, so every vein keeps its place.
+                let center = Vec3::new(rng.roll() * size, rng.roll() * size, rng.roll() * size);
+                if self.origin().y + center.y > vein.max_height {
+                    continue;
+                }
+                let min = (center - Vec3::ONE * vein.radius).max(Vec3::ZERO).as_i32();
+                let max = (center + Vec3::ONE * vein.radius).min(Vec3::ONE * (size - 1.)).as_i32();
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        for x in min.x..=max.x {
+                            let (x, y, z) = (x as usize, y as usize, z as usize);
+                            let p = Vec3::new(x as f32, y as f32, z as f32) + Vec3::ONE * 0.5;
+                            if p.distance_squared(center) <= vein.radius * vein.radius && host(self.get(x, y, z)) {
+                                self.set(x, y, z, vein.voxel);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Generates the chunks of a world. Stages run in the order of `GenerationStage`, and the same seed and position
+/// always give the same chunk.
+pub trait WorldGenerator: Send + Sync + 'static {
+    /// Saved with the world, so it is generated with the same generator when loaded again.
+    fn name(&self) -> &str;
+
+    fn density(&self, chunk: &mut ChunkContext);
+
+    fn surface(&self, _chunk: &mut ChunkContext) {}
+
+    fn caves(&self, _chunk: &mut ChunkContext) {}
+
+    fn ores(&self, _chunk: &mut ChunkContext) {}
+
+    fn features(&self, _chunk: &mut ChunkContext) {}
+
+    /// Air temperature at a world position, `None` where the generator doesn't say.
+    fn temperature(&self, _pos: Vec3, _seed: u64) -> Option<f32> {
+        None
+    }
+
+    fn generate(&self, pos: IVec3, seed: u64) -> ChunkData {
+        let mut chunk = ChunkContext::new(pos, seed);
+        self.density(&mut chunk);
+        self.surface(&mut chunk);
+        self.caves(&mut chunk);
+        self.ores(&mut chunk);
+        self.features(&mut chunk);
+        ChunkData::from_array(&chunk.voxels)
+    }
+}
+
+/// Blobs of a voxel placed in the stone of every chunk.
+#[derive(Debug, Clone)]
+pub struct OreVein {
+    pub voxel: Voxel,
+    pub per_chunk: u32,
+    pub radius: f32,
+    // Only placed below this world height.
+    pub max_height: f32,
+}
+
+/// Blocks the built-in generators are made of, found by name in the block definitions.
+#[derive(Debug, Clone, Default)]
+pub struct TerrainBlocks {
+    // From the surface down, each with how many voxels deep it goes.
+    pub layers: Vec<(Voxel, u32)>,
+    pub stone: Voxel,
+    pub ores: Vec<OreVein>,
+}
+
+impl TerrainBlocks {
+    pub fn from_defs(blocks: &Blocks) -> Result<Self, String> {
+        Self::find(|name| blocks.get(name).map(|block| Voxel::from_block(&blocks[block], 0)))
+    }
+
+    /// Finds the blocks with `find`, which is given the `namespace:id` of every block.
+    pub fn find(find: impl Fn(&str) -> Option<Voxel>) -> Result<Self, String> {
+        let voxel = |name: &str| {
+            find(name).ok_or_else(|| format!("The built-in generators need block {}, which isn't defined.", name))
+        };
+        Ok(Self {
+            layers: vec![(voxel("vanilla:grass")?, 1), (voxel("vanilla:dirt")?, 3)],
+            stone: voxel("vanilla:stone")?,
+            ores: vec![
+                OreVein {
+                    voxel: voxel("vanilla:iron_ore")?,
+                    per_chunk: 4,
+                    radius: 1.5,
+                    max_height: 0.,
+                },
+                OreVein {
+                    voxel: voxel("vanilla:gold_ore")?,
+                    per_chunk: 1,
+                    radius: 1.,
+                    max_height: -100.,
+                },
+            ],
+        })
+    }
+}
+
+/// Hills from a heightmap, with layers below the surface, caves carved out by ridged noise and veins of ore.
+#[derive(Debug, Clone)]
+pub struct TerrainGenerator {
+    pub height_scale: f32,
+    pub frequency: f32,
+    pub octaves: u8,
+    // Ridged noise below this is carved out.
+    pub cave_threshold: f32,
+    pub blocks: TerrainBlocks,
+}
+
+// Without blocks everything is air, they are set once the definitions are loaded.
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        Self {
+            height_scale: 300.,
+            frequency: 0.005,
+            octaves: 8,
+            cave_threshold: 0.,
+            blocks: TerrainBlocks::default(),
+        }
+    }
+}
+
+impl WorldGenerator for TerrainGenerator {
+    fn name(&self) -> &str {
+        "terrain"
+    }
+
+    fn density(&self, chunk: &mut ChunkContext) {
+        let origin = chunk.origin();
+        chunk.heights = NoiseBuilder::fbm_2d_offset(origin.x, CHUNK_SIZE, origin.z, CHUNK_SIZE)
+            .with_seed(chunk.noise_seed(GenerationStage::Density))
+            .with_freq(self.frequency)
+            .with_octaves(self.octaves)
+            .generate()
+            .0
+            .into_iter()
+            .map(|height| height * self.height_scale)
+            .collect();
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    if chunk.world_pos(x, y, z).y < chunk.heights[z * CHUNK_SIZE + x] {
+                        chunk.set(x, y, z, self.blocks.stone);
+                    }
+                }
+            }
+        }
+    }
+
+    fn surface(&self, chunk: &mut ChunkContext) {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    if chunk.get(x, y, z).is_empty() {
+                        continue;
+                    }
+                    let depth = chunk.heights[z * CHUNK_SIZE + x] - chunk.world_pos(x, y, z).y;
+                    let mut bottom = 0.;
+                    for (voxel, thickness) in &self.blocks.layers {
+                        bottom += *thickness as f32;
+                        if depth <= bottom {
+                            chunk.set(x, y, z, *voxel);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn caves(&self, chunk: &mut ChunkContext) {
+        let origin = chunk.origin();
+        let noise = NoiseBuilder::ridge_3d_offset(origin.x, CHUNK_SIZE, origin.y, CHUNK_SIZE, origin.z, CHUNK_SIZE)
+            .with_seed(chunk.noise_seed(GenerationStage::Caves))
+            .generate()
+            .0;
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    if noise[(z * CHUNK_SIZE + y) * CHUNK_SIZE + x] <= self.cave_threshold {
+                        chunk.set(x, y, z, Voxel::default());
+                    }
+                }
+            }
+        }
+    }
+
+    fn ores(&self, chunk: &mut ChunkContext) {
+        chunk.place_ores(&self.blocks.ores, |voxel| voxel == self.blocks.stone);
+    }
+}
+
+/// Flat ground at a height, with the same layers as the terrain generator.
+#[derive(Debug, Clone, Default)]
+pub struct FlatGenerator {
+    pub height: f32,
+    pub blocks: TerrainBlocks,
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn name(&self) -> &str {
+        "flat"
+    }
+
+    fn density(&self, chunk: &mut ChunkContext) {
+        chunk.heights = vec![self.height; CHUNK_SIZE * CHUNK_SIZE];
+        for y in 0..CHUNK_SIZE {
+            let world_y = chunk.world_pos(0, y, 0).y;
+            if world_y >= self.height {
+                break;
+            }
+            let depth = self.height - world_y;
+            let mut bottom = 0.;
+            let voxel = self
+                .blocks
+                .layers
+                .iter()
+                .find(|(_, thickness)| {
+                    bottom += *thickness as f32;
+                    depth <= bottom
+                })
+                .map_or(self.blocks.stone, |(voxel, _)| *voxel);
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    chunk.set(x, y, z, voxel);
+                }
+            }
+        }
+    }
+}
+
+/// The generator new chunks are made with.
+pub struct WorldGen(pub Arc<dyn WorldGenerator>);
+
+impl Default for WorldGen {
+    fn default() -> Self {
+        Self(Arc::new(TerrainGenerator::default()))
+    }
+}
+
+/// Generators a world can be made with, found by name when a saved world is loaded.
+pub struct WorldGenerators {
+    generators: Vec<Arc<dyn WorldGenerator>>,
+}
+
+impl Default for WorldGenerators {
+    fn default() -> Self {
+        Self {
+            generators: vec![
+                Arc::new(TerrainGenerator::default()),
+                Arc::new(FlatGenerator::default()),
+            ],
+        }
+    }
+}
+
+impl WorldGenerators {
+    /// Adds a generator, replacing the one with the same name.
+    pub fn add(&mut self, generator: Arc<dyn WorldGenerator>) {
+        self.generators.retain(|g| g.name() != generator.name());
+        self.generators.push(generator);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn WorldGenerator>> {
+        self.generators.iter().find(|g| g.name() == name).cloned()
+    }
+}
+
+// The built-in generators are made of blocks from the definitions, so they are only built once those are loaded.
+fn build_generators(
+    blocks: Res<Blocks>,
+    mut terrain_blocks: ResMut<TerrainBlocks>,
+    mut generators: ResMut<WorldGenerators>,
+    mut generator: ResMut<WorldGen>,
+    mut printer: EventWriter<Message>,
+) {
+    *terrain_blocks = match TerrainBlocks::from_defs(&blocks) {
+        Ok(terrain_blocks) => terrain_blocks,
+        Err(e) => {
+            printer.send(Message::error(e));
+            return;
+        }
+    };
+    let terrain = Arc::new(TerrainGenerator {
+        blocks: terrain_blocks.clone(),
+        ..Default::default()
+    });
+    if generator.0.name() == terrain.name() {
+        generator.0 = terrain.clone();
+    }
+    generators.add(terrain);
+    generators.add(Arc::new(FlatGenerator {
+        blocks: terrain_blocks.clone(),
+        ..Default::default()
+    }));
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.init_resource::<Seed>()
+        .init_resource::<WorldGen>()
+        .init_resource::<WorldGenerators>()
+        .init_resource::<TerrainBlocks>()
+        .add_startup_system_to_stage(
+            "generate",
+            build_generators.system().label("build_generators").after("generate_blocks"),
+        );
+}
@@ -1,17 +1,18 @@
-use bevy::utils::HashMap;
+use bevy::utils::{HashMap, HashSet};
 use bevy::{math::DVec3, prelude::*};
 use bevy::{
     math::{Vec3Swizzles, Vec4Swizzles},
-    tasks::{AsyncComputeTaskPool, Task},
+    tasks::{AsyncComputeTaskPool, IoTaskPool, Task},
 };
 use futures_lite::future::{block_on, poll_once};
 use rand::{thread_rng, Rng};
-use simdnoise::NoiseBuilder;
 
 use super::{
     chunk::{ChunkData, ChunkPosition, Chunks},
+    generation::{Seed, WorldGen},
     ordered_float::OrderedFloat,
-    voxel::{VoxelArray, CHUNK_SIZE},
+    storage::{region_of, saved_data, ChunkModified, ChunkStore},
+    voxel::CHUNK_SIZE,
 };
 
 #[derive(Default)]
@@ -43,7 +44,7 @@ fn chunk_generator_handler(
 ) {
     for (entity, mut task, info) in tasks.iter_mut() {
         if let Some(data) = block_on(poll_once(&mut *task)) {
-            if data.num_voxels > 0 {
+            if data.num_voxels() > 0 {
                 commands.entity(entity).insert(data);
             }
             commands
@@ -57,45 +58,15 @@ fn chunk_generator_handler(
     }
 }
 
-fn generate_chunk(pos: IVec3, seed: i32) -> ChunkData {
-    let p = pos.as_f32() * CHUNK_SIZE as f32;
-    let noise1 = NoiseBuilder::fbm_2d_offset(p.x, CHUNK_SIZE, p.z, CHUNK_SIZE)
-        .with_seed(seed)
-        .with_freq(0.005)
-        .with_octaves(8)
-        .generate()
-        .0;
-    let noise2 = NoiseBuilder::ridge_3d_offset(p.x, CHUNK_SIZE, p.y, CHUNK_SIZE, p.z, CHUNK_SIZE)
-        .with_seed(seed)
-        .generate()
-        .0;
-
-    let mut voxels = VoxelArray::default();
-    let mut num_voxels = 0;
-    for y in 0..CHUNK_SIZE {
-        for z in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let p = pos.as_f32() * CHUNK_SIZE as f32 + Vec3::new(x as f32, y as f32, z as f32)
-                    - Vec3::ONE * CHUNK_SIZE as f32 / 2.;
-
-                if noise1[z * CHUNK_SIZE + x] * 300. > p.y
-                    && noise2[y * CHUNK_SIZE * CHUNK_SIZE + z * CHUNK_SIZE + x] > 0.
-                {
-                    num_voxels += 1;
-                    voxels[y][z][x].id = ((p.x * p.z * p.y % 9.).abs() + 1.) as u8;
-                }
-            }
-        }
-    }
-    ChunkData { voxels, num_voxels }
-}
-
 fn chunk_loader(
     mut commands: Commands,
     mut chunks: ResMut<Chunks>,
     load_settings: Res<LoadSettings>,
     mut gen: Query<(Entity, &mut ChunkGenerator)>,
     thread_pool: Res<AsyncComputeTaskPool>,
+    store: Res<ChunkStore>,
+    seed: Res<Seed>,
+    generator: Res<WorldGen>,
 ) {
     for (entity, mut gen) in gen.iter_mut() {
         if gen.gen_list_index == 0 && !gen.deleting {
@@ -125,7 +96,18 @@ fn chunk_loader(
             if !chunks.loaded.contains_key(&c) {
                 count += 1;
 
-                let gen_task = thread_pool.spawn(async move { generate_chunk(c, 6969) });
+                let store = store.clone();
+                let (seed, generator) = (seed.0, generator.0.clone());
+                let gen_task = thread_pool.spawn(async move {
+                    match store.load(c) {
+                        Ok(Some(data)) => data,
+                        Ok(None) => generator.generate(c, seed),
+                        Err(e) => {
+                            store.report(format!("Couldn't load chunk {}: {}", c, e));
+                            generator.generate(c, seed)
+                        }
+                    }
+                });
                 gen.gen_job_count += 1;
                 let e = commands
                     .spawn()
@@ -155,10 +137,7 @@ struct DeleteChunks {
     chunks: Vec<(IVec3, Entity)>,
 }
 
-struct Seed(u64);
-
 fn chunk_setup(mut commands: Commands) {
-    commands.insert_resource(Seed(1337));
     commands.insert_resource(Chunks {
         loaded: HashMap::default(),
     })
@@ -169,13 +148,24 @@ fn chunk_deleter(
     mut chunks: ResMut<Chunks>,
     mut query: Query<(Entity, &mut Task<DeleteChunks>, &TaskInfo)>,
     mut generators: Query<&mut ChunkGenerator>,
+    modified: Query<Option<&ChunkData>, With<ChunkModified>>,
+    store: Res<ChunkStore>,
+    pool: Res<IoTaskPool>,
 ) {
     for (entity, mut task, info) in query.iter_mut() {
         if let Some(delete) = block_on(poll_once(&mut *task)) {
+            let mut regions = HashSet::default();
             for (pos, e) in delete.chunks {
                 chunks.loaded.remove(&pos);
+                if let Ok(data) = modified.get(e) {
+                    store.queue(pos, &saved_data(data));
+                    regions.insert(region_of(pos));
+                }
                 commands.entity(e).despawn();
             }
+            for region in regions {
+                store.save_region_in_background(region, &pool);
+            }
             commands.entity(entity).despawn();
             if let Ok(mut gen) = generators.get_mut(info.sender) {
                 gen.deleting = false;
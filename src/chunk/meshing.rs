@@ -3,20 +3,42 @@ use super::{
     loader::{LoadSettings, TaskInfo},
     ordered_float::OrderedFloat,
     shader::Pipeline,
-    voxel::{Face, Voxel, CHUNK_SIZE},
+    voxel::{Face, Voxel, VoxelArray, CHUNK_SIZE},
     ChunkGenerator,
 };
+use crate::defs::{Blocks, Sprites};
 use bevy::render::mesh::{Indices, VertexAttributeValues};
 use bevy::render::pipeline::RenderPipeline;
 use bevy::tasks::AsyncComputeTaskPool;
 use futures_lite::future::{block_on, poll_once};
+use std::sync::Arc;
 
 use bevy::{prelude::*, render::pipeline::PrimitiveTopology, tasks::Task};
 
+/// Voxels that can be meshed, `None` outside of the chunk.
+pub trait VoxelGrid {
+    fn voxel(&self, x: isize, y: isize, z: isize) -> Option<&Voxel>;
+}
+
+impl VoxelGrid for ChunkData {
+    fn voxel(&self, x: isize, y: isize, z: isize) -> Option<&Voxel> {
+        self.get(x, y, z)
+    }
+}
+
+// Chunks were stored like this before they had palettes, the benches mesh it to compare.
+impl VoxelGrid for VoxelArray {
+    fn voxel(&self, x: isize, y: isize, z: isize) -> Option<&Voxel> {
+        use std::convert::TryInto;
+        let (x, y, z): (usize, usize, usize) = (x.try_into().ok()?, y.try_into().ok()?, z.try_into().ok()?);
+        self.get(y)?.get(z)?.get(x)
+    }
+}
+
 // Translated to rust from https://github.com/roboleary/GreedyMesh
-fn greedy_meshing<F: FnMut(([f32; 3], [f32; 3], [f32; 3], [f32; 3]), Voxel, Face, bool)>(
+fn greedy_meshing<F: FnMut(([f32; 3], [f32; 3], [f32; 3], [f32; 3]), Voxel, Face, bool), G: VoxelGrid>(
     mut quad: F,
-    data: &ChunkData,
+    data: &G,
 ) {
     let mut voxel_mask = [None; CHUNK_SIZE * CHUNK_SIZE];
 
@@ -46,8 +68,8 @@ fn greedy_meshing<F: FnMut(([f32; 3], [f32; 3], [f32; 3], [f32; 3]), Voxel, Face
                     x[v] = i;
                     for i in 0..CHUNK_SIZE as isize {
                         x[u] = i;
-                        let v1 = data.get(x[0], x[1], x[2]);
-                        let v2 = data.get(x[0] + q[0], x[1] + q[1], x[2] + q[2]);
+                        let v1 = data.voxel(x[0], x[1], x[2]);
+                        let v2 = data.voxel(x[0] + q[0], x[1] + q[1], x[2] + q[2]);
 
                         voxel_mask[n] = if let (Some(v1), Some(v2)) = (v1, v2) {
                             if v1.is_empty() == v2.is_empty() {
@@ -185,12 +207,72 @@ pub struct MeshData {
     indices: Vec<u16>,
 }
 
+/// Colour of every voxel id from the sprite of its block, air and unknown ids are black.
+#[derive(Default, Clone)]
+pub struct BlockColors(pub Arc<Vec<[f32; 3]>>);
+
+impl BlockColors {
+    pub fn from_defs(blocks: &Blocks, sprites: &Sprites) -> Self {
+        let mut colors = vec![[0.; 3]];
+        colors.extend(blocks.iter().map(|block| {
+            let (r, g, b) = sprites[block.sprite].color;
+            [r as f32 / 255., g as f32 / 255., b as f32 / 255.]
+        }));
+        Self(Arc::new(colors))
+    }
+}
+
+/// Greedy meshes a chunk into packed vertices, coloured by `colors` indexed by voxel id.
+pub fn build_mesh<G: VoxelGrid>(data: &G, colors: &[[f32; 3]]) -> MeshData {
+    let mut vertices: Vec<u32> = Vec::new();
+    let mut indices: Vec<u16> = Vec::new();
+
+    let quad = |q: ([f32; 3], [f32; 3], [f32; 3], [f32; 3]),
+                voxel: Voxel,
+                face: Face,
+                backface: bool| {
+        let start = vertices.len() as u16;
+        let color = colors.get(voxel.id as usize).copied().unwrap_or_default();
+        let light = face.light();
+
+        vertices.push(construct_data(q.0, face, color, light)); //  1-------2
+        vertices.push(construct_data(q.1, face, color, light)); //  |       |
+        vertices.push(construct_data(q.2, face, color, light)); //  |       |
+        vertices.push(construct_data(q.3, face, color, light)); //  0-------3
+
+        if backface {
+            //-----------------------//  1---2
+            indices.push(start + 0); //  |  /
+            indices.push(start + 1); //  | /
+            indices.push(start + 2); //  0/
+
+            //-----------------------//    /2
+            indices.push(start + 0); //   / |
+            indices.push(start + 2); //  /  |
+            indices.push(start + 3); // 0---3
+        } else {
+            //-----------------------//  1---2
+            indices.push(start + 1); //  |  /
+            indices.push(start + 0); //  | /
+            indices.push(start + 2); //  0/
+
+            //-----------------------//    /2
+            indices.push(start + 2); //   / |
+            indices.push(start + 0); //  /  |
+            indices.push(start + 3); // 0---3
+        }
+    };
+    greedy_meshing(quad, data);
+    MeshData { vertices, indices }
+}
+
 fn chunk_mesh_generator(
     mut commands: Commands,
     chunks: Query<(Entity, &ChunkPosition, &ChunkData), Without<ChunkMesh>>,
     thread_pool: Res<AsyncComputeTaskPool>,
     mut gen: Query<(Entity, &mut ChunkGenerator)>,
     load_settings: Res<LoadSettings>,
+    colors: Res<BlockColors>,
 ) {
     for (entity, mut gen) in gen.iter_mut() {
         if gen.mesh_job_count < load_settings.meshed_per_frame {
@@ -203,67 +285,16 @@ fn chunk_mesh_generator(
                 load_settings.meshed_per_frame - gen.mesh_job_count,
                 to_load.len(),
             ) {
-                if to_load[i].2.num_voxels == 0 {
+                if to_load[i].2.num_voxels() == 0 {
                     commands
                         .entity(to_load[i].0)
                         .remove::<ChunkMesh>()
                         .remove::<ChunkData>()
                         .remove_bundle::<MeshBundle>();
                 } else {
-                    let data = *to_load[i].2;
-                    let task = thread_pool.spawn(async move {
-                        let mut vertices: Vec<u32> = Vec::new();
-                        let mut indices: Vec<u16> = Vec::new();
-
-                        let quad = |q: ([f32; 3], [f32; 3], [f32; 3], [f32; 3]),
-                                    voxel: Voxel,
-                                    face: Face,
-                                    backface: bool| {
-                            let start = vertices.len() as u16;
-                            let color = match voxel.id {
-                                1 => [1., 0., 1.],
-                                2 => [0.1, 0.7, 0.],
-                                3 => [0.9, 0., 0.],
-                                4 => [1.0, 0.15, 0.],
-                                5 => [0., 0.25, 0.6],
-                                6 => [0., 0.6, 0.25],
-                                7 => [1., 1., 0.25],
-                                8 => [0.25, 1., 0.25],
-                                9 => [0.25, 0.1, 0.1],
-                                _ => [0., 0., 0.],
-                            };
-                            let light = face.light();
-
-                            vertices.push(construct_data(q.0, face, color, light)); //  1-------2
-                            vertices.push(construct_data(q.1, face, color, light)); //  |       |
-                            vertices.push(construct_data(q.2, face, color, light)); //  |       |
-                            vertices.push(construct_data(q.3, face, color, light)); //  0-------3
-
-                            if backface {
-                                //-----------------------//  1---2
-                                indices.push(start + 0); //  |  /
-                                indices.push(start + 1); //  | /
-                                indices.push(start + 2); //  0/
-
-                                //-----------------------//    /2
-                                indices.push(start + 0); //   / |
-                                indices.push(start + 2); //  /  |
-                                indices.push(start + 3); // 0---3
-                            } else {
-                                //-----------------------//  1---2
-                                indices.push(start + 1); //  |  /
-                                indices.push(start + 0); //  | /
-                                indices.push(start + 2); //  0/
-
-                                //-----------------------//    /2
-                                indices.push(start + 2); //   / |
-                                indices.push(start + 0); //  /  |
-                                indices.push(start + 3); // 0---3
-                            }
-                        };
-                        greedy_meshing(quad, &data);
-                        MeshData { vertices, indices }
-                    });
+                    let data = to_load[i].2.clone();
+                    let colors = colors.0.clone();
+                    let task = thread_pool.spawn(async move { build_mesh(&data, &colors) });
 
                     gen.mesh_job_count += 1;
                     commands
@@ -333,7 +364,13 @@ fn chunk_mesh_updater(
     }
 }
 
+fn build_block_colors(blocks: Res<Blocks>, sprites: Res<Sprites>, mut colors: ResMut<BlockColors>) {
+    *colors = BlockColors::from_defs(&blocks, &sprites);
+}
+
 pub fn add_systems(app: &mut AppBuilder) {
-    app.add_system(chunk_mesh_generator.system())
+    app.init_resource::<BlockColors>()
+        .add_startup_system_to_stage("generate", build_block_colors.system().after("generate_blocks"))
+        .add_system(chunk_mesh_generator.system())
         .add_system(chunk_mesh_updater.system());
 }
@@ -2,24 +2,39 @@ use bevy::prelude::*;
 
 mod chunk;
 mod chunk_culling;
+mod generation;
 mod loader;
 mod meshing;
+mod noise;
 mod ordered_float;
+mod palette;
 mod shader;
+mod storage;
 mod voxel;
 
 mod chunk_edit;
 
+pub use chunk::{ChunkData, Chunks};
 pub use chunk_edit::SphereEdit;
+pub use generation::{
+    ChunkContext, FlatGenerator, GenerationRng, GenerationStage, OreVein, Seed, TerrainBlocks, TerrainGenerator,
+    WorldGen, WorldGenerator, WorldGenerators,
+};
 pub use loader::ChunkGenerator;
-pub use voxel::Voxel;
+pub use meshing::{build_mesh, BlockColors, MeshData, VoxelGrid};
+pub use noise::{CompiledNoise, NoiseBiome, NoiseGenerator, NoiseKind, NoiseNode, NoiseSource};
+pub use storage::{BlockPalette, ChunkModified, ChunkStore};
+pub use voxel::{Voxel, VoxelArray, CHUNK_SIZE};
 pub struct ChunkPlugin;
 
 impl Plugin for ChunkPlugin {
     fn build(&self, app: &mut AppBuilder) {
+        generation::add_systems(app);
         loader::add_systems(app);
         meshing::add_systems(app);
+        noise::add_systems(app);
         chunk_edit::add_systems(app);
+        storage::add_systems(app);
 
         app.add_startup_system(shader::pipeline_setup.system())
             .add_system(chunk_culling::frustum_culling.system());
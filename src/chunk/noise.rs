@@ -0,0 +1,787 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use simdnoise::{scalar, CellDistanceFunction, CellReturnType, NoiseBuilder};
+#[cfg(target_arch = "x86")]
+use std::arch::x86 as arch;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64 as arch;
+
+use crate::defs::{Biomes, Blocks, Definition, Message, NoiseGraphs};
+
+use super::{
+    chunk::CHUNK_VOLUME,
+    generation::{
+        noise_seed, ChunkContext, GenerationStage, OreVein, TerrainBlocks, TerrainGenerator, WorldGen,
+        WorldGenerator, WorldGenerators,
+    },
+    voxel::{Voxel, CHUNK_SIZE},
+};
+
+// The defaults of simdnoise, single points are sampled without its builder.
+const DEFAULT_FREQUENCY: f32 = 0.02;
+const DEFAULT_LACUNARITY: f32 = 0.5;
+const DEFAULT_GAIN: f32 = 2.0;
+const DEFAULT_OCTAVES: u8 = 3;
+const DEFAULT_JITTER: f32 = 0.25;
+
+/// Noise functions of simdnoise a graph can sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NoiseKind {
+    Fbm,
+    Ridge,
+    Turbulence,
+    Cellular,
+}
+
+/// Noise sampled at the world position of every voxel. Unset settings use the defaults of simdnoise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseSource {
+    pub kind: NoiseKind,
+    // Sampled at x and z only, the same all the way up.
+    #[serde(default)]
+    pub flat: bool,
+    #[serde(default)]
+    pub frequency: Option<f32>,
+    #[serde(default)]
+    pub lacunarity: Option<f32>,
+    #[serde(default)]
+    pub gain: Option<f32>,
+    #[serde(default)]
+    pub octaves: Option<u8>,
+    // Added to the seed of the world, so sources with the same settings differ.
+    #[serde(default)]
+    pub seed: i32,
+    // Cellular only, the value of the cell instead of the distance to it.
+    #[serde(default)]
+    pub cell_value: bool,
+    #[serde(default)]
+    pub jitter: Option<f32>,
+}
+
+/// A node of a noise graph. Inputs are the names of other nodes in the same graph.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NoiseNode {
+    Source(NoiseSource),
+    Constant(f32),
+    // World coordinates of the voxel.
+    X,
+    Y,
+    Z,
+    Add(Vec<String>),
+    Mul(Vec<String>),
+    Clamp {
+        input: String,
+        min: f32,
+        max: f32,
+    },
+    // Remaps the input through the line between points, which are `(input, output)`.
+    Spline {
+        input: String,
+        points: Vec<(f32, f32)>,
+    },
+    // The input sampled at the position moved by the three offsets times the strength.
+    Warp {
+        input: String,
+        x: String,
+        y: String,
+        z: String,
+        strength: f32,
+    },
+}
+
+// A source with the defaults filled in.
+#[derive(Debug, Clone, Copy)]
+struct Source {
+    kind: NoiseKind,
+    flat: bool,
+    frequency: f32,
+    lacunarity: f32,
+    gain: f32,
+    octaves: u8,
+    seed: i32,
+    cell_value: bool,
+    jitter: f32,
+}
+
+macro_rules! fractal {
+    ($builder:expr, $source:expr, $seed:expr) => {
+        $builder
+            .with_seed($seed)
+            .with_freq($source.frequency)
+            .with_lacunarity($source.lacunarity)
+            .with_gain($source.gain)
+            .with_octaves($source.octaves)
+            .generate()
+            .0
+    };
+}
+
+// Samples a source at any positions a vector of lanes at a time, with the instructions of a module of simdnoise. The
+// last vector is padded with its first position.
+macro_rules! sample_lanes {
+    ($name:ident, $module:ident, $feature:literal, $lanes:literal, $set1:ident, $load:ident, $store:ident) => {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        #[target_feature(enable = $feature)]
+        unsafe fn $name(&self, positions: &[Vec3], seed: i32) -> Vec<f32> {
+            use simdnoise::$module;
+            let seed = seed.wrapping_add(self.seed);
+            let (lac, gain, octaves) = (arch::$set1(self.lacunarity), arch::$set1(self.gain), self.octaves);
+            let (jitter, distance) = (arch::$set1(self.jitter), CellDistanceFunction::Euclidean);
+            let mut values = Vec::with_capacity(positions.len());
+            for batch in positions.chunks($lanes) {
+                let (mut xs, mut ys, mut zs) = ([0.; $lanes], [0.; $lanes], [0.; $lanes]);
+                for i in 0..$lanes {
+                    // The builder scales positions by the frequency, the vector functions leave it to the caller.
+                    let p = *batch.get(i).unwrap_or(&batch[0]) * self.frequency;
+                    xs[i] = p.x;
+                    ys[i] = p.y;
+                    zs[i] = p.z;
+                }
+                let (x, y, z) = (arch::$load(xs.as_ptr()), arch::$load(ys.as_ptr()), arch::$load(zs.as_ptr()));
+                let noise = match (self.kind, self.flat) {
+                    (NoiseKind::Fbm, true) => $module::fbm_2d(x, z, lac, gain, octaves, seed),
+                    (NoiseKind::Fbm, false) => $module::fbm_3d(x, y, z, lac, gain, octaves, seed),
+                    (NoiseKind::Ridge, true) => $module::ridge_2d(x, z, lac, gain, octaves, seed),
+                    (NoiseKind::Ridge, false) => $module::ridge_3d(x, y, z, lac, gain, octaves, seed),
+                    (NoiseKind::Turbulence, true) => $module::turbulence_2d(x, z, lac, gain, octaves, seed),
+                    (NoiseKind::Turbulence, false) => $module::turbulence_3d(x, y, z, lac, gain, octaves, seed),
+                    (NoiseKind::Cellular, true) => $module::cellular_2d(x, z, distance, self.return_type(), jitter, seed),
+                    (NoiseKind::Cellular, false) => {
+                        $module::cellular_3d(x, y, z, distance, self.return_type(), jitter, seed)
+                    }
+                };
+                let mut out = [0.; $lanes];
+                arch::$store(out.as_mut_ptr(), noise);
+                values.extend_from_slice(&out[..batch.len()]);
+            }
+            values
+        }
+    };
+}
+
+macro_rules! cellular {
+    ($builder:expr, $source:expr, $seed:expr) => {
+        $builder
+            .with_seed($seed)
+            .with_freq($source.frequency)
+            .with_return_type($source.return_type())
+            .with_jitter($source.jitter)
+            .generate()
+            .0
+    };
+}
+
+impl Source {
+    fn new(source: &NoiseSource) -> Self {
+        Self {
+            kind: source.kind,
+            flat: source.flat,
+            frequency: source.frequency.unwrap_or(DEFAULT_FREQUENCY),
+            lacunarity: source.lacunarity.unwrap_or(DEFAULT_LACUNARITY),
+            gain: source.gain.unwrap_or(DEFAULT_GAIN),
+            octaves: source.octaves.unwrap_or(DEFAULT_OCTAVES),
+            seed: source.seed,
+            cell_value: source.cell_value,
+            jitter: source.jitter.unwrap_or(DEFAULT_JITTER),
+        }
+    }
+
+    fn return_type(&self) -> CellReturnType {
+        if self.cell_value {
+            CellReturnType::CellValue
+        } else {
+            CellReturnType::Distance
+        }
+    }
+
+    // Noise of every column of the chunk at `origin`, `[z][x]`.
+    fn columns(&self, origin: Vec3, seed: i32) -> Vec<f32> {
+        let seed = seed.wrapping_add(self.seed);
+        let (x, z, size) = (origin.x, origin.z, CHUNK_SIZE);
+        match self.kind {
+            NoiseKind::Fbm => fractal!(NoiseBuilder::fbm_2d_offset(x, size, z, size), self, seed),
+            NoiseKind::Ridge => fractal!(NoiseBuilder::ridge_2d_offset(x, size, z, size), self, seed),
+            NoiseKind::Turbulence => fractal!(NoiseBuilder::turbulence_2d_offset(x, size, z, size), self, seed),
+            NoiseKind::Cellular => cellular!(NoiseBuilder::cellular_2d_offset(x, size, z, size), self, seed),
+        }
+    }
+
+    // Noise of every voxel of the chunk at `origin`, `[z][y][x]`.
+    fn fill(&self, origin: Vec3, seed: i32) -> Vec<f32> {
+        if self.flat {
+            let columns = self.columns(origin, seed);
+            return (0..CHUNK_VOLUME)
+                .map(|i| columns[i / (CHUNK_SIZE * CHUNK_SIZE) * CHUNK_SIZE + i % CHUNK_SIZE])
+                .collect();
+        }
+        let seed = seed.wrapping_add(self.seed);
+        let (x, y, z, size) = (origin.x, origin.y, origin.z, CHUNK_SIZE);
+        match self.kind {
+            NoiseKind::Fbm => fractal!(NoiseBuilder::fbm_3d_offset(x, size, y, size, z, size), self, seed),
+            NoiseKind::Ridge => fractal!(NoiseBuilder::ridge_3d_offset(x, size, y, size, z, size), self, seed),
+            NoiseKind::Turbulence => {
+                fractal!(NoiseBuilder::turbulence_3d_offset(x, size, y, size, z, size), self, seed)
+            }
+            NoiseKind::Cellular => cellular!(NoiseBuilder::cellular_3d_offset(x, size, y, size, z, size), self, seed),
+        }
+    }
+
+    // The same noise as `sample` at every position, with the best instructions the cpu has like the builder.
+    fn sample_many(&self, positions: &[Vec3], seed: i32) -> Vec<f32> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        unsafe {
+            if is_x86_feature_detected!("avx2") {
+                return self.sample_avx2(positions, seed);
+            } else if is_x86_feature_detected!("sse4.1") {
+                return self.sample_sse41(positions, seed);
+            } else if is_x86_feature_detected!("sse2") {
+                return self.sample_sse2(positions, seed);
+            }
+        }
+        positions.iter().map(|pos| self.sample(*pos, seed)).collect()
+    }
+
+    sample_lanes!(sample_avx2, avx2, "avx2", 8, _mm256_set1_ps, _mm256_loadu_ps, _mm256_storeu_ps);
+    sample_lanes!(sample_sse41, sse41, "sse4.1", 4, _mm_set1_ps, _mm_loadu_ps, _mm_storeu_ps);
+    sample_lanes!(sample_sse2, sse2, "sse2", 4, _mm_set1_ps, _mm_loadu_ps, _mm_storeu_ps);
+
+    // The same noise as `fill` at a single position.
+    fn sample(&self, pos: Vec3, seed: i32) -> f32 {
+        let seed = seed.wrapping_add(self.seed);
+        // The builder scales positions by the frequency, the scalar functions leave it to the caller.
+        let p = pos * self.frequency;
+        let (lac, gain, octaves) = (self.lacunarity, self.gain, self.octaves);
+        let distance = CellDistanceFunction::Euclidean;
+        // The scalar functions don't use any instructions the target might not have.
+        unsafe {
+            match (self.kind, self.flat) {
+                (NoiseKind::Fbm, true) => scalar::fbm_2d(p.x, p.z, lac, gain, octaves, seed),
+                (NoiseKind::Fbm, false) => scalar::fbm_3d(p.x, p.y, p.z, lac, gain, octaves, seed),
+                (NoiseKind::Ridge, true) => scalar::ridge_2d(p.x, p.z, lac, gain, octaves, seed),
+                (NoiseKind::Ridge, false) => scalar::ridge_3d(p.x, p.y, p.z, lac, gain, octaves, seed),
+                (NoiseKind::Turbulence, true) => scalar::turbulence_2d(p.x, p.z, lac, gain, octaves, seed),
+                (NoiseKind::Turbulence, false) => scalar::turbulence_3d(p.x, p.y, p.z, lac, gain, octaves, seed),
+                (NoiseKind::Cellular, true) => {
+                    scalar::cellular_2d(p.x, p.z, distance, self.return_type(), self.jitter, seed)
+                }
+                (NoiseKind::Cellular, false) => {
+                    scalar::cellular_3d(p.x, p.y, p.z, distance, self.return_type(), self.jitter, seed)
+                }
+            }
+        }
+    }
+}
+
+// A compiled node, inputs are indices of earlier ops.
+#[derive(Debug, Clone)]
+enum Op {
+    Source(Source),
+    Constant(f32),
+    X,
+    Y,
+    Z,
+    Add(Vec<usize>),
+    Mul(Vec<usize>),
+    Clamp {
+        input: usize,
+        min: f32,
+        max: f32,
+    },
+    Spline {
+        input: usize,
+        points: Vec<(f32, f32)>,
+    },
+    Warp {
+        input: usize,
+        x: usize,
+        y: usize,
+        z: usize,
+        strength: f32,
+    },
+}
+
+fn spline(points: &[(f32, f32)], value: f32) -> f32 {
+    let i = points.iter().position(|(input, _)| *input > value).unwrap_or(points.len());
+    if i == 0 {
+        return points[0].1;
+    }
+    if i == points.len() {
+        return points[i - 1].1;
+    }
+    let ((x0, y0), (x1, y1)) = (points[i - 1], points[i]);
+    y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+}
+
+// World position of the voxel at `i` of a `[z][y][x]` buffer.
+fn position(origin: Vec3, i: usize) -> Vec3 {
+    origin
+        + Vec3::new(
+            (i % CHUNK_SIZE) as f32,
+            (i / CHUNK_SIZE % CHUNK_SIZE) as f32,
+            (i / (CHUNK_SIZE * CHUNK_SIZE)) as f32,
+        )
+}
+
+// Positions a buffer of values is filled at.
+#[derive(Clone, Copy)]
+enum Points<'a> {
+    // Every voxel of the chunk with its corner here, `[z][y][x]`.
+    Chunk(Vec3),
+    At(&'a [Vec3]),
+}
+
+impl Points<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Self::Chunk(_) => CHUNK_VOLUME,
+            Self::At(positions) => positions.len(),
+        }
+    }
+
+    fn get(&self, i: usize) -> Vec3 {
+        match self {
+            Self::Chunk(origin) => position(*origin, i),
+            Self::At(positions) => positions[i],
+        }
+    }
+}
+
+// Ops `last` depends on, ending with it. Inputs of warps are left out, they are only filled at the warped positions.
+fn needed(ops: &[Op], last: usize) -> Vec<bool> {
+    let mut needed = vec![false; last + 1];
+    needed[last] = true;
+    for (i, op) in ops[..=last].iter().enumerate().rev() {
+        if !needed[i] {
+            continue;
+        }
+        match op {
+            Op::Add(inputs) | Op::Mul(inputs) => inputs.iter().for_each(|input| needed[*input] = true),
+            Op::Clamp { input, .. } | Op::Spline { input, .. } => needed[*input] = true,
+            Op::Warp { x, y, z, .. } => {
+                needed[*x] = true;
+                needed[*y] = true;
+                needed[*z] = true;
+            }
+            _ => {}
+        }
+    }
+    needed
+}
+
+struct Compiler<'a> {
+    nodes: &'a HashMap<String, NoiseNode>,
+    ids: HashMap<String, usize>,
+    // Nodes being compiled, to find cycles.
+    visiting: Vec<String>,
+    ops: Vec<Op>,
+}
+
+impl<'a> Compiler<'a> {
+    fn node(&mut self, name: &str) -> Result<usize, String> {
+        if let Some(id) = self.ids.get(name) {
+            return Ok(*id);
+        }
+        if self.visiting.iter().any(|visiting| visiting == name) {
+            return Err(format!("Noise node {} depends on itself.", name));
+        }
+        let node = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| format!("Unknown noise node {}.", name))?;
+        self.visiting.push(name.to_string());
+        let op = match node {
+            NoiseNode::Source(source) => Op::Source(Source::new(source)),
+            NoiseNode::Constant(value) => Op::Constant(*value),
+            NoiseNode::X => Op::X,
+            NoiseNode::Y => Op::Y,
+            NoiseNode::Z => Op::Z,
+            NoiseNode::Add(inputs) | NoiseNode::Mul(inputs) => {
+                if inputs.is_empty() {
+                    return Err(format!("Noise node {} has no inputs.", name));
+                }
+                let inputs = inputs.iter().map(|input| self.node(input)).collect::<Result<Vec<_>, _>>()?;
+                match node {
+                    NoiseNode::Add(_) => Op::Add(inputs),
+                    _ => Op::Mul(inputs),
+                }
+            }
+            NoiseNode::Clamp { input, min, max } => Op::Clamp {
+                input: self.node(input)?,
+                min: *min,
+                max: *max,
+            },
+            NoiseNode::Spline { input, points } => {
+                if points.is_empty() {
+                    return Err(format!("Spline {} has no points.", name));
+                }
+                let mut points = points.clone();
+                points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                Op::Spline {
+                    input: self.node(input)?,
+                    points,
+                }
+            }
+            NoiseNode::Warp {
+                input,
+                x,
+                y,
+                z,
+                strength,
+            } => Op::Warp {
+                input: self.node(input)?,
+                x: self.node(x)?,
+                y: self.node(y)?,
+                z: self.node(z)?,
+                strength: *strength,
+            },
+        };
+        self.visiting.pop();
+        self.ops.push(op);
+        self.ids.insert(name.to_string(), self.ops.len() - 1);
+        Ok(self.ops.len() - 1)
+    }
+}
+
+/// A noise graph compiled for evaluating whole chunks at once. Nodes the output doesn't depend on are left out, and
+/// every node is evaluated once per chunk, however many nodes use it.
+#[derive(Debug, Clone)]
+pub struct CompiledNoise {
+    // Inputs come before the ops using them, the output is last.
+    ops: Vec<Op>,
+    // Ops that are only sampled at warped positions aren't filled for the whole chunk.
+    filled: Vec<bool>,
+}
+
+impl CompiledNoise {
+    pub fn compile(nodes: &HashMap<String, NoiseNode>, output: &str) -> Result<Self, String> {
+        let mut compiler = Compiler {
+            nodes,
+            ids: HashMap::default(),
+            visiting: vec![],
+            ops: vec![],
+        };
+        compiler.node(output)?;
+        let ops = compiler.ops;
+        let filled = needed(&ops, ops.len() - 1);
+        Ok(Self { ops, filled })
+    }
+
+    /// Value of every voxel of the chunk with its corner at `origin`, `[z][y][x]` like simdnoise.
+    pub fn fill(&self, origin: Vec3, seed: i32) -> Vec<f32> {
+        self.fill_points(&self.filled, Points::Chunk(origin), seed)
+    }
+
+    // Values of the last of the `needed` ops at every point, each op is filled once for all of them.
+    fn fill_points(&self, needed: &[bool], points: Points, seed: i32) -> Vec<f32> {
+        let mut values: Vec<Vec<f32>> = Vec::with_capacity(needed.len());
+        for (op, needed) in self.ops.iter().zip(needed) {
+            let buffer = if *needed {
+                self.fill_op(op, &values, points, seed)
+            } else {
+                vec![]
+            };
+            values.push(buffer);
+        }
+        values.pop().unwrap()
+    }
+
+    fn fill_op(&self, op: &Op, values: &[Vec<f32>], points: Points, seed: i32) -> Vec<f32> {
+        let map = |input: usize, f: &dyn Fn(f32) -> f32| -> Vec<f32> {
+            values[input].iter().map(|value| f(*value)).collect()
+        };
+        match op {
+            // The builder only fills grids, warped positions are sampled in vectors of lanes instead.
+            Op::Source(source) => match points {
+                Points::Chunk(origin) => source.fill(origin, seed),
+                Points::At(positions) => source.sample_many(positions, seed),
+            },
+            Op::Constant(value) => vec![*value; points.len()],
+            Op::X => (0..points.len()).map(|i| points.get(i).x).collect(),
+            Op::Y => (0..points.len()).map(|i| points.get(i).y).collect(),
+            Op::Z => (0..points.len()).map(|i| points.get(i).z).collect(),
+            Op::Add(inputs) | Op::Mul(inputs) => {
+                let add = matches!(op, Op::Add(_));
+                let mut result = values[inputs[0]].clone();
+                for input in &inputs[1..] {
+                    for (result, value) in result.iter_mut().zip(&values[*input]) {
+                        if add {
+                            *result += value;
+                        } else {
+                            *result *= value;
+                        }
+                    }
+                }
+                result
+            }
+            Op::Clamp { input, min, max } => map(*input, &|value| value.max(*min).min(*max)),
+            Op::Spline { input, points } => map(*input, &|value| spline(points, value)),
+            Op::Warp {
+                input,
+                x,
+                y,
+                z,
+                strength,
+            } => {
+                let warped: Vec<Vec3> = (0..points.len())
+                    .map(|i| points.get(i) + Vec3::new(values[*x][i], values[*y][i], values[*z][i]) * *strength)
+                    .collect();
+                self.fill_points(&needed(&self.ops, *input), Points::At(&warped), seed)
+            }
+        }
+    }
+
+    /// Value at a single world position, slower per point than `fill`.
+    pub fn sample(&self, pos: Vec3, seed: i32) -> f32 {
+        self.sample_op(self.ops.len() - 1, pos, seed)
+    }
+
+    fn sample_op(&self, op: usize, pos: Vec3, seed: i32) -> f32 {
+        match &self.ops[op] {
+            Op::Source(source) => source.sample(pos, seed),
+            Op::Constant(value) => *value,
+            Op::X => pos.x,
+            Op::Y => pos.y,
+            Op::Z => pos.z,
+            Op::Add(inputs) => inputs.iter().map(|input| self.sample_op(*input, pos, seed)).sum(),
+            Op::Mul(inputs) => inputs.iter().map(|input| self.sample_op(*input, pos, seed)).product(),
+            Op::Clamp { input, min, max } => self.sample_op(*input, pos, seed).max(*min).min(*max),
+            Op::Spline { input, points } => spline(points, self.sample_op(*input, pos, seed)),
+            Op::Warp {
+                input,
+                x,
+                y,
+                z,
+                strength,
+            } => {
+                let offset = Vec3::new(
+                    self.sample_op(*x, pos, seed),
+                    self.sample_op(*y, pos, seed),
+                    self.sample_op(*z, pos, seed),
+                ) * *strength;
+                self.sample_op(*input, pos + offset, seed)
+            }
+        }
+    }
+}
+
+// Picks the biome of every column, big enough that a player walks a while through each.
+const BIOME_CELLS: Source = Source {
+    kind: NoiseKind::Cellular,
+    flat: true,
+    frequency: 0.002,
+    lacunarity: DEFAULT_LACUNARITY,
+    gain: DEFAULT_GAIN,
+    octaves: DEFAULT_OCTAVES,
+    seed: 0,
+    cell_value: true,
+    jitter: DEFAULT_JITTER,
+};
+
+/// A biome of the noise generator, built from its `Biome` definition.
+#[derive(Debug, Clone)]
+pub struct NoiseBiome {
+    pub density: Arc<CompiledNoise>,
+    // From the surface down, each with how many voxels deep it goes.
+    pub layers: Vec<(Voxel, u32)>,
+    pub stone: Voxel,
+    // Of the air, `None` for comfortable.
+    pub temperature: Option<f32>,
+}
+
+/// Terrain from the noise graphs of the biomes mods define. Voxels are solid where the density of their biome is above
+/// 0, and biomes are picked per column by cells of noise. They don't blend yet, so there can be cliffs between them.
+#[derive(Debug, Clone)]
+pub struct NoiseGenerator {
+    pub biomes: Vec<NoiseBiome>,
+    pub ores: Vec<OreVein>,
+}
+
+impl NoiseGenerator {
+    // The biome of a value of `BIOME_CELLS`.
+    fn biome(&self, cell: f32) -> usize {
+        let count = self.biomes.len();
+        (((cell + 1.) * 0.5 * count as f32) as usize).min(count - 1)
+    }
+}
+
+impl WorldGenerator for NoiseGenerator {
+    fn name(&self) -> &str {
+        "noise"
+    }
+
+    fn density(&self, chunk: &mut ChunkContext) {
+        let origin = chunk.origin();
+        let seed = chunk.noise_seed(GenerationStage::Density);
+        let count = self.biomes.len();
+        chunk.biomes = if count > 1 {
+            BIOME_CELLS.columns(origin, seed).into_iter().map(|cell| self.biome(cell)).collect()
+        } else {
+            vec![0; CHUNK_SIZE * CHUNK_SIZE]
+        };
+        // Only the biomes in this chunk are filled.
+        let mut densities: Vec<Option<Vec<f32>>> = vec![None; count];
+        for biome in chunk.biomes.iter() {
+            if densities[*biome].is_none() {
+                densities[*biome] = Some(self.biomes[*biome].density.fill(origin, seed));
+            }
+        }
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let biome = chunk.biomes[z * CHUNK_SIZE + x];
+                    let density = densities[biome].as_ref().unwrap()[(z * CHUNK_SIZE + y) * CHUNK_SIZE + x];
+                    if density > 0. {
+                        chunk.set(x, y, z, self.biomes[biome].stone);
+                    }
+                }
+            }
+        }
+    }
+
+    fn surface(&self, chunk: &mut ChunkContext) {
+        let seed = chunk.noise_seed(GenerationStage::Density);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let biome = &self.biomes[chunk.biomes[z * CHUNK_SIZE + x]];
+                let total: u32 = biome.layers.iter().map(|(_, thickness)| thickness).sum();
+                if total == 0 {
+                    continue;
+                }
+                // Solid voxels right above the chunk count towards the depth, so layers go on across chunks.
+                let top = chunk.world_pos(x, CHUNK_SIZE, z);
+                let mut depth = 0;
+                while depth < total && biome.density.sample(top + Vec3::new(0., depth as f32, 0.), seed) > 0. {
+                    depth += 1;
+                }
+                for y in (0..CHUNK_SIZE).rev() {
+                    if chunk.get(x, y, z).is_empty() {
+                        depth = 0;
+                        continue;
+                    }
+                    depth += 1;
+                    let mut bottom = 0;
+                    if let Some((voxel, _)) = biome.layers.iter().find(|(_, thickness)| {
+                        bottom += thickness;
+                        depth <= bottom
+                    }) {
+                        chunk.set(x, y, z, *voxel);
+                    }
+                }
+            }
+        }
+    }
+
+    fn temperature(&self, pos: Vec3, seed: u64) -> Option<f32> {
+        let biome = match self.biomes.len() {
+            0 => return None,
+            1 => 0,
+            _ => self.biome(BIOME_CELLS.sample(pos, noise_seed(seed, GenerationStage::Density))),
+        };
+        self.biomes[biome].temperature
+    }
+
+    fn ores(&self, chunk: &mut ChunkContext) {
+        let biomes = &self.biomes;
+        chunk.place_ores(&self.ores, |voxel| biomes.iter().any(|biome| biome.stone == voxel));
+    }
+}
+
+// Finds a block of a biome by `namespace:id`, or by id in the namespace of the biome.
+fn biome_block(blocks: &Blocks, biome: &str, name: &str) -> Option<Voxel> {
+    let string_id = if name.contains(':') {
+        name.to_string()
+    } else {
+        let namespace = biome.split(':').next().unwrap_or_default();
+        format!("{}:{}", namespace, name)
+    };
+    blocks.get(&string_id).map(|block| Voxel::from_block(&blocks[block], 0))
+}
+
+// Worlds that would use the built-in terrain use the biomes of mods instead.
+fn build_noise_generator(
+    graphs: Res<NoiseGraphs>,
+    biomes: Res<Biomes>,
+    blocks: Res<Blocks>,
+    terrain: Res<TerrainBlocks>,
+    mut generators: ResMut<WorldGenerators>,
+    mut generator: ResMut<WorldGen>,
+    mut printer: EventWriter<Message>,
+) {
+    let compiled: Vec<Option<Arc<CompiledNoise>>> = graphs
+        .iter()
+        .map(|graph| match CompiledNoise::compile(&graph.nodes, &graph.output) {
+            Ok(compiled) => Some(Arc::new(compiled)),
+            Err(e) => {
+                printer.send(Message::error(format!("Noise graph {}: {}", graph.get_string_id(), e)));
+                None
+            }
+        })
+        .collect();
+
+    let mut noise_biomes = vec![];
+    'biomes: for def in biomes.iter() {
+        let density = match &compiled[graphs[def.density].get_id()] {
+            Some(density) => density.clone(),
+            None => continue,
+        };
+        let mut layers = vec![];
+        for (name, thickness) in def.layers.iter() {
+            match biome_block(&blocks, &def.get_string_id(), name) {
+                Some(voxel) => layers.push((voxel, *thickness)),
+                None => {
+                    printer.send(Message::error(format!(
+                        "Biome {} uses unknown block {}.",
+                        def.get_string_id(),
+                        name
+                    )));
+                    continue 'biomes;
+                }
+            }
+        }
+        let stone = match &def.stone {
+            Some(name) => match biome_block(&blocks, &def.get_string_id(), name) {
+                Some(voxel) => voxel,
+                None => {
+                    printer.send(Message::error(format!(
+                        "Biome {} uses unknown block {}.",
+                        def.get_string_id(),
+                        name
+                    )));
+                    continue;
+                }
+            },
+            None => terrain.stone,
+        };
+        noise_biomes.push(NoiseBiome {
+            density,
+            layers: if def.layers.is_empty() { terrain.layers.clone() } else { layers },
+            stone,
+            temperature: def.temperature,
+        });
+    }
+    if noise_biomes.is_empty() {
+        return;
+    }
+
+    let noise = Arc::new(NoiseGenerator {
+        biomes: noise_biomes,
+        ores: terrain.ores.clone(),
+    });
+    if generator.0.name() == TerrainGenerator::default().name() {
+        generator.0 = noise.clone();
+    }
+    generators.add(noise);
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_startup_system_to_stage(
+        "generate",
+        build_noise_generator
+            .system()
+            .label("build_noise_generator")
+            .after("build_generators"),
+    );
+}
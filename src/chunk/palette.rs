@@ -0,0 +1,67 @@
+/// Bits per index that a palette of `len` entries needs. Always divides 64, so indices never straddle words.
+pub fn bits_for(len: usize) -> u32 {
+    match len {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        17..=256 => 8,
+        _ => 16,
+    }
+}
+
+/// Fixed size array of palette indices packed `bits` at a time into words.
+#[derive(Clone)]
+pub struct PackedArray {
+    bits: u32,
+    words: Vec<u64>,
+}
+
+impl PackedArray {
+    pub fn new(bits: u32, len: usize) -> Self {
+        debug_assert!(64 % bits == 0);
+        let per_word = (64 / bits) as usize;
+        Self {
+            bits,
+            words: vec![0; (len + per_word - 1) / per_word],
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    fn per_word(&self) -> usize {
+        (64 / self.bits) as usize
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+
+    pub fn get(&self, i: usize) -> usize {
+        let per_word = self.per_word();
+        let shift = (i % per_word) as u32 * self.bits;
+        ((self.words[i / per_word] >> shift) & self.mask()) as usize
+    }
+
+    pub fn set(&mut self, i: usize, value: usize) {
+        let per_word = self.per_word();
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = self.mask();
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !(mask << shift)) | ((value as u64 & mask) << shift);
+    }
+
+    /// The same `len` indices with `bits` per index.
+    pub fn with_bits(&self, bits: u32, len: usize) -> Self {
+        let mut packed = Self::new(bits, len);
+        for i in 0..len {
+            packed.set(i, self.get(i));
+        }
+        packed
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+    }
+}
@@ -0,0 +1,638 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::tasks::IoTaskPool;
+use bevy::utils::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{Blocks, Definition, Message};
+
+use super::{
+    chunk::{ChunkData, ChunkPosition, CHUNK_VOLUME},
+    generation::{Seed, WorldGen, WorldGenerators},
+    voxel::{Voxel, VoxelArray, CHUNK_SIZE},
+};
+
+/// Folder the world is saved in.
+pub const WORLD_DIR: &str = "world";
+/// Chunks along each side of a region, every region is saved in its own file.
+pub const REGION_SIZE: i32 = 8;
+/// Bumped whenever the layout of region files changes.
+pub const FORMAT_VERSION: u32 = 1;
+/// Seconds between saves of the modified chunks that are still loaded.
+pub const SAVE_INTERVAL: f32 = 30.;
+
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+const MAGIC: [u8; 4] = *b"AIRG";
+// Magic, version and the offset and length of every chunk.
+const HEADER_LEN: usize = 8 + REGION_CHUNKS * 8;
+// The first byte of every stored chunk says how the rest is encoded, so new encodings don't need a new version.
+// Encodings 0 and 1 are from before voxels had states and 16 bit ids, and are only read.
+const ENCODING_RAW: u8 = 0;
+const ENCODING_RLE_U8: u8 = 1;
+const ENCODING_RLE: u8 = 2;
+
+/// What a world was made with, saved next to its regions so it keeps generating the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldInfo {
+    pub version: u32,
+    pub seed: u64,
+    pub generator: String,
+    // `namespace:id` of every block id stored in the regions, starting at 1 since 0 is air.
+    #[serde(default)]
+    pub blocks: Vec<String>,
+}
+
+/// Maps block ids to the ids stored in region files and back. Block ids follow the load order of the mods, so the
+/// stored ones are kept by name in the `WorldInfo` instead.
+#[derive(Debug, Clone, Default)]
+pub struct BlockPalette {
+    // Index 0 is air in both. Empty tables keep ids as they are.
+    to_stored: Vec<u16>,
+    to_block: Vec<u16>,
+}
+
+impl BlockPalette {
+    /// Palette of the blocks stored under `stored`, blocks that aren't stored yet are added to the end of it.
+    pub fn new(stored: &mut Vec<String>, blocks: &Blocks) -> Self {
+        let mut to_stored = vec![0];
+        for block in blocks.iter() {
+            let string_id = block.get_string_id();
+            let index = match stored.iter().position(|name| *name == string_id) {
+                Some(index) => index,
+                None => {
+                    stored.push(string_id);
+                    stored.len() - 1
+                }
+            };
+            to_stored.push(index as u16 + 1);
+        }
+        // Stored blocks that are no longer defined become air.
+        let mut to_block = vec![0; stored.len() + 1];
+        for (id, stored_id) in to_stored.iter().enumerate() {
+            to_block[*stored_id as usize] = id as u16;
+        }
+        Self { to_stored, to_block }
+    }
+
+    fn map(table: &[u16], voxel: Voxel) -> Voxel {
+        if table.is_empty() {
+            return voxel;
+        }
+        match table.get(voxel.id as usize) {
+            Some(0) | None => Voxel::default(),
+            Some(&id) => Voxel::new(id, voxel.state),
+        }
+    }
+
+    pub fn to_stored(&self, voxel: Voxel) -> Voxel {
+        Self::map(&self.to_stored, voxel)
+    }
+
+    pub fn to_block(&self, voxel: Voxel) -> Voxel {
+        Self::map(&self.to_block, voxel)
+    }
+}
+
+/// Marks chunks that were changed since they were last saved.
+pub struct ChunkModified;
+
+pub fn region_of(pos: IVec3) -> IVec3 {
+    IVec3::new(
+        pos.x.div_euclid(REGION_SIZE),
+        pos.y.div_euclid(REGION_SIZE),
+        pos.z.div_euclid(REGION_SIZE),
+    )
+}
+
+fn index_in_region(pos: IVec3) -> usize {
+    let x = pos.x.rem_euclid(REGION_SIZE);
+    let y = pos.y.rem_euclid(REGION_SIZE);
+    let z = pos.z.rem_euclid(REGION_SIZE);
+    ((y * REGION_SIZE + z) * REGION_SIZE + x) as usize
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(word)
+}
+
+// Runs of the same voxel as a `u16` length followed by the stored id and state, in `[y][z][x]` order.
+fn encode(data: &ChunkData, palette: &BlockPalette) -> Vec<u8> {
+    let mut bytes = vec![ENCODING_RLE];
+    let mut push = |voxel: Voxel, len: u16| {
+        let voxel = palette.to_stored(voxel);
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(&voxel.id.to_le_bytes());
+        bytes.push(voxel.state);
+    };
+    let mut run: Option<(Voxel, u16)> = None;
+    for voxel in data.iter() {
+        run = match run {
+            Some((v, len)) if v == voxel => Some((v, len + 1)),
+            Some((v, len)) => {
+                push(v, len);
+                Some((voxel, 1))
+            }
+            None => Some((voxel, 1)),
+        };
+    }
+    if let Some((v, len)) = run {
+        push(v, len);
+    }
+    bytes
+}
+
+// Fills the voxels from runs of `run_len` bytes, starting with a `u16` length.
+fn decode_runs(
+    runs: &[u8],
+    run_len: usize,
+    voxel: impl Fn(&[u8]) -> Voxel,
+    mut set: impl FnMut(usize, Voxel),
+) -> io::Result<()> {
+    if runs.len() % run_len != 0 {
+        return Err(invalid("chunk ends in the middle of a run"));
+    }
+    let mut i = 0;
+    for run in runs.chunks(run_len) {
+        let len = u16::from_le_bytes([run[0], run[1]]) as usize;
+        if i + len > CHUNK_VOLUME {
+            return Err(invalid("chunk runs past its last voxel"));
+        }
+        let voxel = voxel(&run[2..]);
+        for i in i..i + len {
+            set(i, voxel);
+        }
+        i += len;
+    }
+    if i != CHUNK_VOLUME {
+        return Err(invalid(format!("chunk has {} voxels instead of {}", i, CHUNK_VOLUME)));
+    }
+    Ok(())
+}
+
+fn decode(bytes: &[u8], palette: &BlockPalette) -> io::Result<ChunkData> {
+    let mut voxels = VoxelArray::default();
+    let mut set = |i: usize, voxel: Voxel| {
+        voxels[i / (CHUNK_SIZE * CHUNK_SIZE)][i / CHUNK_SIZE % CHUNK_SIZE][i % CHUNK_SIZE] = palette.to_block(voxel);
+    };
+    match bytes.split_first() {
+        Some((&ENCODING_RAW, ids)) if ids.len() == CHUNK_VOLUME => {
+            for (i, &id) in ids.iter().enumerate() {
+                set(i, Voxel::new(id as u16, 0));
+            }
+        }
+        Some((&ENCODING_RAW, ids)) => {
+            return Err(invalid(format!("chunk has {} voxels instead of {}", ids.len(), CHUNK_VOLUME)))
+        }
+        Some((&ENCODING_RLE_U8, runs)) => decode_runs(runs, 3, |run| Voxel::new(run[0] as u16, 0), set)?,
+        Some((&ENCODING_RLE, runs)) => decode_runs(
+            runs,
+            5,
+            |run| Voxel::new(u16::from_le_bytes([run[0], run[1]]), run[2]),
+            set,
+        )?,
+        Some((encoding, _)) => return Err(invalid(format!("unknown chunk encoding {}", encoding))),
+        None => return Err(invalid("chunk is empty")),
+    }
+    Ok(ChunkData::from_array(&voxels))
+}
+
+// Offset and length of every chunk in a region file, a length of 0 means the chunk isn't stored.
+fn read_header(bytes: &[u8]) -> io::Result<Vec<(usize, usize)>> {
+    if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+        return Err(invalid("not a region file"));
+    }
+    let version = read_u32(bytes, 4);
+    if version != FORMAT_VERSION {
+        return Err(invalid(format!(
+            "region file has version {}, only version {} can be read",
+            version, FORMAT_VERSION
+        )));
+    }
+    Ok((0..REGION_CHUNKS)
+        .map(|i| (read_u32(bytes, 8 + i * 8) as usize, read_u32(bytes, 12 + i * 8) as usize))
+        .collect())
+}
+
+// The chunks of a region as they are stored, `None` for chunks that were never saved.
+struct Region {
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl Region {
+    fn read(path: &Path) -> io::Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    chunks: vec![None; REGION_CHUNKS],
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let chunks = read_header(&bytes)?
+            .into_iter()
+            .map(|(offset, len)| match len {
+                0 => Ok(None),
+                _ => bytes
+                    .get(offset..offset + len)
+                    .map(|chunk| Some(chunk.to_vec()))
+                    .ok_or_else(|| invalid("chunk goes past the end of the region file")),
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { chunks })
+    }
+
+    // Reads a single chunk without reading the rest of the file.
+    fn read_chunk(path: &Path, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut header = vec![0; HEADER_LEN];
+        file.read_exact(&mut header)?;
+        let (offset, len) = read_header(&header)?[index];
+        if len == 0 {
+            return Ok(None);
+        }
+        // Checked before allocating, a broken header could ask for gigabytes.
+        if offset as u64 + len as u64 > file.metadata()?.len() {
+            return Err(invalid("chunk goes past the end of the region file"));
+        }
+        let mut chunk = vec![0; len];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut chunk)?;
+        Ok(Some(chunk))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        let mut offset = HEADER_LEN;
+        for chunk in &self.chunks {
+            let len = chunk.as_ref().map_or(0, |chunk| chunk.len());
+            bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+            bytes.extend_from_slice(&(len as u32).to_le_bytes());
+            offset += len;
+        }
+        for chunk in self.chunks.iter().flatten() {
+            bytes.extend_from_slice(chunk);
+        }
+        bytes
+    }
+}
+
+// Writes to a temporary file that replaces the old one once it is on disk, so a crash leaves either the old or the
+// new file but never half of one.
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+struct StoreInner {
+    dir: PathBuf,
+    // Chunks waiting to be written, with the generation they were queued at so a save doesn't drop a newer version
+    // queued while it was writing. Loads look here first so they never see an older version on disk.
+    pending: Mutex<HashMap<IVec3, (u64, Arc<ChunkData>)>>,
+    generation: AtomicU64,
+    // Held while a region file is read or rewritten, only kept while someone uses it.
+    regions: Mutex<HashMap<IVec3, Arc<Mutex<()>>>>,
+    // Errors from background tasks, sent as messages by `report_store_errors`.
+    errors: Mutex<Vec<String>>,
+    palette: Mutex<Arc<BlockPalette>>,
+}
+
+/// Region files of modified chunks, shared with the tasks that load and save them.
+#[derive(Clone)]
+pub struct ChunkStore {
+    inner: Arc<StoreInner>,
+}
+
+impl ChunkStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            inner: Arc::new(StoreInner {
+                dir: dir.into(),
+                pending: Default::default(),
+                generation: AtomicU64::new(0),
+                regions: Default::default(),
+                errors: Default::default(),
+                palette: Default::default(),
+            }),
+        }
+    }
+
+    fn region_path(&self, region: IVec3) -> PathBuf {
+        self.inner
+            .dir
+            .join(format!("r.{}.{}.{}.region", region.x, region.y, region.z))
+    }
+
+    // Runs `f` while holding the lock of the region.
+    fn with_region<T>(&self, region: IVec3, f: impl FnOnce() -> T) -> T {
+        let lock = self.inner.regions.lock().unwrap().entry(region).or_default().clone();
+        let result = {
+            let _guard = lock.lock().unwrap();
+            f()
+        };
+        drop(lock);
+        // Nobody else can take the lock while the map is locked, so it can go if the map has the last reference.
+        let mut regions = self.inner.regions.lock().unwrap();
+        if matches!(regions.get(&region), Some(lock) if Arc::strong_count(lock) == 1) {
+            regions.remove(&region);
+        }
+        result
+    }
+
+    /// Sets how block ids are stored, has to be set before any chunk is loaded or saved.
+    pub fn set_palette(&self, palette: BlockPalette) {
+        *self.inner.palette.lock().unwrap() = Arc::new(palette);
+    }
+
+    fn palette(&self) -> Arc<BlockPalette> {
+        self.inner.palette.lock().unwrap().clone()
+    }
+
+    /// Queues a chunk to be written with the next save of its region.
+    pub fn queue(&self, pos: IVec3, data: &ChunkData) {
+        let generation = self.inner.generation.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .pending
+            .lock()
+            .unwrap()
+            .insert(pos, (generation, Arc::new(data.clone())));
+    }
+
+    /// The saved version of a chunk, `None` if it was never modified.
+    pub fn load(&self, pos: IVec3) -> io::Result<Option<ChunkData>> {
+        if let Some((_, data)) = self.inner.pending.lock().unwrap().get(&pos) {
+            return Ok(Some((**data).clone()));
+        }
+        let region = region_of(pos);
+        self.with_region(region, || Region::read_chunk(&self.region_path(region), index_in_region(pos)))?
+            .map(|chunk| decode(&chunk, &self.palette()))
+            .transpose()
+    }
+
+    /// Writes the queued chunks of a region to its file.
+    pub fn save_region(&self, region: IVec3) -> io::Result<()> {
+        self.with_region(region, || self.write_region(region))
+    }
+
+    fn write_region(&self, region: IVec3) -> io::Result<()> {
+        let queued: Vec<(IVec3, u64, Arc<ChunkData>)> = self
+            .inner
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(pos, _)| region_of(**pos) == region)
+            .map(|(pos, (generation, data))| (*pos, *generation, data.clone()))
+            .collect();
+        if queued.is_empty() {
+            return Ok(());
+        }
+
+        let path = self.region_path(region);
+        // A region file that can't be read is left alone instead of being replaced by the queued chunks only.
+        let mut file = Region::read(&path)?;
+        let palette = self.palette();
+        for (pos, _, data) in &queued {
+            file.chunks[index_in_region(*pos)] = Some(encode(data, &palette));
+        }
+        fs::create_dir_all(&self.inner.dir)?;
+        write_atomic(&path, &file.to_bytes())?;
+
+        let mut pending = self.inner.pending.lock().unwrap();
+        for (pos, generation, _) in queued {
+            if matches!(pending.get(&pos), Some((g, _)) if *g == generation) {
+                pending.remove(&pos);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the region in the background, errors are reported the next frame.
+    pub fn save_region_in_background(&self, region: IVec3, pool: &IoTaskPool) {
+        let store = self.clone();
+        pool.spawn(async move {
+            if let Err(e) = store.save_region(region) {
+                store.report(format!("Couldn't save region {}: {}", region, e));
+            }
+        })
+        .detach();
+    }
+
+    /// Writes every queued chunk, blocking until done. Returns the first error, but still tries every region.
+    pub fn flush(&self) -> io::Result<()> {
+        let regions: HashSet<IVec3> = self
+            .inner
+            .pending
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|pos| region_of(*pos))
+            .collect();
+        let mut result = Ok(());
+        for region in regions {
+            if let Err(e) = self.save_region(region) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    fn info_path(&self) -> PathBuf {
+        self.inner.dir.join("world.json")
+    }
+
+    /// The info of the saved world, `None` if there is no saved world yet.
+    pub fn load_info(&self) -> io::Result<Option<WorldInfo>> {
+        let json = match fs::read_to_string(self.info_path()) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let info: WorldInfo = serde_json::from_str(&json).map_err(invalid)?;
+        if info.version != FORMAT_VERSION {
+            return Err(invalid(format!(
+                "world has version {}, only version {} can be read",
+                info.version, FORMAT_VERSION
+            )));
+        }
+        Ok(Some(info))
+    }
+
+    pub fn save_info(&self, info: &WorldInfo) -> io::Result<()> {
+        fs::create_dir_all(&self.inner.dir)?;
+        let json = serde_json::to_string_pretty(info).map_err(invalid)?;
+        write_atomic(&self.info_path(), json.as_bytes())
+    }
+
+    pub fn report(&self, error: String) {
+        self.inner.errors.lock().unwrap().push(error);
+    }
+
+    fn take_errors(&self) -> Vec<String> {
+        std::mem::take(&mut *self.inner.errors.lock().unwrap())
+    }
+}
+
+/// What is saved for a modified chunk, chunks without data were emptied.
+pub fn saved_data(data: Option<&ChunkData>) -> ChunkData {
+    data.cloned().unwrap_or_else(|| ChunkData::all(&Voxel::default()))
+}
+
+// Queues every modified chunk and returns the regions they are in.
+fn queue_modified(
+    commands: &mut Commands,
+    store: &ChunkStore,
+    query: &Query<(Entity, &ChunkPosition, Option<&ChunkData>), With<ChunkModified>>,
+) -> HashSet<IVec3> {
+    let mut regions = HashSet::default();
+    for (entity, pos, data) in query.iter() {
+        store.queue(pos.0, &saved_data(data));
+        regions.insert(region_of(pos.0));
+        commands.entity(entity).remove::<ChunkModified>();
+    }
+    regions
+}
+
+// A saved world keeps its seed, generator and stored block ids, a new one is saved with the current ones. The game
+// stops if the saved world can't be loaded, generating it with another seed would mix two worlds.
+fn load_world_info(
+    store: Res<ChunkStore>,
+    blocks: Res<Blocks>,
+    generators: Res<WorldGenerators>,
+    mut seed: ResMut<Seed>,
+    mut generator: ResMut<WorldGen>,
+    mut printer: EventWriter<Message>,
+    mut exit: EventWriter<AppExit>,
+) {
+    match store.load_info() {
+        Ok(Some(mut info)) => {
+            seed.0 = info.seed;
+            match generators.get(&info.generator) {
+                Some(saved) => generator.0 = saved,
+                None => printer.send(Message::error(format!(
+                    "The world was made with generator {}, which isn't loaded. Using {} instead.",
+                    info.generator,
+                    generator.0.name()
+                ))),
+            }
+            let missing: Vec<&str> = info
+                .blocks
+                .iter()
+                .filter(|name| blocks.get(name).is_none())
+                .map(|name| name.as_str())
+                .collect();
+            if !missing.is_empty() {
+                printer.send(Message::error(format!(
+                    "The world has blocks that aren't defined, they are loaded as air: {}",
+                    missing.join(", ")
+                )));
+            }
+            let stored = info.blocks.len();
+            store.set_palette(BlockPalette::new(&mut info.blocks, &blocks));
+            if info.blocks.len() != stored {
+                if let Err(e) = store.save_info(&info) {
+                    printer.send(Message::error(format!("Couldn't save the world info: {}", e)));
+                }
+            }
+        }
+        Ok(None) => {
+            let mut info = WorldInfo {
+                version: FORMAT_VERSION,
+                seed: seed.0,
+                generator: generator.0.name().to_string(),
+                blocks: vec![],
+            };
+            store.set_palette(BlockPalette::new(&mut info.blocks, &blocks));
+            if let Err(e) = store.save_info(&info) {
+                printer.send(Message::error(format!("Couldn't save the world info: {}", e)));
+            }
+        }
+        Err(e) => {
+            // Nothing runs after this frame to print messages.
+            eprintln!("{}", Message::error(format!("Couldn't load the world info: {}", e)));
+            exit.send(AppExit);
+        }
+    }
+}
+
+#[derive(Default)]
+struct SaveTimer {
+    since_save: f32,
+}
+
+fn save_modified_chunks(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut timer: Local<SaveTimer>,
+    store: Res<ChunkStore>,
+    pool: Res<IoTaskPool>,
+    query: Query<(Entity, &ChunkPosition, Option<&ChunkData>), With<ChunkModified>>,
+) {
+    timer.since_save += time.delta_seconds();
+    if timer.since_save < SAVE_INTERVAL {
+        return;
+    }
+    timer.since_save = 0.;
+    for region in queue_modified(&mut commands, &store, &query) {
+        store.save_region_in_background(region, &pool);
+    }
+}
+
+fn flush_on_exit(
+    mut commands: Commands,
+    mut exit: EventReader<AppExit>,
+    store: Res<ChunkStore>,
+    query: Query<(Entity, &ChunkPosition, Option<&ChunkData>), With<ChunkModified>>,
+) {
+    if exit.iter().next().is_none() {
+        return;
+    }
+    queue_modified(&mut commands, &store, &query);
+    // Nothing runs after this frame to print messages.
+    if let Err(e) = store.flush() {
+        eprintln!("{}", Message::error(format!("Couldn't save the world: {}", e)));
+    }
+}
+
+fn report_store_errors(store: Res<ChunkStore>, mut printer: EventWriter<Message>) {
+    for error in store.take_errors() {
+        printer.send(Message::error(error));
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.insert_resource(ChunkStore::new(WORLD_DIR))
+        .add_startup_system_to_stage("generate", load_world_info.system().after("build_noise_generator"))
+        .add_system(save_modified_chunks.system())
+        .add_system(report_store_errors.system())
+        .add_system_to_stage(CoreStage::Last, flush_on_exit.system());
+}
@@ -1,6 +1,11 @@
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+use crate::defs::{Block, BlockDefinition, Definition};
+
+/// A block and its state. Id 0 is air, any other id is the block with one less id.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Voxel {
-    pub id: u8,
+    pub id: u16,
+    // Meaning depends on the `BlockState` of the block.
+    pub state: u8,
 }
 #[derive(Clone, Copy)]
 pub enum Face {
@@ -34,8 +39,27 @@ impl Face {
 }
 
 impl Voxel {
+    pub fn new(id: u16, state: u8) -> Self {
+        Self { id, state }
+    }
+
+    /// A voxel of `block`, states the block doesn't have are replaced by 0.
+    pub fn from_block(block: &BlockDefinition, state: u8) -> Self {
+        Self {
+            id: block.get_id() as u16 + 1,
+            state: if block.state.is_valid(state) { state } else { 0 },
+        }
+    }
+
+    pub fn block(&self) -> Option<Block> {
+        match self.id {
+            0 => None,
+            id => Some(Block::from(id as usize - 1)),
+        }
+    }
+
     pub fn is_same_face(&self, other: &Voxel, face: Face) -> bool {
-        self.id == other.id
+        self.id == other.id && self.state == other.state
     }
 
     pub fn is_empty(&self) -> bool {
@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+use crate::chunk::NoiseNode;
 use crate::item::ToolProficiencies;
 use bevy::prelude::*;
 use bevy::utils::HashMap;
@@ -13,6 +14,10 @@ use std::{
 };
 
 use crate::item::{ToolPart, ToolProfeciency};
+use crate::stats::{
+    AbilityEffect, BaseStat, DmgType, EffectAction, Reduction, EffectCategory, InteractionOutcome, ResourceConsumption, SoftCap, Stacking,
+    StatKey, Targeting, Trigger,
+};
 
 pub trait Definition {
     fn get_name(&self) -> &String;
@@ -190,9 +195,9 @@ macro_rules! ref_tuples {
 macro_rules! ref_struct {
     ($ty:ident [$($field:ident: $field_ty:ty), *][$($ref_field:ident: $ref_field_ty:ty), *]) => {
         #[derive(Debug, Clone, Serialize, Deserialize)]
-        struct $ty {
-            $($field: $field_ty,) *
-            $($ref_field: $ref_field_ty,) *
+        pub struct $ty {
+            $(pub $field: $field_ty,) *
+            $(pub $ref_field: $ref_field_ty,) *
         }
         paste! {
             #[derive(Debug, Serialize, Deserialize)]
@@ -230,7 +235,7 @@ macro_rules! ref_struct {
 macro_rules! ref_enum {
     ($ty:ident [$($field:ident $(: $field_ty:ty)?), *][$($ref_field:ident: $ref_field_ty:ty), *]) => {
         #[derive(Debug, Clone, Serialize, Deserialize)]
-        enum $ty {
+        pub enum $ty {
             $($field $(($field_ty))?,) *
             $($ref_field($ref_field_ty),) *
         }
@@ -407,9 +412,9 @@ macro_rules! definitions {
                     namespace: String,
                     string_id: String,
                     id: usize,
-                    $($item: $item_type,) *
-                    $($($cross_reference: $cross_reference_type,) *)?
-                    $($($hidden_item: $hidden_item_ty,) *)?
+                    $(pub $item: $item_type,) *
+                    $($(pub $cross_reference: $cross_reference_type,) *)?
+                    $($(pub $hidden_item: $hidden_item_ty,) *)?
                 }
 
                 pub struct [< $ty s >] {
@@ -432,6 +437,11 @@ macro_rules! definitions {
                     pub fn next_id(&self) -> usize {
                         self.items.len()
                     }
+
+                    /// Finds a definition by its `namespace:id`.
+                    pub fn get(&self, string_id: &str) -> Option<$ty> {
+                        self.items.iter().find(|item| item.get_string_id() == string_id).map(|item| $ty(item.id))
+                    }
                 }
 
                 impl Index<$ty> for [< $ty s >] {
@@ -640,6 +650,8 @@ macro_rules! definitions {
                                     })
                                 }).collect::<Option<Vec<[< $ty Definition >]>>>()?
                             };
+                        // Definitions are indexed by id.
+                        [< $ty:snake s_defs >].items.sort_by_key(|item| item.id);
                         $($on_done(&mut [< $ty:snake s_defs >]);)?
                         commands.insert_resource([< $ty:snake s_defs >]);
                     ) *
@@ -674,7 +686,7 @@ macro_rules! definitions {
                             def.id = obj["id"].as_str()?.into();
 
 
-                            let string_id = namespace.clone() + def.id.as_str();
+                            let string_id = gen_id(namespace, &def.id);
                             let id = {
                                 let temp = self.[< $ty:snake _count >];
                                 self.[< $ty:snake _count >] += 1;
@@ -741,17 +753,16 @@ macro_rules! definitions {
                 data: Vec<u8>,
             }
 
-            pub fn generate_binary(
-                mut commands: Commands,
-                $([< $ty:snake s>]: Res<[< $ty s>]>), *
-            ) {
+            // Exclusive, since there are more definition types than a system can take resources.
+            pub fn generate_binary(world: &mut World) {
                 let mut obj = serde_json::Map::<String, serde_json::Value>::default();
                 $(
-                    obj.insert(stringify!([< $ty:snake s>]).to_string(), serde_json::to_value([< $ty:snake s>].items.clone()).unwrap());
+                    let [< $ty:snake s>] = world.get_resource::<[< $ty s>]>().expect("Definitions have to be built before generating the binary.");
+                    obj.insert(stringify!([< $ty:snake s>]).to_string(), serde_json::to_value(&[< $ty:snake s>].items).unwrap());
                 ) *
                 let mut data: Vec<u8> = vec![];
                 serde_cbor::to_writer(&mut data, &obj).expect("Failed to write json object to byte vector. Maybe low on ram?");
-                commands.insert_resource(DefinitionBinary { data });
+                world.insert_resource(DefinitionBinary { data });
             }
         }
     }
@@ -762,7 +773,7 @@ ref_struct! {
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-enum TextureCrop {
+pub enum TextureCrop {
     Full,
     Crop(f32, f32, f32, f32),
     Animated(f32, f32),
@@ -773,6 +784,40 @@ impl Default for TextureCrop {
         Self::Full
     }
 }
+
+/// What the state stored with every voxel of a block means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockState {
+    None,
+    // One of the six faces the top of the block points to.
+    Facing,
+    // How full a liquid is, from 0 for empty to the number of levels.
+    Level(u8),
+    // Stage of growth, from 0 for just planted to the number of stages.
+    Growth(u8),
+}
+
+impl Default for BlockState {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl BlockState {
+    /// How many states there are, states go from 0 to one less.
+    pub fn count(&self) -> u16 {
+        match self {
+            Self::None => 1,
+            Self::Facing => 6,
+            Self::Level(levels) => *levels as u16 + 1,
+            Self::Growth(stages) => *stages as u16 + 1,
+        }
+    }
+
+    pub fn is_valid(&self, state: u8) -> bool {
+        (state as u16) < self.count()
+    }
+}
 ref_struct! {
     OreData[min_drop: usize, max_drop: usize][material: Material]
 }
@@ -822,13 +867,13 @@ definitions! {
 
     Model[faces: Vec<(f32, f32, f32, f32)>],
 
-    Block[friction: f32][sprite: Sprite],
+    Block[friction: f32, state: BlockState][sprite: Sprite],
     Liquid[viscocity: f32][sprite: Sprite],
     Recipe[],
 
     BodyPart[],
 
-    Species[][],
+    Species[triggers: Vec<Trigger>, base_stats: HashMap<BaseStat, f32>][],
 
     Sprite[color: (u8, u8, u8), crop: TextureCrop][texture: Texture],
     Sfx[pitch: f32, volume: f32][sound: Sound],
@@ -836,6 +881,69 @@ definitions! {
 
     Texture[location: String],
     Sound[location: String],
+
+    StatCapOverride[target: String, min: Option<f32>, max: Option<f32>, soft: Option<SoftCap>],
+
+    Effect[
+            stacking: Stacking,
+            max_stacks: Option<u32>,
+            interval: Option<f32>,
+            categories: EffectCategory,
+            on_start: Vec<EffectAction>,
+            on_tick: Vec<EffectAction>,
+            on_end: Vec<EffectAction>,
+            on_expire: Vec<EffectAction>,
+        ],
+
+    Ability[
+            cost: Vec<ResourceConsumption>,
+            cooldown: f32,
+            cast_time: f32,
+            range: Option<f32>,
+            targeting: Targeting,
+            effects: Vec<AbilityEffect>,
+        ],
+
+    // The built-in damage types are defined in the vanilla mod.
+    DamageType[
+            scaling: Option<StatKey>,
+            crit_chance: Option<StatKey>,
+            can_crit: bool,
+            armor: Option<StatKey>,
+            reduction: Option<Reduction>,
+            flat_reduction: Option<Reduction>,
+            bypass_mitigation: bool,
+        ],
+
+    // Damage of any of the types hitting an entity with the effect.
+    Interaction[damage_types: Vec<DmgType>, outcomes: Vec<InteractionOutcome>][effect: Effect],
+
+    // Stats and resources of mods, referred to by `namespace:id` wherever a built-in one could be used.
+    CustomStat[
+            default: f32,
+            from: Vec<(StatKey, f32)>,
+            min: Option<f32>,
+            max: Option<f32>,
+            soft: Option<SoftCap>,
+        ],
+    CustomResource[max: Option<StatKey>, regen: Option<StatKey>, start_full: bool],
+
+    // How the regeneration of a resource, by name or `namespace:id`, is scaled by the combat state.
+    Regeneration[
+            resource: String,
+            in_combat: Option<f32>,
+            out_of_combat: Option<f32>,
+            resting: Option<f32>,
+            sleeping: Option<f32>,
+            delay: f32,
+            rest_burst: f32,
+        ],
+
+    // Nodes by name, the density is the value of the output node.
+    NoiseGraph[nodes: HashMap<String, NoiseNode>, output: String],
+    // Terrain of the noise generator. Layers go from the surface down, by block and how deep they go, the built-in
+    // layers and stone are used where they are left out. Without a temperature the air is comfortable.
+    Biome[layers: Vec<(String, u32)>, stone: Option<String>, temperature: Option<f32>][density: NoiseGraph],
 }
 
 pub enum MessageType {
@@ -850,13 +958,13 @@ pub struct Message {
 }
 
 impl Message {
-    fn error(message: String) -> Self {
+    pub fn error(message: String) -> Self {
         Self {
             ty: MessageType::Error,
             message: message,
         }
     }
-    fn warning(message: String) -> Self {
+    pub fn warning(message: String) -> Self {
         Self {
             ty: MessageType::Warning,
             message: message,
@@ -903,6 +1011,9 @@ fn init_definitions(mut commands: Commands, mut printer: EventWriter<Message>) {
     }
 }
 
+/// Levels of the blocks generated for liquids.
+pub const LIQUID_LEVELS: u8 = 8;
+
 fn generate_blocks(
     mut blocks: ResMut<Blocks>,
     mut recipes: ResMut<Recipes>,
@@ -917,6 +1028,7 @@ fn generate_blocks(
             string_id: format!("liquid_{}", liquid.id),
             namespace: liquid.namespace.clone(),
             friction: liquid.viscocity,
+            state: BlockState::Level(LIQUID_LEVELS),
             sprite: liquid.sprite,
         })
     }
@@ -934,12 +1046,12 @@ impl Plugin for Definitions {
         .add_startup_stage_after(
             "init",
             "generate",
-            SystemStage::parallel().with_system(generate_blocks.system()),
+            SystemStage::parallel().with_system(generate_blocks.system().label("generate_blocks")),
         )
         .add_startup_stage_after(
             "generate",
             "binary",
-            SystemStage::single_threaded().with_system(generate_binary.system()),
+            SystemStage::single_threaded().with_system(generate_binary.exclusive_system()),
         );
     }
 }
@@ -5,6 +5,7 @@ use crate::defs;
 use crate::serializable;
 use serde::{Serialize, Deserialize};
 use crate::count_idents;
+use crate::stats::{Stat, Stats};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ToolPart {
@@ -39,6 +40,21 @@ serializable! {
     }
 }
 
+impl ToolProficiencies {
+    pub fn blocking(&self) -> f32 {
+        self.blocking
+    }
+
+    /// Gives the holder the blocking of the tool, undone by `unequip`.
+    pub fn equip(&self, stats: &mut Stats) {
+        stats.add_stat(Stat::Blocking, self.blocking);
+    }
+
+    pub fn unequip(&self, stats: &mut Stats) {
+        stats.add_stat(Stat::Blocking, -self.blocking);
+    }
+}
+
 pub struct Tool {
     id : defs::Tool,
     materials: Vec<defs::Material>,
@@ -0,0 +1,10 @@
+pub mod chunk;
+pub mod creature;
+#[cfg(feature = "debug")]
+pub mod debug;
+pub mod defs;
+pub mod item;
+pub mod macro_help;
+pub mod network;
+pub mod stats;
+pub mod world;
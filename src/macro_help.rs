@@ -60,10 +60,10 @@ pub trait Bitmap {
 
 #[macro_export]
 macro_rules! bitmap_inner {
-    ($ty:ty, $data:expr, $current_value:ident) => {
+    ($ty:ident, $data:expr, $current_value:ident) => {
         pub const $current_value: $ty = $ty(1 << $data);
     };
-    ($ty:ty, $data:expr, $current_value:ident, $($value:ident), +) => {
+    ($ty:ident, $data:expr, $current_value:ident, $($value:ident), +) => {
         crate::bitmap_inner!($ty, $data, $current_value);
         crate::bitmap_inner!($ty, ($data + 1), $($value), +);
     }
@@ -72,7 +72,7 @@ macro_rules! bitmap_inner {
 #[macro_export]
 macro_rules! bitmap {
     ($acc:vis $name:ident: $ty:ty[$($value:ident), * $(,)?]) => {
-        #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+        #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
         $acc struct $name($ty);
         #[allow(non_upper_case_globals)]
         impl $name {
@@ -91,6 +91,30 @@ macro_rules! bitmap {
                 !other.is_empty() && (other - *self).is_empty()
             }
         }
+        // Serialized as a list of the names of the set values.
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(
+                    [$((stringify!($value), $name::$value)), *]
+                        .iter()
+                        .filter(|(_, value)| crate::macro_help::Bitmap::is(self, *value))
+                        .map(|(name, _)| *name),
+                )
+            }
+        }
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let mut result = $name(0);
+                for name in Vec::<String>::deserialize(deserializer)? {
+                    result.0 |= match name.as_str() {
+                        $(stringify!($value) => $name::$value.0,) *
+                        other => return Err(serde::de::Error::unknown_variant(other, &[$(stringify!($value)), *])),
+                    };
+                }
+                Ok(result)
+            }
+        }
+
         impl std::ops::Not for $name {
             type Output = $name;
             fn not(self) -> Self {
@@ -1,15 +1,7 @@
-mod chunk;
-mod creature;
 #[cfg(feature = "debug")]
-mod debug;
-mod defs;
-mod item;
-mod macro_help;
-mod network;
-mod stats;
-mod world;
-
-use crate::chunk::ChunkGenerator;
+use aigame::debug;
+use aigame::chunk::{self, ChunkGenerator};
+use aigame::{defs, stats};
 use bevy::{prelude::*, render::camera::Camera};
 use bevy_flycam::{MovementSettings, PlayerPlugin};
 
@@ -28,17 +20,45 @@ fn add_chunk_generator_to_camera(
     }
 }
 
+fn add_stats_to_camera(mut commands: Commands, query: Query<Entity, (With<Camera>, Without<stats::Stats>)>) {
+    for e in query.iter() {
+        commands
+            .entity(e)
+            .insert(stats::Stats::new(stats::BaseStats::ones()))
+            .insert(stats::Environment::default());
+    }
+}
+
 fn movement_input(
     mut commands: Commands,
+    time: Res<Time>,
     keys: Res<Input<KeyCode>>,
     mut settings: ResMut<MovementSettings>,
-    camera: Query<&GlobalTransform, With<Camera>>,
+    mut sprinting: Local<bool>,
+    mut camera: Query<(&GlobalTransform, Option<&mut stats::Stats>), With<Camera>>,
 ) {
     const BOOST: f32 = 5.;
-    if keys.just_pressed(KeyCode::LControl) {
-        settings.speed *= BOOST;
-    } else if keys.just_released(KeyCode::LControl) {
-        settings.speed /= BOOST;
+    // Sprinting and jumping spend stamina, sprinting stops when it runs out. The fly camera has no jump, flying up
+    // is the closest there is.
+    let mut can_sprint = true;
+    for (_, stats) in camera.iter_mut() {
+        if let Some(mut stats) = stats {
+            if keys.pressed(KeyCode::LControl) {
+                can_sprint &= stats.sprint(time.delta_seconds());
+            }
+            if keys.just_pressed(KeyCode::Space) {
+                stats.jump();
+            }
+        }
+    }
+    let sprint = keys.pressed(KeyCode::LControl) && can_sprint;
+    if sprint != *sprinting {
+        if sprint {
+            settings.speed *= BOOST;
+        } else {
+            settings.speed /= BOOST;
+        }
+        *sprinting = sprint;
     }
     if keys.just_pressed(KeyCode::C) {
         settings.speed *= BOOST * 10.;
@@ -47,7 +67,7 @@ fn movement_input(
     }
 
     if keys.just_pressed(KeyCode::R) {
-        for c in camera.iter() {
+        for (c, _) in camera.iter_mut() {
             commands.spawn().insert(chunk::SphereEdit::new(
                 c.translation,
                 200.,
@@ -71,11 +91,14 @@ fn main() {
         .insert_resource(Msaa { samples: 8 })
         .add_plugins(DefaultPlugins)
         .add_plugin(DebugPlugin)
-        .add_plugin(chunk::ChunkPlugin)
         .add_plugin(PlayerPlugin)
         .add_event::<defs::Message>()
         .add_plugin(defs::Definitions)
+        // After the definitions, its startup systems run in their stages.
+        .add_plugin(chunk::ChunkPlugin)
+        .add_plugin(stats::StatsPlugin)
         .add_system(add_chunk_generator_to_camera.system())
+        .add_system(add_stats_to_camera.system())
         .add_system(printer.system())
         .add_system(movement_input.system())
         .run();
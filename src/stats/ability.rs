@@ -0,0 +1,388 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{Abilitys, Ability, Effects};
+use crate::stats::effect::ApplyEffect;
+use crate::stats::{
+    Attack, ConsumptionType, DamageSource, Dmg, ResourceConsumption, ResourceKey, SpatialIndex, StatGain, Stats,
+};
+
+/// Who an ability affects.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Targeting {
+    Caster,
+    Single,
+    // Everything with stats within `radius` of the target, the caster included.
+    Area { radius: f32 },
+}
+
+impl Default for Targeting {
+    fn default() -> Self {
+        Self::Single
+    }
+}
+
+/// What happens to each target when an ability goes off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AbilityEffect {
+    Damage(Dmg),
+    // The effect is given by its `namespace:id`.
+    Effect { effect: String, strength: f32, duration: f32 },
+    // The gain is removed again after `duration` seconds.
+    Buff { gain: StatGain, duration: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CastTarget {
+    None,
+    Entity(Entity),
+    Position(Vec3),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CastError {
+    UnknownAbility,
+    AlreadyCasting,
+    OnCooldown { remaining: f32 },
+    NotEnough { resource: ResourceKey, needed: f32, has: f32 },
+    OutOfRange { distance: f32, range: f32 },
+    InvalidTarget,
+}
+
+struct KnownAbility {
+    ability: Ability,
+    cooldown: f32,
+}
+
+struct Cast {
+    ability: Ability,
+    target: CastTarget,
+    remaining: f32,
+}
+
+/// The abilities an entity knows, their cooldowns and what it is currently casting.
+#[derive(Default)]
+pub struct Abilities {
+    known: Vec<KnownAbility>,
+    casting: Option<Cast>,
+}
+
+impl Abilities {
+    pub fn learn(&mut self, ability: Ability) {
+        if !self.knows(ability) {
+            self.known.push(KnownAbility {
+                ability,
+                cooldown: 0.0,
+            });
+        }
+    }
+
+    pub fn forget(&mut self, ability: Ability) {
+        self.known.retain(|known| known.ability != ability);
+    }
+
+    pub fn knows(&self, ability: Ability) -> bool {
+        self.known.iter().any(|known| known.ability == ability)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Ability> + '_ {
+        self.known.iter().map(|known| known.ability)
+    }
+
+    /// Seconds until the ability can be cast again.
+    pub fn cooldown(&self, ability: Ability) -> Option<f32> {
+        self.known
+            .iter()
+            .find(|known| known.ability == ability)
+            .map(|known| known.cooldown)
+    }
+
+    pub fn casting(&self) -> Option<Ability> {
+        self.casting.as_ref().map(|cast| cast.ability)
+    }
+
+    pub fn interrupt(&mut self) {
+        self.casting = None;
+    }
+
+    fn tick(&mut self, delta: f32) -> Option<Cast> {
+        for known in self.known.iter_mut() {
+            known.cooldown = (known.cooldown - delta).max(0.0);
+        }
+        let done = match &mut self.casting {
+            Some(cast) => {
+                cast.remaining -= delta;
+                cast.remaining <= 0.0
+            }
+            None => false,
+        };
+        if done {
+            self.casting.take()
+        } else {
+            None
+        }
+    }
+}
+
+fn can_afford(stats: &Stats, cost: &ResourceConsumption) -> Result<(), CastError> {
+    let has = stats[cost.resource];
+    let (needed, enough) = match cost.ty {
+        ConsumptionType::Flat(f) => (f, has >= f),
+        // Percentage costs only need there to be something left.
+        ConsumptionType::Percent(_) => (0.0, has > 0.0),
+    };
+    if !stats.has_resource(cost.resource) || !enough {
+        Err(CastError::NotEnough {
+            resource: cost.resource,
+            needed,
+            has,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks if the caster could start casting the ability right now. `distance` is the distance to the target, if there is one.
+pub fn validate_cast(
+    abilities: &Abilities,
+    stats: &Stats,
+    ability: Ability,
+    defs: &Abilitys,
+    target: CastTarget,
+    distance: Option<f32>,
+) -> Result<(), CastError> {
+    let cooldown = abilities.cooldown(ability).ok_or(CastError::UnknownAbility)?;
+    if abilities.casting.is_some() {
+        return Err(CastError::AlreadyCasting);
+    }
+    if cooldown > 0.0 {
+        return Err(CastError::OnCooldown {
+            remaining: cooldown,
+        });
+    }
+    let def = &defs[ability];
+    match (def.targeting, target) {
+        (Targeting::Single, CastTarget::Entity(_)) => {}
+        (Targeting::Single, _) => return Err(CastError::InvalidTarget),
+        _ => {}
+    }
+    if let (Some(range), Some(distance)) = (def.range, distance) {
+        if def.targeting != Targeting::Caster && distance > range {
+            return Err(CastError::OutOfRange { distance, range });
+        }
+    }
+    for cost in &def.cost {
+        can_afford(stats, cost)?;
+    }
+    Ok(())
+}
+
+/// Asks for `caster` to cast an ability, answered with `CastStarted` or `CastFailed`.
+pub struct CastAbility {
+    pub caster: Entity,
+    pub ability: Ability,
+    pub target: CastTarget,
+}
+
+pub struct CastStarted {
+    pub caster: Entity,
+    pub ability: Ability,
+}
+
+pub struct CastFailed {
+    pub caster: Entity,
+    pub ability: Ability,
+    pub reason: CastError,
+}
+
+// Sent once the cast time is over and the ability has been applied to `targets`.
+pub struct AbilityUsed {
+    pub caster: Entity,
+    pub ability: Ability,
+    pub targets: Vec<Entity>,
+}
+
+/// Stat gains from abilities that wear off, with the seconds they have left. Saved with the `Stats` the gains are in,
+/// as nothing else could take them off again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Buffs {
+    buffs: Vec<(StatGain, f32)>,
+}
+
+/// Adds a gain that wears off after `duration` seconds. Entities get their `Buffs` with their `Stats`, the gain isn't
+/// added before then as nothing would remove it.
+pub(super) fn add_buff(buffs: &mut Query<&mut Buffs>, stats: &mut Stats, target: Entity, gain: &StatGain, duration: f32) {
+    if let Ok(mut buffs) = buffs.get_mut(target) {
+        stats.add_gain(gain);
+        buffs.buffs.push((gain.clone(), duration));
+    }
+}
+
+fn position(transforms: &Query<&GlobalTransform>, entity: Entity) -> Option<Vec3> {
+    transforms.get(entity).ok().map(|t| t.translation)
+}
+
+fn target_position(transforms: &Query<&GlobalTransform>, target: CastTarget) -> Option<Vec3> {
+    match target {
+        CastTarget::None => None,
+        CastTarget::Entity(entity) => position(transforms, entity),
+        CastTarget::Position(pos) => Some(pos),
+    }
+}
+
+fn start_casts(
+    defs: Res<Abilitys>,
+    mut requests: EventReader<CastAbility>,
+    mut casters: Query<(&mut Abilities, &mut Stats)>,
+    transforms: Query<&GlobalTransform>,
+    mut started: EventWriter<CastStarted>,
+    mut failed: EventWriter<CastFailed>,
+) {
+    for request in requests.iter() {
+        let (mut abilities, mut stats) = match casters.get_mut(request.caster) {
+            Ok(caster) => caster,
+            Err(_) => continue,
+        };
+        let distance = position(&transforms, request.caster)
+            .zip(target_position(&transforms, request.target))
+            .map(|(a, b)| a.distance(b));
+        if let Err(reason) = validate_cast(&abilities, &stats, request.ability, &defs, request.target, distance) {
+            failed.send(CastFailed {
+                caster: request.caster,
+                ability: request.ability,
+                reason,
+            });
+            continue;
+        }
+        let def = &defs[request.ability];
+        for cost in &def.cost {
+            stats.consume_resource(*cost);
+        }
+        if let Some(known) = abilities.known.iter_mut().find(|known| known.ability == request.ability) {
+            known.cooldown = def.cooldown;
+        }
+        abilities.casting = Some(Cast {
+            ability: request.ability,
+            target: request.target,
+            remaining: def.cast_time,
+        });
+        started.send(CastStarted {
+            caster: request.caster,
+            ability: request.ability,
+        });
+    }
+}
+
+fn finish_casts(
+    time: Res<Time>,
+    defs: Res<Abilitys>,
+    effects: Res<Effects>,
+    mut casters: Query<(Entity, &mut Abilities)>,
+    mut stats: Query<&mut Stats>,
+    mut buffs: Query<&mut Buffs>,
+    transforms: Query<&GlobalTransform>,
+    index: Res<SpatialIndex>,
+    mut apply_effect: EventWriter<ApplyEffect>,
+    mut attacks: EventWriter<Attack>,
+    mut used: EventWriter<AbilityUsed>,
+) {
+    let delta = time.delta_seconds();
+    let mut done = vec![];
+    for (caster, mut abilities) in casters.iter_mut() {
+        if let Some(cast) = abilities.tick(delta) {
+            done.push((caster, cast));
+        }
+    }
+
+    for (caster, cast) in done {
+        let def = &defs[cast.ability];
+        let targets: Vec<Entity> = match def.targeting {
+            Targeting::Caster => vec![caster],
+            Targeting::Single => match cast.target {
+                CastTarget::Entity(target) => vec![target],
+                _ => vec![],
+            },
+            Targeting::Area { radius } => {
+                let center = target_position(&transforms, cast.target).or_else(|| position(&transforms, caster));
+                match center {
+                    Some(center) => index.within(center, radius).into_iter().map(|(e, _)| e).collect(),
+                    None => vec![],
+                }
+            }
+        };
+
+        for effect in &def.effects {
+            match effect {
+                AbilityEffect::Damage(dmg) => {
+                    for target in &targets {
+                        attacks.send(Attack {
+                            attacker: Some(caster),
+                            target: *target,
+                            dmg: dmg.clone(),
+                            source: DamageSource::Ability(cast.ability),
+                        });
+                    }
+                }
+                AbilityEffect::Effect { effect, strength, duration } => {
+                    let effect = match effects.get(effect) {
+                        Some(effect) => effect,
+                        None => continue,
+                    };
+                    for target in &targets {
+                        apply_effect.send(ApplyEffect {
+                            target: *target,
+                            source: Some(caster),
+                            effect,
+                            strength: *strength,
+                            duration: *duration,
+                        });
+                    }
+                }
+                AbilityEffect::Buff { gain, duration } => {
+                    for target in &targets {
+                        if let Ok(mut target_stats) = stats.get_mut(*target) {
+                            add_buff(&mut buffs, &mut target_stats, *target, gain, *duration);
+                        }
+                    }
+                }
+            }
+        }
+
+        used.send(AbilityUsed {
+            caster,
+            ability: cast.ability,
+            targets,
+        });
+    }
+}
+
+// Buffs are added to an entity through its existing `Buffs`, systems inserting their own would replace each other's.
+fn add_buffs(mut commands: Commands, query: Query<Entity, (Added<Stats>, Without<Buffs>)>) {
+    for entity in query.iter() {
+        commands.entity(entity).insert(Buffs::default());
+    }
+}
+
+fn update_buffs(time: Res<Time>, mut query: Query<(&mut Buffs, &mut Stats)>) {
+    let delta = time.delta_seconds();
+    for (mut buffs, mut stats) in query.iter_mut() {
+        for (gain, remaining) in buffs.buffs.iter_mut() {
+            *remaining -= delta;
+            if *remaining <= 0.0 {
+                stats.remove_gain(gain);
+            }
+        }
+        buffs.buffs.retain(|(_, remaining)| *remaining > 0.0);
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_event::<CastAbility>()
+        .add_event::<CastStarted>()
+        .add_event::<CastFailed>()
+        .add_event::<AbilityUsed>()
+        .add_system(start_casts.system().label("start_casts"))
+        .add_system(finish_casts.system().label("finish_casts").after("start_casts"))
+        .add_system(add_buffs.system())
+        .add_system(update_buffs.system());
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::defs::{Effect, Species};
+use crate::stats::{ActiveEffects, ApplyEffect, SpatialIndex, StatGain, Stats};
+
+/// Seconds between the times auras look for entities in range.
+pub const AURA_PULSE: f32 = 0.25;
+// Effects from auras last this long, so they wear off shortly after leaving the range.
+const AURA_EFFECT_DURATION: f32 = AURA_PULSE * 2.0;
+
+/// Which side an entity is on. Entities without one are only allies of themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Faction(pub u32);
+
+/// Who an aura affects, the owner of the aura counts as its own ally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuraFilter {
+    All,
+    Allies,
+    Enemies,
+    Species(Species),
+}
+
+#[derive(Debug, Clone)]
+pub enum AuraEffect {
+    // Added when an entity comes in range and removed when it leaves.
+    Gain(StatGain),
+    // Applied when an entity comes in range, the instance from the aura is refreshed every pulse while it stays.
+    Effect(Effect),
+}
+
+/// What happens when an entity is in range of several auras with the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuraStacking {
+    // Every aura applies.
+    Stack,
+    // Only the one with the highest strength applies.
+    Strongest,
+}
+
+#[derive(Debug, Clone)]
+pub struct Aura {
+    // Auras of an entity are told apart by name, and stack by it.
+    pub name: String,
+    pub radius: f32,
+    pub filter: AuraFilter,
+    pub effect: AuraEffect,
+    // Gains are scaled by it as with effects, and effects are applied with it.
+    pub strength: f32,
+    pub stacking: AuraStacking,
+}
+
+/// The auras an entity gives off.
+#[derive(Default)]
+pub struct Auras {
+    auras: Vec<Aura>,
+}
+
+impl Auras {
+    /// Adds the aura, replacing the one with the same name.
+    pub fn add(&mut self, aura: Aura) {
+        self.remove(&aura.name);
+        self.auras.push(aura);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.auras.retain(|aura| aura.name != name);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Aura> + '_ {
+        self.auras.iter()
+    }
+}
+
+// An aura of a specific entity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct AuraKey {
+    owner: Entity,
+    name: String,
+}
+
+/// Gains an entity has from the auras it is in range of. Not saved, see `saved_stats`.
+#[derive(Default)]
+pub struct AuraGains {
+    // Scaled by the strength of the aura.
+    gains: Vec<(AuraKey, StatGain)>,
+}
+
+impl AuraGains {
+    /// The stats to save, without the gains of auras. Auras are found by entity, which isn't kept over a reload, and
+    /// add their gains again on the next pulse once the entity is in range.
+    pub fn saved_stats(&self, stats: &Stats) -> Stats {
+        let mut saved = stats.clone();
+        for (_, gain) in &self.gains {
+            saved.remove_gain(gain);
+        }
+        saved
+    }
+
+    /// Owners of the auras affecting the entity.
+    pub fn sources(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.gains.iter().map(|(key, _)| key.owner)
+    }
+}
+
+#[derive(Default)]
+struct AuraPulse {
+    until_pulse: f32,
+}
+
+fn affects(filter: AuraFilter, owner: Entity, target: Entity, factions: &Query<&Faction>, species: &Query<&Species>) -> bool {
+    let allies = owner == target
+        || matches!((factions.get(owner), factions.get(target)), (Ok(a), Ok(b)) if a == b);
+    match filter {
+        AuraFilter::All => true,
+        AuraFilter::Allies => allies,
+        AuraFilter::Enemies => !allies,
+        AuraFilter::Species(s) => species.get(target).map(|t| *t == s).unwrap_or(false),
+    }
+}
+
+struct InRange<'a> {
+    key: AuraKey,
+    aura: &'a Aura,
+}
+
+fn update_auras(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pulse: Local<AuraPulse>,
+    index: Res<SpatialIndex>,
+    sources: Query<(Entity, &Auras, &GlobalTransform)>,
+    factions: Query<&Faction>,
+    species: Query<&Species>,
+    mut targets: Query<(Entity, &mut Stats, Option<&mut AuraGains>)>,
+    mut active_effects: Query<&mut ActiveEffects>,
+    mut apply_effect: EventWriter<ApplyEffect>,
+) {
+    pulse.until_pulse -= time.delta_seconds();
+    if pulse.until_pulse > 0.0 {
+        return;
+    }
+    pulse.until_pulse += AURA_PULSE;
+
+    let mut in_range: HashMap<Entity, Vec<InRange>> = HashMap::new();
+    for (owner, auras, transform) in sources.iter() {
+        for aura in auras.iter() {
+            for (target, _) in index.within(transform.translation, aura.radius) {
+                if affects(aura.filter, owner, target, &factions, &species) {
+                    in_range.entry(target).or_default().push(InRange {
+                        key: AuraKey {
+                            owner,
+                            name: aura.name.clone(),
+                        },
+                        aura,
+                    });
+                }
+            }
+        }
+    }
+
+    for auras in in_range.values_mut() {
+        // Strongest first, ties go to the oldest entity so the same aura wins every pulse.
+        auras.sort_by(|a, b| {
+            b.aura
+                .strength
+                .partial_cmp(&a.aura.strength)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.key.owner.cmp(&b.key.owner))
+        });
+        let mut seen: Vec<String> = vec![];
+        auras.retain(|in_range| {
+            if in_range.aura.stacking == AuraStacking::Stack {
+                return true;
+            }
+            if seen.contains(&in_range.key.name) {
+                false
+            } else {
+                seen.push(in_range.key.name.clone());
+                true
+            }
+        });
+    }
+
+    let mut new_gains = HashMap::new();
+    for (target, mut stats, gains) in targets.iter_mut() {
+        let auras = in_range.remove(&target).unwrap_or_default();
+        for in_range in &auras {
+            if let AuraEffect::Effect(effect) = &in_range.aura.effect {
+                let refreshed = match active_effects.get_mut(target) {
+                    Ok(mut effects) => effects.refresh(*effect, in_range.key.owner, AURA_EFFECT_DURATION),
+                    Err(_) => false,
+                };
+                if refreshed {
+                    continue;
+                }
+                apply_effect.send(ApplyEffect {
+                    target,
+                    source: Some(in_range.key.owner),
+                    effect: *effect,
+                    strength: in_range.aura.strength,
+                    duration: AURA_EFFECT_DURATION,
+                });
+            }
+        }
+        let wanted = || {
+            auras.iter().filter_map(|in_range| match &in_range.aura.effect {
+                AuraEffect::Gain(gain) => Some((&in_range.key, in_range.aura.strength, gain)),
+                AuraEffect::Effect(_) => None,
+            })
+        };
+
+        let mut gains = match gains {
+            Some(gains) => gains,
+            None => {
+                let mut added = AuraGains::default();
+                for (key, strength, gain) in wanted() {
+                    let gain = gain.scaled(strength);
+                    stats.add_gain(&gain);
+                    added.gains.push((key.clone(), gain));
+                }
+                if !added.gains.is_empty() {
+                    new_gains.insert(target, added);
+                }
+                continue;
+            }
+        };
+        // Gains of auras that are out of range or have changed are taken away before adding the new ones.
+        gains.gains.retain(|(key, gain)| {
+            let keep = wanted().any(|(k, s, g)| k == key && g.scaled(s) == *gain);
+            if !keep {
+                stats.remove_gain(gain);
+            }
+            keep
+        });
+        for (key, strength, gain) in wanted() {
+            if !gains.gains.iter().any(|(k, _)| k == key) {
+                let gain = gain.scaled(strength);
+                stats.add_gain(&gain);
+                gains.gains.push((key.clone(), gain));
+            }
+        }
+    }
+    for (entity, gains) in new_gains {
+        commands.entity(entity).insert(gains);
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_system(update_auras.system().label("update_auras"));
+}
@@ -6,7 +6,7 @@ use crate::count_idents;
 // Takes a parameter pack of Full name as identifier, short version of name, bool if stat is increased with level-up
 macro_rules! base_stats {
     ($($name:ident $short:literal $can_level:literal), *,) => {
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
         pub enum BaseStat {
             $($name), *
         }
@@ -58,6 +58,7 @@ base_stats! {
 }
 
 
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub struct BaseStats {
     data: [f32; NUM_BASE_STATS],
 }
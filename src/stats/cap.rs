@@ -0,0 +1,213 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{DamageTypes, Definition, Message, StatCapOverrides};
+use crate::stats::stat::{distribution, STAT_ITER};
+use crate::stats::{DmgType, Stat, Stats};
+
+/// Curve applied to the part of a value that goes above `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SoftCap {
+    // The excess approaches `half * 2` but never reaches it, at `half` excess half of it is kept.
+    Distribution { start: f32, half: f32 },
+    // The excess is scaled by `factor`.
+    Linear { start: f32, factor: f32 },
+}
+
+impl SoftCap {
+    pub fn apply(&self, value: f32) -> f32 {
+        match *self {
+            Self::Distribution { start, half } => {
+                if value > start {
+                    let excess = value - start;
+                    start + excess * distribution(excess, half)
+                } else {
+                    value
+                }
+            }
+            Self::Linear { start, factor } => {
+                if value > start {
+                    start + (value - start) * factor
+                } else {
+                    value
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatCap {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    pub soft: Option<SoftCap>,
+}
+
+impl StatCap {
+    pub const fn none() -> Self {
+        Self {
+            min: None,
+            max: None,
+            soft: None,
+        }
+    }
+
+    pub const fn with_min(mut self, min: f32) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    pub const fn with_max(mut self, max: f32) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    pub const fn with_soft(mut self, soft: SoftCap) -> Self {
+        self.soft = Some(soft);
+        self
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.min.is_none() && self.max.is_none() && self.soft.is_none()
+    }
+
+    /// Soft cap is applied first, then the value is clamped to the hard caps.
+    pub fn apply(&self, value: f32) -> f32 {
+        let mut value = match self.soft {
+            Some(soft) => soft.apply(value),
+            None => value,
+        };
+        if let Some(min) = self.min {
+            value = value.max(min);
+        }
+        if let Some(max) = self.max {
+            value = value.min(max);
+        }
+        value
+    }
+
+    fn merge(&mut self, min: Option<f32>, max: Option<f32>, soft: Option<SoftCap>) {
+        self.min = min.or(self.min);
+        self.max = max.or(self.max);
+        self.soft = soft.or(self.soft);
+    }
+}
+
+impl Display for StatCap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_none() {
+            return write!(f, "uncapped");
+        }
+        let mut first = true;
+        let mut sep = |f: &mut std::fmt::Formatter<'_>| {
+            let r = if first { Ok(()) } else { write!(f, ", ") };
+            first = false;
+            r
+        };
+        if let Some(min) = self.min {
+            sep(f)?;
+            write!(f, "min {}", min)?;
+        }
+        if let Some(max) = self.max {
+            sep(f)?;
+            write!(f, "max {}", max)?;
+        }
+        match self.soft {
+            Some(SoftCap::Distribution { start, half }) => {
+                sep(f)?;
+                write!(f, "soft cap from {} (half {})", start, half)?;
+            }
+            Some(SoftCap::Linear { start, factor }) => {
+                sep(f)?;
+                write!(f, "soft cap from {} (x{})", start, factor)?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Caps for every stat and damage type, starting from the ones declared in `stats!` and `DmgType::default_cap`.
+#[derive(Debug, Clone)]
+pub struct CapTable {
+    stats: Vec<StatCap>,
+    // Indexed by damage type, only as long as the last overridden type.
+    dmgs: Vec<StatCap>,
+}
+
+impl Default for CapTable {
+    fn default() -> Self {
+        Self {
+            stats: STAT_ITER.iter().map(|stat| stat.default_cap()).collect(),
+            dmgs: vec![],
+        }
+    }
+}
+
+impl CapTable {
+    pub fn stat(&self, stat: Stat) -> StatCap {
+        self.stats[stat as usize]
+    }
+
+    pub fn dmg(&self, ty: DmgType) -> StatCap {
+        self.dmgs.get(ty.index()).copied().unwrap_or_else(|| ty.default_cap())
+    }
+
+    fn dmg_mut(&mut self, ty: DmgType) -> &mut StatCap {
+        if self.dmgs.len() <= ty.index() {
+            // The default cap is the same for every type.
+            self.dmgs.resize(ty.index() + 1, ty.default_cap());
+        }
+        &mut self.dmgs[ty.index()]
+    }
+}
+
+/// Caps given to new `Stats`, `None` if no mod overrides the declared caps.
+#[derive(Default)]
+pub struct StatCaps(pub Option<Arc<CapTable>>);
+
+fn build_stat_caps(
+    mut commands: Commands,
+    overrides: Res<StatCapOverrides>,
+    dmg_types: Res<DamageTypes>,
+    mut printer: EventWriter<Message>,
+) {
+    let mut table = CapTable::default();
+    let mut changed = false;
+    for cap in overrides.iter() {
+        let name = serde_json::Value::String(cap.target.clone());
+        // Only looked up, a stat name would otherwise be registered as a damage type.
+        let ty = || DmgType::find(&cap.target).filter(|ty| dmg_types.get(&ty.id()).is_some());
+        if let Ok(stat) = serde_json::from_value::<Stat>(name) {
+            table.stats[stat as usize].merge(cap.min, cap.max, cap.soft);
+            changed = true;
+        } else if let Some(ty) = ty() {
+            table.dmg_mut(ty).merge(cap.min, cap.max, cap.soft);
+            changed = true;
+        } else {
+            printer.send(Message::error(format!(
+                "Cap override {} targets unknown stat or damage type {}.",
+                cap.get_string_id(),
+                cap.target
+            )));
+        }
+    }
+    commands.insert_resource(StatCaps(if changed { Some(Arc::new(table)) } else { None }));
+}
+
+fn apply_stat_caps(caps: Res<StatCaps>, mut query: Query<&mut Stats, Added<Stats>>) {
+    if let Some(caps) = &caps.0 {
+        for mut stats in query.iter_mut() {
+            stats.set_caps(Some(caps.clone()));
+        }
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.init_resource::<StatCaps>()
+        .add_startup_system_to_stage("generate", build_stat_caps.system())
+        .add_system_to_stage(CoreStage::PreUpdate, apply_stat_caps.system());
+}
@@ -0,0 +1,166 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::stats::stat::distribution;
+use crate::stats::{BaseStat, CombatRng, StatKey, Stats};
+
+/// The roll added to the score of a check is uniform in `[0, CHECK_DIE)`.
+pub const CHECK_DIE: f32 = 20.0;
+/// Points above or below the difficulty for every degree of success or failure.
+pub const DEGREE_STEP: f32 = 5.0;
+// Karma is added to the roll times this, so bad karma makes every check harder.
+const KARMA_FACTOR: f32 = 0.1;
+// Luck at which there is half a chance of rolling twice and keeping the better roll.
+const LUCK_HALF: f32 = 50.0;
+
+/// What a check is rolled with, the score is the sum of each stat times its weight.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CheckFormula {
+    pub terms: Vec<(StatKey, f32)>,
+}
+
+impl CheckFormula {
+    pub fn new(terms: Vec<(StatKey, f32)>) -> Self {
+        Self { terms }
+    }
+}
+
+/// How much a stat added to a check.
+#[derive(Debug, Clone, Copy)]
+pub struct CheckTerm {
+    pub stat: StatKey,
+    pub value: f32,
+    pub weight: f32,
+}
+
+impl CheckTerm {
+    pub fn contribution(&self) -> f32 {
+        self.value * self.weight
+    }
+}
+
+/// Everything that went into a check, `total` is `score + roll + karma`.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub terms: Vec<CheckTerm>,
+    pub score: f32,
+    pub roll: f32,
+    // Luck gave a second roll and the better one was kept.
+    pub lucky: bool,
+    pub karma: f32,
+    pub total: f32,
+    pub difficulty: f32,
+    // Zero or more is a success, every `DEGREE_STEP` above the difficulty is one more degree. Failures are negative,
+    // -1 for missing it by less than one step.
+    pub degrees: i32,
+}
+
+impl CheckResult {
+    pub fn success(&self) -> bool {
+        self.degrees >= 0
+    }
+
+    pub fn margin(&self) -> f32 {
+        self.total - self.difficulty
+    }
+
+    fn against(mut self, difficulty: f32) -> Self {
+        self.difficulty = difficulty;
+        self.degrees = (self.margin() / DEGREE_STEP).floor() as i32;
+        self
+    }
+}
+
+impl Display for CheckResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for term in &self.terms {
+            write!(f, "{} {} x{} + ", term.stat, term.value, term.weight)?;
+        }
+        write!(f, "roll {}", self.roll)?;
+        if self.lucky {
+            write!(f, " (lucky)")?;
+        }
+        if self.karma != 0.0 {
+            write!(f, " + karma {}", self.karma)?;
+        }
+        write!(f, " = {} vs {}: ", self.total, self.difficulty)?;
+        if self.success() {
+            write!(f, "success by {} degrees", self.degrees)
+        } else {
+            write!(f, "failure by {} degrees", -self.degrees)
+        }
+    }
+}
+
+fn roll(stats: &Stats, formula: &CheckFormula, rng: &mut dyn CombatRng) -> CheckResult {
+    let terms: Vec<CheckTerm> = formula
+        .terms
+        .iter()
+        .map(|(stat, weight)| CheckTerm {
+            stat: *stat,
+            value: stats.get(*stat),
+            weight: *weight,
+        })
+        .collect();
+    let score = terms.iter().map(|term| term.contribution()).sum();
+    let luck = stats[BaseStat::Luck].max(0.0);
+    let mut roll = rng.roll() * CHECK_DIE;
+    let lucky = rng.chance(1.0 - distribution(luck, LUCK_HALF));
+    if lucky {
+        roll = roll.max(rng.roll() * CHECK_DIE);
+    }
+    let karma = stats[BaseStat::Karma] * KARMA_FACTOR;
+    CheckResult {
+        terms,
+        score,
+        roll,
+        lucky,
+        karma,
+        total: score + roll + karma,
+        difficulty: 0.0,
+        degrees: 0,
+    }
+}
+
+/// Rolls `formula` for `stats` against a fixed difficulty, e.g. picking a lock.
+pub fn check(stats: &Stats, formula: &CheckFormula, difficulty: f32, rng: &mut dyn CombatRng) -> CheckResult {
+    roll(stats, formula, rng).against(difficulty)
+}
+
+/// A check rolled against another check, e.g. sneaking past someone.
+#[derive(Debug, Clone)]
+pub struct ContestResult {
+    // The difficulty of the active side is the total of the passive one, and the other way around.
+    pub active: CheckResult,
+    pub passive: CheckResult,
+}
+
+impl ContestResult {
+    /// Ties go to the active side.
+    pub fn active_wins(&self) -> bool {
+        self.active.success()
+    }
+
+    pub fn degrees(&self) -> i32 {
+        self.active.degrees
+    }
+}
+
+/// Rolls a check for each side, the active side wins if its total meets the total of the passive side.
+pub fn contest(
+    active: &Stats,
+    active_formula: &CheckFormula,
+    passive: &Stats,
+    passive_formula: &CheckFormula,
+    rng: &mut dyn CombatRng,
+) -> ContestResult {
+    let a = roll(active, active_formula, rng);
+    let p = roll(passive, passive_formula, rng);
+    let (a_total, p_total) = (a.total, p.total);
+    ContestResult {
+        active: a.against(p_total),
+        passive: p.against(a_total),
+    }
+}
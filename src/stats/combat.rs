@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::defs::{Ability, Effect, Effects, Interaction, Interactions, Species, Speciess};
+use crate::stats::ability::add_buff;
+use crate::stats::{
+    interact, ActiveEffects, ApplyEffect, Buffs, CombatRandom, DamageReport, Dmg, DmgResult, DmgTable, InteractionOutcome, Reaction,
+    RemoveEffect, Resource, SpatialIndex, Stats, TriggerEvent, TriggerSource, TriggerTarget, Triggers, MAX_TRIGGER_DEPTH,
+};
+
+/// What caused a damage instance, the attacker is kept next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DamageSource {
+    // A plain attack, e.g. a weapon swing.
+    Direct,
+    Ability(Ability),
+    // A tick of an effect, the attacker is whoever applied the effect.
+    Effect(Effect),
+    // Damage from a reaction, e.g. reflected damage.
+    Reaction,
+    // Damage passed on by an elemental interaction, e.g. electricity chaining between wet targets.
+    Interaction(Interaction),
+    // Damage without an attacker, e.g. falling or the temperature.
+    Environment,
+}
+
+impl DamageSource {
+    /// Only direct hits and abilities are scaled by the attacker's stats, the rest is already calculated.
+    pub fn is_scaled(&self) -> bool {
+        matches!(self, Self::Direct | Self::Ability(_))
+    }
+}
+
+/// Asks for `dmg` to be dealt to `target`. The damage is scaled by the attacker's stats if the source allows it.
+pub struct Attack {
+    pub attacker: Option<Entity>,
+    pub target: Entity,
+    pub dmg: Dmg,
+    pub source: DamageSource,
+}
+
+// Sent for every attack that has been resolved, reactions like reflected damage included.
+pub struct DamageDealt {
+    pub attacker: Option<Entity>,
+    pub target: Entity,
+    pub source: DamageSource,
+    pub report: DamageReport,
+}
+
+struct PendingAttack {
+    attacker: Option<Entity>,
+    target: Entity,
+    dmg: Dmg,
+    source: DamageSource,
+    depth: u32,
+}
+
+struct Fired {
+    owner: Entity,
+    other: Option<Entity>,
+    reaction: Reaction,
+}
+
+fn fire(
+    triggers: &Query<&Triggers>,
+    rng: &mut CombatRandom,
+    owner: Entity,
+    other: Option<Entity>,
+    events: &[TriggerEvent],
+    fired: &mut Vec<Fired>,
+) {
+    if let Ok(triggers) = triggers.get(owner) {
+        for event in events {
+            for trigger in triggers.on(*event) {
+                if rng.rng().chance(trigger.chance) {
+                    fired.push(Fired {
+                        owner,
+                        other,
+                        reaction: trigger.reaction.clone(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+// Entities with stats within `radius` of `center`, closest first.
+fn nearby(
+    index: &SpatialIndex,
+    transforms: &Query<&GlobalTransform>,
+    center: Entity,
+    radius: f32,
+    exclude: &[Entity],
+) -> Vec<Entity> {
+    let center = match transforms.get(center) {
+        Ok(t) => t.translation,
+        Err(_) => return vec![],
+    };
+    let mut found: Vec<(Entity, f32)> = index
+        .within(center, radius)
+        .into_iter()
+        .filter(|(e, _)| !exclude.contains(e))
+        .collect();
+    found.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    found.into_iter().map(|(e, _)| e).collect()
+}
+
+fn resolve_attacks(
+    effects: Res<Effects>,
+    interactions: Res<Interactions>,
+    dmg_types: Res<DmgTable>,
+    mut rng: ResMut<CombatRandom>,
+    mut attacks: EventReader<Attack>,
+    mut stats: Query<&mut Stats>,
+    triggers: Query<&Triggers>,
+    mut buffs: Query<&mut Buffs>,
+    active_effects: Query<&ActiveEffects>,
+    index: Res<SpatialIndex>,
+    transforms: Query<&GlobalTransform>,
+    mut damage_dealt: EventWriter<DamageDealt>,
+    mut apply_effect: EventWriter<ApplyEffect>,
+    mut remove_effect: EventWriter<RemoveEffect>,
+) {
+    let mut queue: VecDeque<PendingAttack> = attacks
+        .iter()
+        .map(|attack| PendingAttack {
+            attacker: attack.attacker,
+            target: attack.target,
+            dmg: attack.dmg.clone(),
+            source: attack.source,
+            depth: 0,
+        })
+        .collect();
+
+    while let Some(attack) = queue.pop_front() {
+        let mut dmg = match attack.attacker.and_then(|attacker| stats.get_mut(attacker).ok()) {
+            Some(attacker) if attack.source.is_scaled() => attack.dmg.calculate_dealt(&attacker, &dmg_types, rng.rng()),
+            _ => attack.dmg,
+        };
+        let interacted = match active_effects.get(attack.target) {
+            Ok(target_effects) => interact(&interactions, &mut dmg, target_effects),
+            Err(_) => vec![],
+        };
+        let report = match stats.get_mut(attack.target) {
+            Ok(mut target) => target.apply_damage(&dmg, &dmg_types, rng.rng()),
+            Err(_) => continue,
+        };
+
+        if report.is_hit() {
+            for interacted in interacted {
+                for outcome in &interactions[interacted.interaction].outcomes {
+                    match outcome {
+                        InteractionOutcome::Consume => remove_effect.send(RemoveEffect {
+                            target: attack.target,
+                            effect: interacted.effect,
+                        }),
+                        InteractionOutcome::Apply { effect, strength, duration } => {
+                            if let Some(effect) = effects.get(effect) {
+                                apply_effect.send(ApplyEffect {
+                                    target: attack.target,
+                                    source: attack.attacker,
+                                    effect,
+                                    strength: *strength,
+                                    duration: *duration,
+                                });
+                            }
+                        }
+                        // Chains could bounce between the same entities forever without the limit.
+                        InteractionOutcome::Chain { radius, percent, max_targets } if attack.depth < MAX_TRIGGER_DEPTH => {
+                            let exclude: Vec<Entity> = attack.attacker.into_iter().chain(Some(attack.target)).collect();
+                            for target in nearby(&index, &transforms, attack.target, *radius, &exclude)
+                                .into_iter()
+                                .take(*max_targets as usize)
+                            {
+                                queue.push_back(PendingAttack {
+                                    attacker: attack.attacker,
+                                    target,
+                                    dmg: Dmg::create(
+                                        interactions[interacted.interaction]
+                                            .damage_types
+                                            .iter()
+                                            .map(|ty| (*ty, dmg.get(*ty) * percent))
+                                            .collect(),
+                                    ),
+                                    source: DamageSource::Interaction(interacted.interaction),
+                                    depth: attack.depth + 1,
+                                });
+                            }
+                        }
+                        InteractionOutcome::Spread { radius, percent, duration } => {
+                            let exclude: Vec<Entity> = attack.attacker.into_iter().chain(Some(attack.target)).collect();
+                            for target in nearby(&index, &transforms, attack.target, *radius, &exclude) {
+                                apply_effect.send(ApplyEffect {
+                                    target,
+                                    source: attack.attacker,
+                                    effect: interacted.effect,
+                                    strength: interacted.strength * percent,
+                                    duration: *duration,
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // Reactions can cause new attacks, which could go back and forth forever without a limit.
+        if attack.depth < MAX_TRIGGER_DEPTH {
+            let mut fired = vec![];
+            if let Some(attacker) = attack.attacker {
+                let mut events = vec![];
+                if report.is_hit() {
+                    events.push(TriggerEvent::OnHit);
+                    if report.crit {
+                        events.push(TriggerEvent::OnCrit);
+                    }
+                }
+                if report.lethal {
+                    events.push(TriggerEvent::OnKill);
+                }
+                fire(&triggers, &mut rng, attacker, Some(attack.target), &events, &mut fired);
+            }
+            let mut events = vec![];
+            if report.is_hit() && report.total > 0.0 {
+                events.push(TriggerEvent::OnDamaged);
+            }
+            if report.result == DmgResult::Dodge {
+                events.push(TriggerEvent::OnDodge);
+            }
+            fire(&triggers, &mut rng, attack.target, attack.attacker, &events, &mut fired);
+
+            for Fired { owner, other, reaction } in fired {
+                let target = |target: TriggerTarget| match target {
+                    TriggerTarget::Owner => Some(owner),
+                    TriggerTarget::Other => other,
+                };
+                match reaction {
+                    Reaction::Heal { percent } => {
+                        if let Ok(mut stats) = stats.get_mut(owner) {
+                            stats.gain_resource(Resource::HP, report.total * percent);
+                        }
+                    }
+                    Reaction::Reflect { percent } => {
+                        if let Some(other) = other {
+                            queue.push_back(PendingAttack {
+                                attacker: Some(owner),
+                                target: other,
+                                dmg: Dmg::create(
+                                    report.amounts.iter().map(|amount| (amount.ty, amount.taken * percent)).collect(),
+                                ),
+                                source: DamageSource::Reaction,
+                                depth: attack.depth + 1,
+                            });
+                        }
+                    }
+                    Reaction::ApplyEffect { effect, strength, duration, target: t } => {
+                        if let (Some(target), Some(effect)) = (target(t), effects.get(&effect)) {
+                            apply_effect.send(ApplyEffect {
+                                target,
+                                source: Some(owner),
+                                effect,
+                                strength,
+                                duration,
+                            });
+                        }
+                    }
+                    Reaction::Buff { gain, duration, target: t } => {
+                        if let Some(target) = target(t) {
+                            if let Ok(mut stats) = stats.get_mut(target) {
+                                add_buff(&mut buffs, &mut stats, target, &gain, duration);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        damage_dealt.send(DamageDealt {
+            attacker: attack.attacker,
+            target: attack.target,
+            source: attack.source,
+            report,
+        });
+    }
+}
+
+// Spawned entities get the triggers of their species.
+fn add_species_triggers(
+    mut commands: Commands,
+    defs: Res<Speciess>,
+    mut query: Query<(Entity, &Species, Option<&mut Triggers>), Added<Species>>,
+) {
+    for (entity, species, triggers) in query.iter_mut() {
+        let source = TriggerSource::Species(*species);
+        match triggers {
+            Some(mut triggers) => triggers.add_all(source, &defs[*species].triggers),
+            None => {
+                let mut triggers = Triggers::default();
+                triggers.add_all(source, &defs[*species].triggers);
+                commands.entity(entity).insert(triggers);
+            }
+        }
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_event::<Attack>()
+        .add_event::<DamageDealt>()
+        .add_system(add_species_triggers.system())
+        .add_system(resolve_attacks.system().label("resolve_attacks").after("finish_casts"));
+}
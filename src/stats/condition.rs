@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{Blocks, Tool, Tools};
+use crate::stats::{Environment, ResourceChanged, ResourceKey, StatAccessor, StatChanged, StatGain, StatKey, Stats, TriggerSource};
+
+/// What an entity holds and carries, for conditions on equipment.
+#[derive(Debug, Clone, Default)]
+pub struct Equipment {
+    pub held: Vec<Tool>,
+    // Weight carried and how much can be carried.
+    pub load: f32,
+    pub capacity: f32,
+}
+
+/// Something that has to be true for a conditional gain to apply. Blocks and tools are given by `namespace:id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Condition {
+    // Percentages are of the max of the resource.
+    ResourceBelow { resource: ResourceKey, percent: f32 },
+    ResourceAbove { resource: ResourceKey, percent: f32 },
+    StatBelow { stat: StatKey, value: f32 },
+    StatAbove { stat: StatKey, value: f32 },
+    // Standing on a block of the type.
+    OnBlock(String),
+    // Hours since midnight, `from` can be later than `to` for times that go past midnight.
+    TimeOfDay { from: f32, to: f32 },
+    // Temperature of the surroundings, not of the body.
+    TemperatureBelow(f32),
+    TemperatureAbove(f32),
+    Holding(String),
+    // Percentage of the carrying capacity.
+    LoadBelow(f32),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+/// Everything a condition can look at.
+pub struct ConditionContext<'a> {
+    pub stats: &'a Stats,
+    // Entities without one are taken to stand nowhere at noon in a comfortable temperature.
+    pub environment: Option<&'a Environment>,
+    pub equipment: Option<&'a Equipment>,
+    pub blocks: &'a Blocks,
+    pub tools: &'a Tools,
+}
+
+impl Condition {
+    pub fn check(&self, context: &ConditionContext) -> bool {
+        let environment = context.environment.cloned().unwrap_or_default();
+        let resource = |resource: ResourceKey| {
+            let max = resource.max(context.stats);
+            if max > 0.0 {
+                context.stats[resource] / max
+            } else {
+                0.0
+            }
+        };
+        match self {
+            Self::ResourceBelow { resource: r, percent } => context.stats.has_resource(*r) && resource(*r) < *percent,
+            Self::ResourceAbove { resource: r, percent } => context.stats.has_resource(*r) && resource(*r) > *percent,
+            Self::StatBelow { stat, value } => stat.get_value(context.stats) < *value,
+            Self::StatAbove { stat, value } => stat.get_value(context.stats) > *value,
+            Self::OnBlock(block) => environment.block_below.is_some() && environment.block_below == context.blocks.get(block),
+            Self::TimeOfDay { from, to } => {
+                let time = environment.time_of_day;
+                if from <= to {
+                    *from <= time && time < *to
+                } else {
+                    time >= *from || time < *to
+                }
+            }
+            Self::TemperatureBelow(temperature) => environment.temperature < *temperature,
+            Self::TemperatureAbove(temperature) => environment.temperature > *temperature,
+            Self::Holding(tool) => match (context.equipment, context.tools.get(tool)) {
+                (Some(equipment), Some(tool)) => equipment.held.contains(&tool),
+                _ => false,
+            },
+            Self::LoadBelow(percent) => match context.equipment {
+                Some(equipment) if equipment.capacity > 0.0 => equipment.load / equipment.capacity < *percent,
+                // Nothing carried is below any load.
+                _ => true,
+            },
+            Self::All(conditions) => conditions.iter().all(|condition| condition.check(context)),
+            Self::Any(conditions) => conditions.iter().any(|condition| condition.check(context)),
+            Self::Not(condition) => !condition.check(context),
+        }
+    }
+}
+
+/// A gain that is only added while its condition holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionalGain {
+    pub when: Condition,
+    pub gain: StatGain,
+}
+
+/// The conditional gains of an entity, checked again whenever its stats, environment or equipment change.
+#[derive(Default)]
+pub struct ConditionalGains {
+    // Whether the gain is currently added to the stats.
+    gains: Vec<(TriggerSource, ConditionalGain, bool)>,
+}
+
+impl ConditionalGains {
+    pub fn add(&mut self, source: TriggerSource, gain: ConditionalGain) {
+        self.gains.push((source, gain, false));
+    }
+
+    /// Removes every gain given by `source`, taking away the ones that are active.
+    pub fn remove(&mut self, stats: &mut Stats, source: TriggerSource) {
+        for (s, conditional, active) in &self.gains {
+            if *s == source && *active {
+                stats.remove_gain(&conditional.gain);
+            }
+        }
+        self.gains.retain(|(s, _, _)| *s != source);
+    }
+
+    pub fn active(&self) -> impl Iterator<Item = &ConditionalGain> + '_ {
+        self.gains.iter().filter(|(_, _, active)| *active).map(|(_, gain, _)| gain)
+    }
+}
+
+fn update_conditional_gains(
+    blocks: Res<Blocks>,
+    tools: Res<Tools>,
+    mut stat_changed: EventReader<StatChanged>,
+    mut resource_changed: EventReader<ResourceChanged>,
+    changed: Query<
+        Entity,
+        (
+            With<ConditionalGains>,
+            Or<(Changed<ConditionalGains>, Changed<Environment>, Changed<Equipment>)>,
+        ),
+    >,
+    mut query: Query<(&mut ConditionalGains, &mut Stats, Option<&Environment>, Option<&Equipment>)>,
+) {
+    let entities: HashSet<Entity> = changed
+        .iter()
+        .chain(stat_changed.iter().map(|event| event.entity))
+        .chain(resource_changed.iter().map(|event| event.entity))
+        .collect();
+    for entity in entities {
+        let (mut gains, mut stats, environment, equipment) = match query.get_mut(entity) {
+            Ok(entity) => entity,
+            Err(_) => continue,
+        };
+        // Checked against the stats from before any of them change, so one gain can't turn another on or off.
+        let checked: Vec<bool> = {
+            let context = ConditionContext {
+                stats: &stats,
+                environment,
+                equipment,
+                blocks: &blocks,
+                tools: &tools,
+            };
+            gains.gains.iter().map(|(_, conditional, _)| conditional.when.check(&context)).collect()
+        };
+        // Only touched when something turns on or off, so unchanged gains aren't checked again next frame.
+        if gains.gains.iter().zip(&checked).all(|((_, _, active), check)| active == check) {
+            continue;
+        }
+        for ((_, conditional, active), check) in gains.gains.iter_mut().zip(checked) {
+            if *active != check {
+                if check {
+                    stats.add_gain(&conditional.gain);
+                } else {
+                    stats.remove_gain(&conditional.gain);
+                }
+                *active = check;
+            }
+        }
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_system_to_stage(
+        CoreStage::PostUpdate,
+        update_conditional_gains.system().after("send_stat_events"),
+    );
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::stats::{DamageDealt, DamageReport, DamageSource, GainXp, Stats, XpSource};
+
+/// Seconds damage is remembered for, anyone who dealt damage within it gets an assist on a kill.
+pub const ASSIST_WINDOW: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DamageRecord {
+    pub attacker: Option<Entity>,
+    pub source: DamageSource,
+    pub amount: f32,
+    // Seconds since the damage was dealt.
+    pub age: f32,
+}
+
+/// The damage an entity has taken within the assist window.
+#[derive(Debug, Clone, Default)]
+pub struct DamageHistory {
+    records: Vec<DamageRecord>,
+}
+
+impl DamageHistory {
+    pub fn iter(&self) -> impl Iterator<Item = &DamageRecord> + '_ {
+        self.records.iter()
+    }
+
+    pub fn record(&mut self, attacker: Option<Entity>, source: DamageSource, amount: f32) {
+        self.records.push(DamageRecord {
+            attacker,
+            source,
+            amount,
+            age: 0.0,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    /// Damage dealt by each attacker within the window, most damage first.
+    pub fn contributors(&self) -> Vec<(Entity, f32)> {
+        let mut totals: HashMap<Entity, f32> = HashMap::new();
+        for record in &self.records {
+            if let Some(attacker) = record.attacker {
+                *totals.entry(attacker).or_default() += record.amount;
+            }
+        }
+        let mut totals: Vec<(Entity, f32)> = totals.into_iter().collect();
+        totals.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        totals
+    }
+
+    fn tick(&mut self, delta: f32) {
+        for record in self.records.iter_mut() {
+            record.age += delta;
+        }
+        self.records.retain(|record| record.age <= ASSIST_WINDOW);
+    }
+}
+
+// Sent when an entity is brought to zero HP. The killer is whoever dealt the final blow, or the one that dealt the most
+// damage within the window if the final blow had no attacker, e.g. a fall.
+pub struct Killed {
+    pub victim: Entity,
+    pub killer: Option<Entity>,
+    pub assists: Vec<Entity>,
+    // Source and report of the final blow.
+    pub source: DamageSource,
+    pub report: DamageReport,
+}
+
+fn record_damage(
+    mut commands: Commands,
+    mut dealt: EventReader<DamageDealt>,
+    mut histories: Query<&mut DamageHistory>,
+    mut killed: EventWriter<Killed>,
+) {
+    let mut new_histories: HashMap<Entity, DamageHistory> = HashMap::new();
+    for event in dealt.iter() {
+        if !event.report.is_hit() || event.report.total <= 0.0 {
+            continue;
+        }
+        let mut history = histories.get_mut(event.target).ok();
+        let history = match &mut history {
+            Some(history) => &mut **history,
+            None => new_histories.entry(event.target).or_default(),
+        };
+        // Overkill doesn't count towards credit.
+        history.record(event.attacker, event.source, event.report.total - event.report.overkill);
+
+        if event.report.lethal {
+            let contributors = history.contributors();
+            let killer = event.attacker.or_else(|| contributors.first().map(|(e, _)| *e));
+            killed.send(Killed {
+                victim: event.target,
+                killer,
+                assists: contributors
+                    .into_iter()
+                    .map(|(e, _)| e)
+                    .filter(|e| Some(*e) != killer && *e != event.target)
+                    .collect(),
+                source: event.source,
+                report: event.report.clone(),
+            });
+            history.clear();
+        }
+    }
+    for (entity, history) in new_histories {
+        commands.entity(entity).insert(history);
+    }
+}
+
+fn update_histories(time: Res<Time>, mut query: Query<&mut DamageHistory>) {
+    let delta = time.delta_seconds();
+    for mut history in query.iter_mut() {
+        history.tick(delta);
+    }
+}
+
+fn award_kills(mut killed: EventReader<Killed>, stats: Query<&Stats>, mut xp: EventWriter<GainXp>) {
+    for event in killed.iter() {
+        let victim_level = stats.get(event.victim).map(|s| s.level().level()).unwrap_or(0);
+        if let Some(killer) = event.killer {
+            // Killing yourself isn't worth anything.
+            if killer != event.victim {
+                xp.send(GainXp {
+                    entity: killer,
+                    source: XpSource::Kill { victim_level },
+                });
+            }
+        }
+        for assist in &event.assists {
+            xp.send(GainXp {
+                entity: *assist,
+                source: XpSource::Assist { victim_level },
+            });
+        }
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_event::<Killed>()
+        .add_system(record_damage.system().label("record_damage").after("resolve_attacks"))
+        .add_system(update_histories.system().after("record_damage"))
+        .add_system(award_kills.system().after("record_damage"));
+}
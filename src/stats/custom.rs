@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
+use bevy::prelude::*;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::defs::{CustomResources, CustomStats, Definition, Message};
+use crate::stats::{BaseStat, Resource, Stat, StatAccessor, StatCap, Stats};
+
+/// Names registered while the game runs, the index of a name is the id of whatever it names. The names are global as
+/// definitions refer to them while they are deserialized, before any resource exists. What a name stands for is still
+/// only given by its definition, names that no definition registers are reported once the definitions are loaded.
+pub(crate) struct Names(RwLock<Vec<String>>);
+
+impl Names {
+    pub const fn new() -> Self {
+        Self(RwLock::new(Vec::new()))
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.0.read().unwrap().iter().position(|n| n == name)
+    }
+
+    /// Finds the name, or registers it if it is new.
+    pub fn intern(&self, name: &str) -> usize {
+        if let Some(i) = self.find(name) {
+            return i;
+        }
+        let mut names = self.0.write().unwrap();
+        // It could have been added between the two locks.
+        match names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                names.push(name.to_string());
+                names.len() - 1
+            }
+        }
+    }
+
+    pub fn get(&self, i: usize) -> String {
+        self.0.read().unwrap()[i].clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.read().unwrap().len()
+    }
+}
+
+/// A key that is only known at runtime, stored as an index into the names registered for its kind.
+pub trait ModKey: Copy {
+    fn index(&self) -> usize;
+    fn from_index(index: usize) -> Self;
+}
+
+macro_rules! mod_keys {
+    ($($(#[$meta:meta])* $name:ident: $names:ident), * $(,)?) => {
+        $(
+            static $names: Names = Names::new();
+
+            $(#[$meta])*
+            #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            pub struct $name(u16);
+
+            impl $name {
+                /// Finds or registers the key with the `namespace:id`.
+                pub fn named(name: &str) -> Self {
+                    Self($names.intern(name) as u16)
+                }
+
+                /// The `namespace:id` of the key.
+                pub fn id(&self) -> String {
+                    $names.get(self.0 as usize)
+                }
+
+                /// Every key that has been registered, including the ones only referred to.
+                pub fn registered() -> Vec<Self> {
+                    (0..$names.len()).map(|i| Self(i as u16)).collect()
+                }
+            }
+
+            impl ModKey for $name {
+                fn index(&self) -> usize {
+                    self.0 as usize
+                }
+
+                fn from_index(index: usize) -> Self {
+                    Self(index as u16)
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{}", self.id())
+                }
+            }
+
+            impl fmt::Debug for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    fmt::Display::fmt(self, f)
+                }
+            }
+
+            impl Serialize for $name {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_str(&self.id())
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let name = String::deserialize(deserializer)?;
+                    if name.contains(':') {
+                        Ok(Self::named(&name))
+                    } else {
+                        Err(D::Error::custom(format!("{} should be given as namespace:id", name)))
+                    }
+                }
+            }
+        ) *
+    };
+}
+
+mod_keys! {
+    /// A stat added by a mod through a `CustomStat` definition.
+    ModStat: MOD_STATS,
+    /// A resource added by a mod through a `CustomResource` definition.
+    ModResource: MOD_RESOURCES,
+}
+
+/// Anything with a value in `Stats` that definitions can refer to. Built-in stats are written by name, e.g. `Strength`,
+/// and custom ones by `namespace:id`. The names that are both a base stat and a stat, e.g. `Vision`, are the stat.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StatKey {
+    Stat(Stat),
+    Base(BaseStat),
+    Resource(Resource),
+    Mod(ModStat),
+}
+
+impl StatKey {
+    pub fn named(name: &str) -> Option<Self> {
+        if name.contains(':') {
+            return Some(Self::Mod(ModStat::named(name)));
+        }
+        let value = || serde_json::Value::String(name.to_string());
+        serde_json::from_value(value())
+            .map(Self::Stat)
+            .or_else(|_| serde_json::from_value(value()).map(Self::Base))
+            .or_else(|_| serde_json::from_value(value()).map(Self::Resource))
+            .ok()
+    }
+}
+
+impl StatAccessor for StatKey {
+    fn get_value(&self, stats: &Stats) -> f32 {
+        match self {
+            Self::Stat(stat) => stats[*stat],
+            Self::Base(stat) => stats[*stat],
+            Self::Resource(resource) => stats[*resource],
+            Self::Mod(stat) => stats.mod_stat(*stat),
+        }
+    }
+
+    fn get_base_value(&self, stats: &Stats) -> f32 {
+        match self {
+            Self::Stat(stat) => stats.get_base(*stat),
+            Self::Base(stat) => stats.get_base(*stat),
+            Self::Resource(resource) => stats[*resource],
+            Self::Mod(stat) => stats.get_base(*stat),
+        }
+    }
+}
+
+impl fmt::Display for StatKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stat(stat) => write!(f, "{:?}", stat),
+            Self::Base(stat) => write!(f, "{}", stat.get_name()),
+            Self::Resource(resource) => write!(f, "{}", resource.name()),
+            Self::Mod(stat) => write!(f, "{}", stat),
+        }
+    }
+}
+
+impl Serialize for StatKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Stat(stat) => stat.serialize(serializer),
+            Self::Base(stat) => stat.serialize(serializer),
+            Self::Resource(resource) => resource.serialize(serializer),
+            Self::Mod(stat) => stat.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StatKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::named(&name).ok_or_else(|| D::Error::custom(format!("unknown stat {}", name)))
+    }
+}
+
+/// A built-in resource, written by name, or one added by a mod, written by `namespace:id`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ResourceKey {
+    Builtin(Resource),
+    Mod(ModResource),
+}
+
+impl ResourceKey {
+    pub fn is_need(&self) -> bool {
+        matches!(self, Self::Builtin(resource) if resource.is_need())
+    }
+
+    pub fn regen(&self, stats: &Stats) -> f32 {
+        match self {
+            Self::Builtin(resource) => resource.regen(stats),
+            Self::Mod(resource) => stats.mod_resource_info(*resource).map(|info| info.regen(stats)).unwrap_or(0.0),
+        }
+    }
+
+    pub fn max(&self, stats: &Stats) -> f32 {
+        match self {
+            Self::Builtin(resource) => resource.max(stats),
+            Self::Mod(resource) => stats.mod_resource_info(*resource).map(|info| info.max(stats)).unwrap_or(f32::MAX),
+        }
+    }
+
+    pub fn start(&self, stats: &Stats) -> f32 {
+        match self {
+            Self::Builtin(resource) => resource.start(stats),
+            Self::Mod(resource) => stats.mod_resource_info(*resource).map(|info| info.start(stats)).unwrap_or(0.0),
+        }
+    }
+}
+
+impl From<Resource> for ResourceKey {
+    fn from(resource: Resource) -> Self {
+        Self::Builtin(resource)
+    }
+}
+
+impl From<ModResource> for ResourceKey {
+    fn from(resource: ModResource) -> Self {
+        Self::Mod(resource)
+    }
+}
+
+impl PartialEq<Resource> for ResourceKey {
+    fn eq(&self, other: &Resource) -> bool {
+        *self == Self::Builtin(*other)
+    }
+}
+
+impl fmt::Display for ResourceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Builtin(resource) => write!(f, "{}", resource.name()),
+            Self::Mod(resource) => write!(f, "{}", resource),
+        }
+    }
+}
+
+impl Serialize for ResourceKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Builtin(resource) => resource.serialize(serializer),
+            Self::Mod(resource) => resource.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ResourceKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        if name.contains(':') {
+            return Ok(Self::Mod(ModResource::named(&name)));
+        }
+        serde_json::from_value(serde_json::Value::String(name.clone()))
+            .map(Self::Builtin)
+            .map_err(|_| D::Error::custom(format!("unknown resource {}", name)))
+    }
+}
+
+/// Values for some of the keys of a kind, indexed by key. Serialized as a map from `namespace:id` to value.
+#[derive(Debug, Clone)]
+pub struct ModValues<K, V> {
+    values: Vec<Option<V>>,
+    key: PhantomData<K>,
+}
+
+impl<K, V> Default for ModValues<K, V> {
+    fn default() -> Self {
+        Self {
+            values: vec![],
+            key: PhantomData,
+        }
+    }
+}
+
+impl<K: ModKey, V> ModValues<K, V> {
+    pub fn is_empty(&self) -> bool {
+        self.values.iter().all(|v| v.is_none())
+    }
+
+    pub fn contains(&self, key: K) -> bool {
+        matches!(self.values.get(key.index()), Some(Some(_)))
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        self.values.get(key.index()).and_then(|v| v.as_ref())
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.values.get_mut(key.index()).and_then(|v| v.as_mut())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if self.values.len() <= key.index() {
+            self.values.resize_with(key.index() + 1, || None);
+        }
+        self.values[key.index()].replace(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, &V)> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.as_ref().map(|v| (K::from_index(i), v)))
+    }
+}
+
+impl<K: ModKey + Serialize, V: Serialize> Serialize for ModValues<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de, K: ModKey + Eq + Hash + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for ModValues<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut values = Self::default();
+        for (key, value) in HashMap::<K, V>::deserialize(deserializer)? {
+            values.insert(key, value);
+        }
+        Ok(values)
+    }
+}
+
+/// What a custom stat is calculated from, from its `CustomStat` definition.
+#[derive(Debug, Clone, Default)]
+pub struct ModStatInfo {
+    // The base value of the stat before any gains.
+    pub default: f32,
+    // Added to the base, each key's value times the factor.
+    pub from: Vec<(StatKey, f32)>,
+    pub cap: StatCap,
+}
+
+/// How a custom resource behaves, from its `CustomResource` definition.
+#[derive(Debug, Clone, Default)]
+pub struct ModResourceInfo {
+    // Resources without a max can grow without limit.
+    pub max: Option<StatKey>,
+    // Gained per second, can be negative.
+    pub regen: Option<StatKey>,
+    // Starts and is restored to the max instead of 0.
+    pub start_full: bool,
+}
+
+impl ModResourceInfo {
+    pub fn max(&self, stats: &Stats) -> f32 {
+        self.max.map(|key| stats.get(key)).unwrap_or(f32::MAX)
+    }
+
+    pub fn regen(&self, stats: &Stats) -> f32 {
+        self.regen.map(|key| stats.get(key)).unwrap_or(0.0)
+    }
+
+    pub fn start(&self, stats: &Stats) -> f32 {
+        match self.max {
+            Some(max) if self.start_full => stats.get(max),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Every custom stat and resource registered by mods.
+#[derive(Debug, Clone, Default)]
+pub struct CustomTable {
+    pub stats: ModValues<ModStat, ModStatInfo>,
+    pub resources: ModValues<ModResource, ModResourceInfo>,
+}
+
+/// Custom stats and resources given to new `Stats`, `None` if no mod registers any.
+#[derive(Default)]
+pub struct CustomStatTable(pub Option<Arc<CustomTable>>);
+
+fn build_custom_table(
+    mut commands: Commands,
+    stats: Res<CustomStats>,
+    resources: Res<CustomResources>,
+    mut printer: EventWriter<Message>,
+) {
+    let mut table = CustomTable::default();
+    for def in stats.iter() {
+        table.stats.insert(
+            ModStat::named(&def.get_string_id()),
+            ModStatInfo {
+                default: def.default,
+                from: def.from.clone(),
+                cap: StatCap {
+                    min: def.min,
+                    max: def.max,
+                    soft: def.soft,
+                },
+            },
+        );
+    }
+    for def in resources.iter() {
+        table.resources.insert(
+            ModResource::named(&def.get_string_id()),
+            ModResourceInfo {
+                max: def.max,
+                regen: def.regen,
+                start_full: def.start_full,
+            },
+        );
+    }
+    for stat in ModStat::registered() {
+        if !table.stats.contains(stat) {
+            printer.send(Message::error(format!("Custom stat {} is used, but no mod defines it.", stat)));
+        }
+    }
+    for resource in ModResource::registered() {
+        if !table.resources.contains(resource) {
+            printer.send(Message::error(format!("Custom resource {} is used, but no mod defines it.", resource)));
+        }
+    }
+    let empty = table.stats.is_empty() && table.resources.is_empty();
+    commands.insert_resource(CustomStatTable(if empty { None } else { Some(Arc::new(table)) }));
+}
+
+fn apply_custom_table(table: Res<CustomStatTable>, mut query: Query<&mut Stats, Added<Stats>>) {
+    if let Some(table) = &table.0 {
+        for mut stats in query.iter_mut() {
+            stats.set_custom_table(Some(table.clone()));
+        }
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.init_resource::<CustomStatTable>()
+        .add_startup_system_to_stage("generate", build_custom_table.system())
+        .add_system_to_stage(CoreStage::PreUpdate, apply_custom_table.system());
+}
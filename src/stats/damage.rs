@@ -1,168 +1,418 @@
-use rand::Rng;
-use rand::thread_rng;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
 
+use bevy::prelude::*;
+use paste::paste;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::count_idents;
+use crate::defs::{DamageTypes, Definition, Message};
 use crate::stats::Stats;
 use crate::stats::Stat;
+use crate::stats::StatCap;
+use crate::stats::StatKey;
+use crate::stats::CombatRng;
+use crate::stats::custom::Names;
+use crate::stats::stat::distribution;
 
+fn one() -> f32 {
+    1.0
+}
 
-macro_rules! damages {
-    ($($name:ident, dmg => $dmg_calc:expr, red => $red_calc:expr), *,) => {
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-        pub enum DmgType {
-            $($name), *
-        }
+// Names of the damage types added by mods, the id of one is its index plus the number of built-in types.
+static CUSTOM_DMG_TYPES: Names = Names::new();
 
-        pub struct Dmg {
-            dmgs: HashMap<DmgType, f32>,
-            dodgeable: bool, 
-            speed: f32,
-        }
+// The built-in types have fixed ids so they can be used from code, what they do is still defined by the vanilla mod.
+macro_rules! dmg_types {
+    ($($name:ident), *) => {
+        paste! {
+            const NUM_DMG_TYPES: usize = count_idents!($($name), *);
+            const DMG_TYPE_NAMES: [&'static str; NUM_DMG_TYPES] = [$(stringify!($name)), *];
+            const DMG_TYPE_IDS: [&'static str; NUM_DMG_TYPES] = [$(concat!("vanilla:", stringify!([<$name:lower>]))), *];
 
-        impl Dmg {
-            pub fn create(dmgs: HashMap<DmgType, f32>) -> Self {
-                Self {
-                    dmgs,
-                    dodgeable: false,
-                    speed: 0.0,
-                }
-            }
-            pub fn new(dmgs: HashMap<DmgType, f32>, dodgeable: bool, speed: f32) -> Self {
-                Self {
-                    dmgs,
-                    dodgeable,
-                    speed,
-                }
+            #[repr(u16)]
+            enum BuiltinDmgType {
+                $($name), *
             }
 
-            pub fn calculate_dealt(&self, stats: &Stats) -> Self {
-                Self::new(self.dmgs.iter().map(|(ty, v)| {
-                    (*ty, match ty {
-                        $(DmgType::$name => $dmg_calc(stats, *v)), *
-                    })
-                }).collect(), self.dodgeable, self.speed)
+            #[allow(non_upper_case_globals)]
+            impl DmgType {
+                $(pub const $name: DmgType = DmgType(BuiltinDmgType::$name as u16);) *
             }
 
-            pub fn calculate_taken(&self, stats: &Stats) -> Self {
-                Self::new(self.dmgs.iter().map(|(ty, v)| {
-                    (*ty, match ty {
-                        $(DmgType::$name => $red_calc(stats, *v)), *
-                    })
-                }).collect(), self.dodgeable, self.speed)
-            }
+            pub const DMG_TYPE_ITER: [DmgType; NUM_DMG_TYPES] = [$(DmgType::$name), *];
+        }
+    };
+}
 
-            pub fn can_dodge(&self) -> bool {
-                self.dodgeable
-            }
-            pub fn get_speed(&self) -> f32 {
-                self.speed
-            }
+/// A damage type, either built in or added by a mod. What it does is given by its `DamageType` definition.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DmgType(u16);
 
-            pub fn sum(&self) -> f32 { 
-                let mut sum = 0.0;
-                for (_, &val) in &self.dmgs {
-                    sum += val;
-                }
-                sum
-            }
+dmg_types!(Physical, Cutting, Magic, Mental, Curse, Holy, Fire, Ice, Wind, Electric, Earth, Pure);
+
+impl DmgType {
+    /// Finds or registers the damage type with the `namespace:id`. Names without a namespace are taken to be vanilla,
+    /// so the names of the built-in types, e.g. `Fire`, work as well.
+    pub fn named(name: &str) -> Self {
+        let id = Self::full_id(name);
+        if let Some(i) = DMG_TYPE_IDS.iter().position(|builtin| *builtin == id) {
+            return DmgType(i as u16);
+        }
+        DmgType((NUM_DMG_TYPES + CUSTOM_DMG_TYPES.intern(&id)) as u16)
+    }
+
+    /// Finds the damage type like `named`, without registering it if it is new.
+    pub fn find(name: &str) -> Option<Self> {
+        let id = Self::full_id(name);
+        match DMG_TYPE_IDS.iter().position(|builtin| *builtin == id) {
+            Some(i) => Some(DmgType(i as u16)),
+            None => CUSTOM_DMG_TYPES.find(&id).map(|i| DmgType((NUM_DMG_TYPES + i) as u16)),
+        }
+    }
+
+    fn full_id(name: &str) -> String {
+        if name.contains(':') {
+            name.to_string()
+        } else {
+            format!("vanilla:{}", name.to_lowercase())
+        }
+    }
+
+    /// Every type added by mods, including the ones only referred to.
+    pub fn registered_custom() -> Vec<Self> {
+        (0..CUSTOM_DMG_TYPES.len()).map(|i| DmgType((NUM_DMG_TYPES + i) as u16)).collect()
+    }
+
+    /// The `namespace:id` of the type.
+    pub fn id(&self) -> String {
+        match DMG_TYPE_IDS.get(self.index()) {
+            Some(id) => id.to_string(),
+            None => CUSTOM_DMG_TYPES.get(self.index() - NUM_DMG_TYPES),
+        }
+    }
+
+    pub fn is_builtin(&self) -> bool {
+        self.index() < NUM_DMG_TYPES
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+
+    // Cap on the damage taken after reductions when no mod overrides it, damage never heals.
+    pub fn default_cap(&self) -> StatCap {
+        StatCap::none().with_min(0.0)
+    }
+}
+
+impl fmt::Display for DmgType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match DMG_TYPE_NAMES.get(self.index()) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", self.id()),
+        }
+    }
+}
+
+impl fmt::Debug for DmgType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+// Built-in types are written by name, e.g. `Fire`, others by `namespace:id`.
+impl Serialize for DmgType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DmgType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(DmgType::named(&String::deserialize(deserializer)?))
+    }
+}
+
+/// How a reduction is worked out from the stats of whoever takes the damage.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Reduction {
+    // The value of a stat.
+    Stat(StatKey),
+    // The armor of the damage type times `factor`.
+    Linear { factor: f32 },
+    // `distribution(armor, half)`, which goes from 1 towards 0 as the armor grows.
+    Distribution { half: f32 },
+}
+
+impl Reduction {
+    fn value(&self, stats: &Stats, armor: f32) -> f32 {
+        match self {
+            Self::Stat(stat) => stats.get(*stat),
+            Self::Linear { factor } => armor * factor,
+            Self::Distribution { half } => distribution(armor, *half),
+        }
+    }
+}
+
+/// What a damage type does, from its `DamageType` definition.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DmgTypeInfo {
+    // Multiplies the damage dealt.
+    pub scaling: Option<StatKey>,
+    pub crit_chance: Option<StatKey>,
+    // Without it the crit chance is ignored.
+    pub can_crit: bool,
+    // Used by `Linear` and `Distribution` reductions.
+    pub armor: Option<StatKey>,
+    // Multiplies the damage taken.
+    pub reduction: Option<Reduction>,
+    // Subtracted from the damage taken before `reduction`.
+    pub flat_reduction: Option<Reduction>,
+    // Skips reductions and blocking.
+    pub bypass_mitigation: bool,
+}
+
+// Types without a definition aren't scaled, can't crit and aren't reduced.
+static UNDEFINED_DMG_TYPE: DmgTypeInfo = DmgTypeInfo {
+    scaling: None,
+    crit_chance: None,
+    can_crit: false,
+    armor: None,
+    reduction: None,
+    flat_reduction: None,
+    bypass_mitigation: false,
+};
+
+impl DmgTypeInfo {
+    pub fn crit_chance(&self, stats: &Stats) -> f32 {
+        match self.crit_chance {
+            Some(stat) if self.can_crit => stats.get(stat),
+            _ => 0.0,
+        }
+    }
+
+    // Damage of this type dealt by `stats`, before crits.
+    pub fn dealt(&self, stats: &Stats, val: f32) -> f32 {
+        val * self.scaling.map(|stat| stats.get(stat)).unwrap_or(1.0)
+    }
+
+    // Damage of this type taken by `stats` after reductions and the damage cap of `ty`.
+    pub fn taken(&self, ty: DmgType, stats: &Stats, val: f32) -> f32 {
+        if self.bypass_mitigation {
+            return stats.dmg_cap(ty).apply(val);
+        }
+        let armor = self.armor.map(|stat| stats.get(stat)).unwrap_or(0.0);
+        let flat = self.flat_reduction.map(|r| r.value(stats, armor)).unwrap_or(0.0);
+        let mul = self.reduction.map(|r| r.value(stats, armor)).unwrap_or(1.0);
+        stats.dmg_cap(ty).apply((val - flat) * mul)
+    }
+}
+
+/// What every damage type does, built from the `DamageType` definitions.
+#[derive(Debug, Clone, Default)]
+pub struct DmgTable {
+    types: Vec<Option<DmgTypeInfo>>,
+}
+
+impl DmgTable {
+    pub fn insert(&mut self, ty: DmgType, info: DmgTypeInfo) {
+        if self.types.len() <= ty.index() {
+            self.types.resize(ty.index() + 1, None);
+        }
+        self.types[ty.index()] = Some(info);
+    }
+
+    pub fn contains(&self, ty: DmgType) -> bool {
+        matches!(self.types.get(ty.index()), Some(Some(_)))
+    }
+
+    pub fn get(&self, ty: DmgType) -> &DmgTypeInfo {
+        match self.types.get(ty.index()) {
+            Some(Some(info)) => info,
+            _ => &UNDEFINED_DMG_TYPE,
         }
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (DmgType, &DmgTypeInfo)> + '_ {
+        self.types
+            .iter()
+            .enumerate()
+            .filter_map(|(i, info)| info.as_ref().map(|info| (DmgType(i as u16), info)))
+    }
 }
 
+// Ordered so that rolls are made in the same order every time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dmg {
+    dmgs: BTreeMap<DmgType, f32>,
+    #[serde(default)]
+    dodgeable: bool,
+    #[serde(default)]
+    speed: f32,
+    #[serde(skip)]
+    crit: bool,
+    #[serde(skip, default = "one")]
+    crit_mul: f32,
+    #[serde(skip, default = "one")]
+    accuracy: f32,
+}
+
+impl Dmg {
+    pub fn create(dmgs: BTreeMap<DmgType, f32>) -> Self {
+        Self {
+            dmgs,
+            dodgeable: false,
+            speed: 0.0,
+            crit: false,
+            crit_mul: 1.0,
+            accuracy: 1.0,
+        }
+    }
+    pub fn new(dmgs: BTreeMap<DmgType, f32>, dodgeable: bool, speed: f32) -> Self {
+        Self {
+            dmgs,
+            dodgeable,
+            speed,
+            crit: false,
+            crit_mul: 1.0,
+            accuracy: 1.0,
+        }
+    }
+
+    // Each damage type rolls for crit on its own. The attacker's accuracy is kept for when the damage is applied.
+    pub fn calculate_dealt(&self, stats: &Stats, types: &DmgTable, rng: &mut dyn CombatRng) -> Self {
+        let crit_mul = stats[Stat::CritDamage];
+        let mut crit = false;
+        let mut dealt = Self::new(self.dmgs.iter().map(|(ty, v)| {
+            let info = types.get(*ty);
+            let mul = if rng.chance(info.crit_chance(stats)) {
+                crit = true;
+                crit_mul
+            } else {
+                1.0
+            };
+            (*ty, mul * info.dealt(stats, *v))
+        }).collect(), self.dodgeable, self.speed);
+        dealt.crit = crit;
+        dealt.crit_mul = if crit { crit_mul } else { 1.0 };
+        dealt.accuracy = stats[Stat::Accuracy];
+        dealt
+    }
+
+    pub fn calculate_taken(&self, stats: &Stats, types: &DmgTable) -> Self {
+        Self::new(self.dmgs.iter().map(|(ty, v)| {
+            (*ty, types.get(*ty).taken(*ty, stats, *v))
+        }).collect(), self.dodgeable, self.speed)
+    }
+
+    pub fn is_crit(&self) -> bool {
+        self.crit
+    }
+
+    pub fn crit_multiplier(&self) -> f32 {
+        self.crit_mul
+    }
+
+    // Chance for the damage to hit at all.
+    pub fn accuracy(&self) -> f32 {
+        self.accuracy
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (DmgType, f32)> + '_ {
+        self.dmgs.iter().map(|(ty, v)| (*ty, *v))
+    }
+
+    pub fn get(&self, ty: DmgType) -> f32 {
+        self.dmgs.get(&ty).copied().unwrap_or(0.0)
+    }
+
+    pub fn add(&mut self, ty: DmgType, amount: f32) {
+        *self.dmgs.entry(ty).or_insert(0.0) += amount;
+    }
+
+    pub fn scale(&mut self, ty: DmgType, mul: f32) {
+        if let Some(v) = self.dmgs.get_mut(&ty) {
+            *v *= mul;
+        }
+    }
+
+    pub fn can_dodge(&self) -> bool {
+        self.dodgeable
+    }
+    pub fn get_speed(&self) -> f32 {
+        self.speed
+    }
 
-damages! {
-    Physical,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::PhysicalDamage] * if thread_rng().gen::<f32>() < stats[Stat::PhysicalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::PhysicalReductionFlat]) * stats[Stat::PhysicalReduction]
-    },
-    Cutting,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::CuttingDamage] * if thread_rng().gen::<f32>() < stats[Stat::PhysicalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::CuttingReductionFlat]) * stats[Stat::CuttingReduction]
-    },
-    Magic,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::MagicalDamage] * if thread_rng().gen::<f32>() < stats[Stat::MagicalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::MagicalReductionFlat]) * stats[Stat::MagicalReduction]
-    },
-    Mental,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::MentalDamage]
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::MentalReductionFlat]) * stats[Stat::MentalReduction]
-    },
-    Curse,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::CurseDamage] * if thread_rng().gen::<f32>() < stats[Stat::MagicalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::CurseReductionFlat]) * stats[Stat::CurseReduction]
-    },
-    Holy,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::HolyDamage] * if thread_rng().gen::<f32>() < stats[Stat::MagicalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::HolyReductionFlat]) * stats[Stat::HolyReduction]
-    },
-    Fire,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::FireDamage] * if thread_rng().gen::<f32>() < stats[Stat::ElementalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::FireReductionFlat]) * stats[Stat::FireReduction]
-    },
-    Ice,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::IceDamage] * if thread_rng().gen::<f32>() < stats[Stat::ElementalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::IceReductionFlat]) * stats[Stat::IceReduction]
-    },
-    Wind,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::WindDamage] * if thread_rng().gen::<f32>() < stats[Stat::ElementalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::WindReductionFlat]) * stats[Stat::WindReduction]
-    },
-    Electric,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::ElectricDamage] * if thread_rng().gen::<f32>() < stats[Stat::ElementalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::ElectricReductionFlat]) * stats[Stat::ElectricReduction]
-    },
-    Earth,
-    dmg => |stats: &Stats, val: f32| {
-        val * stats[Stat::EarthDamage] * if thread_rng().gen::<f32>() < stats[Stat::ElementalCritChance] { 2.0 } else { 1.0 } // Maybe have a crit damage stat?
-    },
-    red => |stats: &Stats, val: f32| {
-        (val - stats[Stat::EarthReductionFlat]) * stats[Stat::EarthReduction]
-    },
-    Pure,
-    dmg => |_: &Stats, val: f32| {
-        val
-    },
-    red => |_: &Stats, val: f32| {
-        val
-    },
+    pub fn sum(&self) -> f32 { 
+        let mut sum = 0.0;
+        for (_, &val) in &self.dmgs {
+            sum += val;
+        }
+        sum
+    }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DmgResult {
     Hit,
     Dodge,
     Miss,
 }
 
+/// How much of one damage type got through.
+#[derive(Debug, Copy, Clone)]
+pub struct DmgAmount {
+    pub ty: DmgType,
+    // Dealt by the attacker, crits included.
+    pub raw: f32,
+    // Removed by reductions and blocking.
+    pub mitigated: f32,
+    // What was actually taken, `raw - mitigated`.
+    pub taken: f32,
+}
+
+/// Everything that happened when damage was applied to an entity.
+#[derive(Debug, Clone)]
+pub struct DamageReport {
+    pub result: DmgResult,
+    pub accuracy: f32,
+    pub blocked: bool,
+    pub crit: bool,
+    pub crit_multiplier: f32,
+    // Empty unless the damage hit.
+    pub amounts: Vec<DmgAmount>,
+    pub total: f32,
+    // Damage beyond what was needed to bring HP to zero.
+    pub overkill: f32,
+    pub lethal: bool,
+}
+
+impl DamageReport {
+    pub fn new(result: DmgResult, dmg: &Dmg) -> Self {
+        Self {
+            result,
+            accuracy: dmg.accuracy(),
+            blocked: false,
+            crit: dmg.is_crit(),
+            crit_multiplier: dmg.crit_multiplier(),
+            amounts: vec![],
+            total: 0.0,
+            overkill: 0.0,
+            lethal: false,
+        }
+    }
+
+    pub fn is_hit(&self) -> bool {
+        self.result == DmgResult::Hit
+    }
+
+    pub fn get(&self, ty: DmgType) -> Option<&DmgAmount> {
+        self.amounts.iter().find(|amount| amount.ty == ty)
+    }
+}
+
 #[macro_export]
 macro_rules! dmg {
     ($($type:ident: $value:expr), *) => {
@@ -177,4 +427,33 @@ macro_rules! dmg {
     ([$map:expr], $speed:expr) => {
         crate::stats::damage::Dmg::new($map, true, $speed)
     };
-}
\ No newline at end of file
+}
+
+fn build_dmg_table(mut commands: Commands, defs: Res<DamageTypes>, mut printer: EventWriter<Message>) {
+    let mut table = DmgTable::default();
+    for def in defs.iter() {
+        table.insert(
+            DmgType::named(&def.get_string_id()),
+            DmgTypeInfo {
+                scaling: def.scaling,
+                crit_chance: def.crit_chance,
+                can_crit: def.can_crit,
+                armor: def.armor,
+                reduction: def.reduction,
+                flat_reduction: def.flat_reduction,
+                bypass_mitigation: def.bypass_mitigation,
+            },
+        );
+    }
+    for ty in DmgType::registered_custom() {
+        if defs.get(&ty.id()).is_none() {
+            printer.send(Message::error(format!("Damage type {} is used, but no mod defines it.", ty)));
+        }
+    }
+    commands.insert_resource(table);
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.init_resource::<DmgTable>()
+        .add_startup_system_to_stage("generate", build_dmg_table.system().label("build_dmg_table"));
+}
@@ -1,64 +1,671 @@
-use crate::stats::stat::distribution;
-use crate::stats::{Stat, Stats};
-use crate::dmg;
+use std::collections::HashMap;
 
-struct EffectData {
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{Definition, Effect, Effects, Message};
+use crate::stats::{Attack, DamageSource, Dmg, DmgType, ResourceKey, StatGain, Stats, MAX_TRIGGER_DEPTH};
+use crate::{bitmap, count_idents};
+use crate::macro_help::Bitmap;
+
+bitmap! {
+    pub EffectCategory: u16[Magic, Physical, Elemental, Poison, Curse, Movement, Buff, Debuff]
+}
+
+/// What happens when an effect is applied to an entity that already has it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stacking {
+    // The effect keeps one instance, duration and strength are set to the highest of the two.
+    Refresh,
+    // The effect keeps one instance, each stack adds its strength and refreshes the duration.
+    Intensity,
+    // Every application is its own instance with its own duration.
+    Independent,
+}
+
+impl Default for Stacking {
+    fn default() -> Self {
+        Self::Refresh
+    }
+}
+
+/// Something an effect does to the entity it is on. Amounts are multiplied by the strength of the effect.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EffectAction {
+    // Added values are multiplied by the strength and multipliers raised to the power of it.
+    // Every gain is taken away again when the effect ends.
+    Gain(StatGain),
+    Damage { ty: DmgType, amount: f32 },
+    Drain { resource: ResourceKey, amount: f32 },
+    Restore { resource: ResourceKey, amount: f32 },
+    // Applies another effect, by `namespace:id`, to the same entity.
+    Apply { effect: String, strength: f32, duration: f32 },
+}
+
+// An effect an action wants to apply, applied by the system once the action is done.
+struct PendingEffect {
+    effect: Effect,
+    source: Option<Entity>,
+    strength: f32,
+    duration: f32,
+}
+
+// Damage is sent as an attack, so it goes through the same resolution and is credited to the source of the effect.
+struct PendingDamage {
+    effect: Effect,
+    source: Option<Entity>,
+    dmg: Dmg,
+}
+
+#[derive(Default)]
+struct Pending {
+    effects: Vec<PendingEffect>,
+    damage: Vec<PendingDamage>,
+}
+
+fn run_actions(
+    defs: &Effects,
+    actions: &[EffectAction],
+    stats: &mut Stats,
+    effect: Effect,
+    source: Option<Entity>,
     strength: f32,
-    data: f32,
+    scale: f32,
+    gains: &mut Vec<StatGain>,
+    pending: &mut Pending,
+) {
+    for action in actions {
+        match action {
+            EffectAction::Gain(gain) => {
+                let gain = gain.scaled(strength);
+                stats.add_gain(&gain);
+                gains.push(gain);
+            }
+            EffectAction::Damage { ty, amount } => {
+                pending.damage.push(PendingDamage {
+                    effect,
+                    source,
+                    dmg: Dmg::create(std::iter::once((*ty, amount * strength * scale)).collect()),
+                });
+            }
+            EffectAction::Drain { resource, amount } => stats.gain_resource(*resource, -amount * strength * scale),
+            EffectAction::Restore { resource, amount } => stats.gain_resource(*resource, amount * strength * scale),
+            EffectAction::Apply { effect, strength: s, duration } => {
+                if let Some(effect) = defs.get(effect) {
+                    pending.effects.push(PendingEffect {
+                        effect,
+                        source,
+                        strength: s * strength,
+                        duration: *duration,
+                    });
+                }
+            }
+        }
+    }
 }
 
-macro_rules! effects {
-    ($($name:ident $(, start => $start:expr)? $(, update => $update:expr)? $(, end => $end:expr)?) *) => {
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-        enum Effect {
-            $($name), *
+#[derive(Debug, Clone)]
+pub struct ActiveEffect {
+    effect: Effect,
+    source: Option<Entity>,
+    // Strength of a single stack.
+    strength: f32,
+    stacks: u32,
+    duration: f32,
+    remaining: f32,
+    until_tick: f32,
+    // Gains given by the actions, removed when the effect ends.
+    gains: Vec<StatGain>,
+}
+
+impl ActiveEffect {
+    pub fn effect(&self) -> Effect {
+        self.effect
+    }
+
+    pub fn source(&self) -> Option<Entity> {
+        self.source
+    }
+
+    pub fn stacks(&self) -> u32 {
+        self.stacks
+    }
+
+    pub fn remaining(&self) -> f32 {
+        self.remaining
+    }
+
+    /// Strength of all stacks together.
+    pub fn total_strength(&self) -> f32 {
+        self.strength * self.stacks as f32
+    }
+
+    fn start(&mut self, defs: &Effects, stats: &mut Stats, pending: &mut Pending) {
+        let strength = self.total_strength();
+        run_actions(defs, &defs[self.effect].on_start, stats, self.effect, self.source, strength, 1.0, &mut self.gains, pending);
+    }
+
+    fn remove_gains(&mut self, stats: &mut Stats) {
+        for gain in self.gains.drain(..) {
+            stats.remove_gain(&gain);
         }
+    }
+
+    // Runs `on_start` again after the strength or stacks have changed.
+    fn restart(&mut self, defs: &Effects, stats: &mut Stats, pending: &mut Pending) {
+        self.remove_gains(stats);
+        self.start(defs, stats, pending);
+    }
+
+    fn end(&mut self, defs: &Effects, stats: &mut Stats, expired: bool, pending: &mut Pending) {
+        let def = &defs[self.effect];
+        let strength = self.total_strength();
+        let mut gains = vec![];
+        run_actions(defs, &def.on_end, stats, self.effect, self.source, strength, 1.0, &mut gains, pending);
+        if expired {
+            run_actions(defs, &def.on_expire, stats, self.effect, self.source, strength, 1.0, &mut gains, pending);
+        }
+        self.gains.extend(gains);
+        self.remove_gains(stats);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectRemoved {
+    Expired,
+    Dispelled,
+    Removed,
+}
+
+/// The effects on an entity, and the effects it can't get.
+#[derive(Debug, Clone, Default)]
+pub struct ActiveEffects {
+    effects: Vec<ActiveEffect>,
+    immune_categories: EffectCategory,
+    immune_effects: Vec<Effect>,
+}
+
+/// An `ActiveEffect` as it is saved. The effect is kept by its `namespace:id`, as the ids of definitions depend on the
+/// mods that were loaded. Entities aren't kept between saves, so neither is the source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedEffect {
+    effect: String,
+    strength: f32,
+    stacks: u32,
+    duration: f32,
+    remaining: f32,
+    until_tick: f32,
+    gains: Vec<StatGain>,
+}
 
-        impl Effect {
-            fn name(&self) -> &'static str {
-                match self {
-                    $(Self::$name => stringify!($name)), *
+/// `ActiveEffects` as they are saved, see `SavedEffect`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedEffects {
+    effects: Vec<SavedEffect>,
+    immune_categories: EffectCategory,
+    immune_effects: Vec<String>,
+}
+
+impl SavedEffects {
+    /// Resolves the effects with the loaded definitions. Effects that are no longer defined are dropped and their
+    /// gains taken off `stats`, which were saved with them.
+    pub fn load(self, defs: &Effects, stats: &mut Stats) -> ActiveEffects {
+        let mut effects = vec![];
+        for saved in self.effects {
+            match defs.get(&saved.effect) {
+                Some(effect) => effects.push(ActiveEffect {
+                    effect,
+                    source: None,
+                    strength: saved.strength,
+                    stacks: saved.stacks,
+                    duration: saved.duration,
+                    remaining: saved.remaining,
+                    until_tick: saved.until_tick,
+                    gains: saved.gains,
+                }),
+                None => {
+                    for gain in &saved.gains {
+                        stats.remove_gain(gain);
+                    }
                 }
             }
+        }
+        ActiveEffects {
+            effects,
+            immune_categories: self.immune_categories,
+            immune_effects: self.immune_effects.iter().filter_map(|effect| defs.get(effect)).collect(),
+        }
+    }
+}
+
+impl ActiveEffects {
+    pub fn save(&self, defs: &Effects) -> SavedEffects {
+        SavedEffects {
+            effects: self
+                .effects
+                .iter()
+                .map(|active| SavedEffect {
+                    effect: defs[active.effect].get_string_id(),
+                    strength: active.strength,
+                    stacks: active.stacks,
+                    duration: active.duration,
+                    remaining: active.remaining,
+                    until_tick: active.until_tick,
+                    gains: active.gains.clone(),
+                })
+                .collect(),
+            immune_categories: self.immune_categories,
+            immune_effects: self.immune_effects.iter().map(|effect| defs[*effect].get_string_id()).collect(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ActiveEffect> + '_ {
+        self.effects.iter()
+    }
+
+    pub fn has(&self, effect: Effect) -> bool {
+        self.effects.iter().any(|e| e.effect == effect)
+    }
+
+    pub fn stacks(&self, effect: Effect) -> u32 {
+        self.effects.iter().filter(|e| e.effect == effect).map(|e| e.stacks).sum()
+    }
+
+    /// Keeps the instance of `effect` applied by `source` going for at least `duration` seconds, without applying it
+    /// again. Returns `false` if there is no such instance.
+    pub fn refresh(&mut self, effect: Effect, source: Entity, duration: f32) -> bool {
+        match self.effects.iter_mut().find(|e| e.effect == effect && e.source == Some(source)) {
+            Some(active) => {
+                active.remaining = active.remaining.max(duration);
+                active.duration = active.duration.max(duration);
+                true
+            }
+            None => false,
+        }
+    }
 
-            fn start(&self, stats: &mut Stats, data: &mut EffectData) {
-                match self {
-                    $($(Self::$name => { $start(stats, data);},)?) *
-                    _ => {}
+    pub fn add_immunity(&mut self, categories: EffectCategory) {
+        self.immune_categories |= categories;
+    }
+
+    pub fn remove_immunity(&mut self, categories: EffectCategory) {
+        self.immune_categories -= categories;
+    }
+
+    pub fn add_effect_immunity(&mut self, effect: Effect) {
+        if !self.immune_effects.contains(&effect) {
+            self.immune_effects.push(effect);
+        }
+    }
+
+    pub fn remove_effect_immunity(&mut self, effect: Effect) {
+        self.immune_effects.retain(|e| *e != effect);
+    }
+
+    pub fn is_immune(&self, defs: &Effects, effect: Effect) -> bool {
+        self.immune_effects.contains(&effect) || !(defs[effect].categories & self.immune_categories).is_empty()
+    }
+
+    /// Puts an effect on the entity following its stacking rules, returns the number of stacks it ended up with,
+    /// or `None` if the entity is immune.
+    fn apply(
+        &mut self,
+        defs: &Effects,
+        stats: &mut Stats,
+        request: &ApplyEffect,
+        pending: &mut Pending,
+    ) -> Option<u32> {
+        let ApplyEffect { effect, source, strength, duration, .. } = *request;
+        if self.is_immune(defs, effect) {
+            return None;
+        }
+        let def = &defs[effect];
+        let max_stacks = def.max_stacks.unwrap_or(1).max(1);
+        let existing = self.effects.iter().position(|e| e.effect == effect);
+        match (def.stacking, existing) {
+            (Stacking::Refresh, Some(i)) => {
+                let active = &mut self.effects[i];
+                active.remaining = active.remaining.max(duration);
+                active.duration = active.duration.max(duration);
+                active.source = source.or(active.source);
+                if strength > active.strength {
+                    active.strength = strength;
+                    active.restart(defs, stats, pending);
                 }
+                Some(active.stacks)
             }
-            fn update(&self, stats: &mut Stats, data: &mut EffectData, delta: f32) {
-                match self {
-                    $($(Self::$name => { $update(stats, data, delta);},)?) *
-                    _ => {}
+            (Stacking::Intensity, Some(i)) => {
+                let active = &mut self.effects[i];
+                active.remaining = duration;
+                active.duration = duration;
+                active.source = source.or(active.source);
+                if active.stacks < max_stacks {
+                    active.stacks += 1;
+                    active.restart(defs, stats, pending);
                 }
+                Some(active.stacks)
+            }
+            (Stacking::Independent, Some(_)) if self.stacks(effect) >= max_stacks => {
+                // Replace the instance that would run out first.
+                let i = self
+                    .effects
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| e.effect == effect)
+                    .min_by(|(_, a), (_, b)| a.remaining.partial_cmp(&b.remaining).unwrap_or(std::cmp::Ordering::Equal))
+                    .map(|(i, _)| i)
+                    .unwrap();
+                let mut replaced = self.effects.remove(i);
+                replaced.remove_gains(stats);
+                self.push(defs, stats, request, pending);
+                Some(self.stacks(effect))
             }
-            fn end(&self, stats: &mut Stats, data: &mut EffectData) {
-                match self {
-                    $($(Self::$name => { $end(stats, data);},)?) *
-                    _ => {}
+            _ => {
+                self.push(defs, stats, request, pending);
+                Some(self.stacks(effect))
+            }
+        }
+    }
+
+    fn push(
+        &mut self,
+        defs: &Effects,
+        stats: &mut Stats,
+        request: &ApplyEffect,
+        pending: &mut Pending,
+    ) {
+        let mut active = ActiveEffect {
+            effect: request.effect,
+            source: request.source,
+            strength: request.strength,
+            stacks: 1,
+            duration: request.duration,
+            remaining: request.duration,
+            until_tick: defs[request.effect].interval.unwrap_or(0.0),
+            gains: vec![],
+        };
+        active.start(defs, stats, pending);
+        self.effects.push(active);
+    }
+
+    /// Removes every effect in any of the categories, returning the effects that were removed.
+    fn dispel(
+        &mut self,
+        defs: &Effects,
+        stats: &mut Stats,
+        categories: EffectCategory,
+        pending: &mut Pending,
+    ) -> Vec<Effect> {
+        self.remove_where(defs, stats, pending, |effect| !(defs[effect].categories & categories).is_empty())
+    }
+
+    /// Removes every instance of the effect, returns if there were any.
+    fn remove(&mut self, defs: &Effects, stats: &mut Stats, effect: Effect, pending: &mut Pending) -> bool {
+        !self.remove_where(defs, stats, pending, |e| e == effect).is_empty()
+    }
+
+    fn remove_where(
+        &mut self,
+        defs: &Effects,
+        stats: &mut Stats,
+        pending: &mut Pending,
+        f: impl Fn(Effect) -> bool,
+    ) -> Vec<Effect> {
+        let mut removed = vec![];
+        let mut i = 0;
+        while i < self.effects.len() {
+            if f(self.effects[i].effect) {
+                let mut active = self.effects.remove(i);
+                active.end(defs, stats, false, pending);
+                removed.push(active.effect);
+            } else {
+                i += 1;
+            }
+        }
+        removed
+    }
+
+    /// Updates every effect, returning the effects that ran out.
+    fn update(
+        &mut self,
+        defs: &Effects,
+        stats: &mut Stats,
+        delta: f32,
+        pending: &mut Pending,
+    ) -> Vec<Effect> {
+        let mut expired = vec![];
+        for active in self.effects.iter_mut() {
+            let def = &defs[active.effect];
+            let strength = active.total_strength();
+            let step = delta.min(active.remaining);
+            match def.interval {
+                Some(interval) if interval > 0.0 => {
+                    active.until_tick -= step;
+                    while active.until_tick <= 0.0 {
+                        run_actions(defs, &def.on_tick, stats, active.effect, active.source, strength, 1.0, &mut active.gains, pending);
+                        active.until_tick += interval;
+                    }
                 }
+                // Without an interval, tick amounts are per second.
+                None => run_actions(defs, &def.on_tick, stats, active.effect, active.source, strength, step, &mut active.gains, pending),
+                // Would never stop ticking, these are taken out when the definitions are loaded.
+                Some(_) => {}
+            }
+            active.remaining -= delta;
+            if active.remaining <= 0.0 {
+                active.end(defs, stats, true, pending);
+                expired.push(active.effect);
             }
+        }
+        self.effects.retain(|active| active.remaining > 0.0);
+        expired
+    }
+}
+
+/// Asks for an effect to be put on an entity.
+#[derive(Debug, Clone, Copy)]
+pub struct ApplyEffect {
+    pub target: Entity,
+    pub source: Option<Entity>,
+    pub effect: Effect,
+    pub strength: f32,
+    pub duration: f32,
+}
+
+/// Asks for the effects in any of the categories to be removed from an entity.
+pub struct DispelEffects {
+    pub target: Entity,
+    pub categories: EffectCategory,
+}
+
+/// Asks for every instance of an effect to be removed from an entity.
+pub struct RemoveEffect {
+    pub target: Entity,
+    pub effect: Effect,
+}
+
+pub struct EffectApplied {
+    pub entity: Entity,
+    pub effect: Effect,
+    pub source: Option<Entity>,
+    pub stacks: u32,
+}
 
+// Sent when an effect couldn't be applied because the entity is immune to it.
+pub struct EffectResisted {
+    pub entity: Entity,
+    pub effect: Effect,
+}
+
+pub struct EffectEnded {
+    pub entity: Entity,
+    pub effect: Effect,
+    pub reason: EffectRemoved,
+}
+
+fn send_damage(entity: Entity, damage: Vec<PendingDamage>, attacks: &mut EventWriter<Attack>) {
+    for d in damage {
+        attacks.send(Attack {
+            attacker: d.source,
+            target: entity,
+            dmg: d.dmg,
+            source: DamageSource::Effect(d.effect),
+        });
+    }
+}
+
+fn apply_effects(
+    mut commands: Commands,
+    defs: Res<Effects>,
+    mut requests: EventReader<ApplyEffect>,
+    mut stats: Query<&mut Stats>,
+    mut effects: Query<&mut ActiveEffects>,
+    mut applied: EventWriter<EffectApplied>,
+    mut resisted: EventWriter<EffectResisted>,
+    mut attacks: EventWriter<Attack>,
+) {
+    let mut new_effects: HashMap<Entity, ActiveEffects> = HashMap::new();
+    let mut queue: Vec<(ApplyEffect, u32)> = requests
+        .iter()
+        .map(|request| (*request, 0))
+        .collect();
+    while let Some((request, depth)) = queue.pop() {
+        let mut stats = match stats.get_mut(request.target) {
+            Ok(stats) => stats,
+            Err(_) => continue,
+        };
+        let mut pending = Pending::default();
+        let result = match effects.get_mut(request.target) {
+            Ok(mut effects) => effects.apply(&defs, &mut stats, &request, &mut pending),
+            Err(_) => new_effects
+                .entry(request.target)
+                .or_default()
+                .apply(&defs, &mut stats, &request, &mut pending),
+        };
+        match result {
+            Some(stacks) => applied.send(EffectApplied {
+                entity: request.target,
+                effect: request.effect,
+                source: request.source,
+                stacks,
+            }),
+            None => resisted.send(EffectResisted {
+                entity: request.target,
+                effect: request.effect,
+            }),
+        }
+        send_damage(request.target, pending.damage, &mut attacks);
+        // Effects that apply effects when they start could otherwise keep going forever.
+        if depth < MAX_TRIGGER_DEPTH {
+            for p in pending.effects {
+                queue.push((
+                    ApplyEffect {
+                        target: request.target,
+                        source: p.source,
+                        effect: p.effect,
+                        strength: p.strength,
+                        duration: p.duration,
+                    },
+                    depth + 1,
+                ));
+            }
         }
     }
+    for (entity, effects) in new_effects {
+        commands.entity(entity).insert(effects);
+    }
 }
 
+fn send_pending(
+    entity: Entity,
+    pending: Pending,
+    apply: &mut EventWriter<ApplyEffect>,
+    attacks: &mut EventWriter<Attack>,
+) {
+    for p in pending.effects {
+        apply.send(ApplyEffect {
+            target: entity,
+            source: p.source,
+            effect: p.effect,
+            strength: p.strength,
+            duration: p.duration,
+        });
+    }
+    send_damage(entity, pending.damage, attacks);
+}
 
-effects! { 
-    Fire,
-    update => |stats: &mut Stats, data: &mut EffectData, delta: f32| {
-        stats.apply_damage(&dmg!(Fire: data.strength * delta));
+fn update_effects(
+    time: Res<Time>,
+    defs: Res<Effects>,
+    mut dispels: EventReader<DispelEffects>,
+    mut removals: EventReader<RemoveEffect>,
+    mut query: Query<(Entity, &mut ActiveEffects, &mut Stats)>,
+    mut apply: EventWriter<ApplyEffect>,
+    mut attacks: EventWriter<Attack>,
+    mut ended: EventWriter<EffectEnded>,
+) {
+    for dispel in dispels.iter() {
+        if let Ok((_, mut effects, mut stats)) = query.get_mut(dispel.target) {
+            let mut pending = Pending::default();
+            for effect in effects.dispel(&defs, &mut stats, dispel.categories, &mut pending) {
+                ended.send(EffectEnded {
+                    entity: dispel.target,
+                    effect,
+                    reason: EffectRemoved::Dispelled,
+                });
+            }
+            send_pending(dispel.target, pending, &mut apply, &mut attacks);
+        }
     }
-    Poison,
-    update => |stats: &mut Stats, data: &mut EffectData, delta: f32| {
-        stats.apply_damage(&dmg!(Curse: data.strength * delta));
+    for removal in removals.iter() {
+        if let Ok((_, mut effects, mut stats)) = query.get_mut(removal.target) {
+            let mut pending = Pending::default();
+            if effects.remove(&defs, &mut stats, removal.effect, &mut pending) {
+                ended.send(EffectEnded {
+                    entity: removal.target,
+                    effect: removal.effect,
+                    reason: EffectRemoved::Removed,
+                });
+            }
+            send_pending(removal.target, pending, &mut apply, &mut attacks);
+        }
     }
-    Slow,
-    start => |stats: &mut Stats, data: &mut EffectData| {
-        stats.mul_stat(Stat::Speed, distribution(data.strength, 4.0));
-    },
-    end => |stats: &mut Stats, data: &mut EffectData| {
-        stats.mul_stat(Stat::Speed, 1.0 / distribution(data.strength, 4.0));
+
+    let delta = time.delta_seconds();
+    for (entity, mut effects, mut stats) in query.iter_mut() {
+        let mut pending = Pending::default();
+        for effect in effects.update(&defs, &mut stats, delta, &mut pending) {
+            ended.send(EffectEnded {
+                entity,
+                effect,
+                reason: EffectRemoved::Expired,
+            });
+        }
+        send_pending(entity, pending, &mut apply, &mut attacks);
+    }
+}
+
+fn check_effects(mut defs: ResMut<Effects>, mut printer: EventWriter<Message>) {
+    for def in defs.iter_mut() {
+        if let Some(interval) = def.interval.filter(|interval| *interval <= 0.0) {
+            printer.send(Message::error(format!(
+                "Effect {} has an interval of {}, it has to be above 0. Its tick amounts are per second instead.",
+                def.get_string_id(),
+                interval
+            )));
+            def.interval = None;
+        }
     }
-}
\ No newline at end of file
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_startup_system_to_stage("generate", check_effects.system())
+        .add_event::<ApplyEffect>()
+        .add_event::<DispelEffects>()
+        .add_event::<RemoveEffect>()
+        .add_event::<EffectApplied>()
+        .add_event::<EffectResisted>()
+        .add_event::<EffectEnded>()
+        .add_system(apply_effects.system().label("apply_effects").after("resolve_attacks"))
+        .add_system(update_effects.system().after("apply_effects"));
+}
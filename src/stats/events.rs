@@ -0,0 +1,188 @@
+use bevy::prelude::*;
+
+use crate::chunk::{ChunkData, Chunks, Seed, WorldGen, CHUNK_SIZE};
+use crate::defs::Block;
+use crate::stats::{
+    Attack, CombatTracker, DamageSource, Dmg, DmgType, ResourceKey, StatKey, Stats, COMFORTABLE_AMBIENT_TEMPERATURE,
+};
+
+pub struct StatChanged {
+    pub entity: Entity,
+    pub stat: StatKey,
+    pub old: f32,
+    pub new: f32,
+}
+
+pub struct ResourceChanged {
+    pub entity: Entity,
+    pub resource: ResourceKey,
+    pub old: f32,
+    pub new: f32,
+}
+
+// Sent when a resource reaches zero, e.g. when an entity dies from running out of HP.
+pub struct ResourceDepleted {
+    pub entity: Entity,
+    pub resource: ResourceKey,
+}
+
+// The surroundings of an entity, entities without one are kept at a comfortable temperature. The temperature is the
+// one of the world generator where the entity is.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub temperature: f32,
+    // The block the entity stands on, `None` while in the air.
+    pub block_below: Option<Block>,
+    // Hours since midnight.
+    pub time_of_day: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            temperature: COMFORTABLE_AMBIENT_TEMPERATURE,
+            block_below: None,
+            time_of_day: WorldClock::default().time_of_day,
+        }
+    }
+}
+
+// Real seconds in a day of the world.
+const DAY_LENGTH: f32 = 20.0 * 60.0;
+// `Environment::time_of_day` is only updated once the clock has moved this many hours, a minute, so conditions on it
+// aren't checked again every frame.
+const TIME_OF_DAY_STEP: f32 = 1.0 / 60.0;
+
+/// The time in the world.
+pub struct WorldClock {
+    // Hours since midnight.
+    pub time_of_day: f32,
+}
+
+impl Default for WorldClock {
+    fn default() -> Self {
+        Self { time_of_day: 12.0 }
+    }
+}
+
+fn update_world_clock(time: Res<Time>, mut clock: ResMut<WorldClock>) {
+    clock.time_of_day = (clock.time_of_day + time.delta_seconds() * 24.0 / DAY_LENGTH).rem_euclid(24.0);
+}
+
+// The block right under `position`, `None` for air or a chunk that isn't loaded.
+fn block_below(position: Vec3, chunks: &Chunks, data: &Query<&ChunkData>) -> Option<Block> {
+    let below = (position - Vec3::new(0.0, 0.1, 0.0)).floor().as_i32();
+    let size = CHUNK_SIZE as i32;
+    let chunk = IVec3::new(below.x.div_euclid(size), below.y.div_euclid(size), below.z.div_euclid(size));
+    let local = IVec3::new(below.x.rem_euclid(size), below.y.rem_euclid(size), below.z.rem_euclid(size));
+    let data = data.get(*chunks.get(chunk)?).ok()?;
+    data.get(local.x, local.y, local.z)?.block()
+}
+
+fn update_environment(
+    clock: Res<WorldClock>,
+    chunks: Option<Res<Chunks>>,
+    generator: Option<Res<WorldGen>>,
+    seed: Option<Res<Seed>>,
+    data: Query<&ChunkData>,
+    mut query: Query<(&GlobalTransform, &mut Environment)>,
+) {
+    for (transform, mut environment) in query.iter_mut() {
+        // Only written when they change, conditions are checked again whenever the environment changes.
+        let block = chunks.as_ref().and_then(|chunks| block_below(transform.translation, chunks, &data));
+        if environment.block_below != block {
+            environment.block_below = block;
+        }
+        let temperature = match (&generator, &seed) {
+            (Some(generator), Some(seed)) => generator.0.temperature(transform.translation, seed.0),
+            _ => None,
+        }
+        .unwrap_or(COMFORTABLE_AMBIENT_TEMPERATURE);
+        if environment.temperature != temperature {
+            environment.temperature = temperature;
+        }
+        let since = (clock.time_of_day - environment.time_of_day).rem_euclid(24.0);
+        if since >= TIME_OF_DAY_STEP {
+            environment.time_of_day = clock.time_of_day;
+        }
+    }
+}
+
+fn update_resources(
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Stats, Option<&Environment>, Option<&CombatTracker>)>,
+    mut attacks: EventWriter<Attack>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut stats, environment, tracker) in query.iter_mut() {
+        if let Some(environment) = environment {
+            stats.set_ambient_temperature(environment.temperature);
+        }
+        if let Some(tracker) = tracker {
+            stats.set_combat_status(tracker.status());
+        }
+        let damage = stats.update_resources(delta);
+        if damage > 0.0 {
+            attacks.send(Attack {
+                attacker: None,
+                target: entity,
+                dmg: Dmg::create(vec![(DmgType::Pure, damage)].into_iter().collect()),
+                source: DamageSource::Environment,
+            });
+        }
+    }
+}
+
+fn send_stat_events(
+    mut query: Query<(Entity, &mut Stats)>,
+    mut stat_changed: EventWriter<StatChanged>,
+    mut resource_changed: EventWriter<ResourceChanged>,
+    mut resource_depleted: EventWriter<ResourceDepleted>,
+) {
+    for (entity, mut stats) in query.iter_mut() {
+        for (stat, old) in stats.drain_stat_changes() {
+            let new = stats[stat];
+            if old != new {
+                stat_changed.send(StatChanged {
+                    entity,
+                    stat: StatKey::Stat(stat),
+                    old,
+                    new,
+                });
+            }
+        }
+        for (stat, old) in stats.drain_mod_stat_changes() {
+            stat_changed.send(StatChanged {
+                entity,
+                stat: StatKey::Mod(stat),
+                old,
+                new: stats.mod_stat(stat),
+            });
+        }
+        for (resource, old) in stats.drain_resource_changes() {
+            let new = stats[resource];
+            if old != new {
+                resource_changed.send(ResourceChanged {
+                    entity,
+                    resource,
+                    old,
+                    new,
+                });
+            }
+            if old > 0.0 && new <= 0.0 {
+                resource_depleted.send(ResourceDepleted { entity, resource });
+            }
+        }
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.add_event::<StatChanged>()
+        .add_event::<ResourceChanged>()
+        .add_event::<ResourceDepleted>()
+        .init_resource::<WorldClock>()
+        .add_system(update_world_clock.system().label("update_world_clock"))
+        .add_system(update_environment.system().label("update_environment").after("update_world_clock"))
+        .add_system(update_resources.system().label("update_resources").after("track_combat"))
+        .add_system_to_stage(CoreStage::PostUpdate, send_stat_events.system().label("send_stat_events"));
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{Definition, Effect, Interaction, Interactions};
+use crate::stats::{ActiveEffects, Dmg, DmgType};
+
+/// What happens when damage of one of the types of an interaction hits an entity with its effect. Percentages are
+/// of the damage of those types, after the attacker's stats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum InteractionOutcome {
+    // Multiplies the damage of the matching types.
+    Amplify { multiplier: f32 },
+    // Adds damage of another type.
+    Bonus { ty: DmgType, percent: f32 },
+    // Removes the effect from the target.
+    Consume,
+    // Applies an effect, by `namespace:id`, to the target.
+    Apply { effect: String, strength: f32, duration: f32 },
+    // Deals a part of the damage to the closest other entities.
+    Chain { radius: f32, percent: f32, max_targets: u32 },
+    // Puts the effect on other entities close by, with a part of its strength.
+    Spread { radius: f32, percent: f32, duration: f32 },
+}
+
+/// An interaction that applies to an attack.
+pub struct Interacted {
+    pub interaction: Interaction,
+    pub effect: Effect,
+    // Damage of the matching types, before the interaction changed it.
+    pub amount: f32,
+    // Strength of the effect on the target.
+    pub strength: f32,
+}
+
+/// Finds the interactions between the damage and the effects on the target, and changes the damage by them.
+/// Outcomes that don't change the damage are left for after it has been applied.
+pub fn interact(defs: &Interactions, dmg: &mut Dmg, effects: &ActiveEffects) -> Vec<Interacted> {
+    let mut interacted = vec![];
+    for def in defs.iter() {
+        let active = match effects.iter().find(|active| active.effect() == def.effect) {
+            Some(active) => active,
+            None => continue,
+        };
+        let amount: f32 = def.damage_types.iter().map(|ty| dmg.get(*ty)).sum();
+        if amount <= 0.0 {
+            continue;
+        }
+        for outcome in &def.outcomes {
+            match outcome {
+                InteractionOutcome::Amplify { multiplier } => {
+                    for ty in &def.damage_types {
+                        dmg.scale(*ty, *multiplier);
+                    }
+                }
+                InteractionOutcome::Bonus { ty, percent } => dmg.add(*ty, amount * percent),
+                _ => {}
+            }
+        }
+        interacted.push(Interacted {
+            interaction: Interaction::from(def.get_id()),
+            effect: def.effect,
+            amount,
+            strength: active.total_strength(),
+        });
+    }
+    interacted
+}
@@ -0,0 +1,242 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::stats::{BaseStat, BaseStats, Stats};
+
+/// How much xp is needed to go from one level to the next.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum XpCurve {
+    Linear { base: f32, per_level: f32 },
+    Polynomial { base: f32, exponent: f32 },
+    Exponential { base: f32, growth: f32 },
+    // Xp needed for each level, the last entry is used for all levels after it.
+    Table(Vec<f32>),
+}
+
+impl Default for XpCurve {
+    fn default() -> Self {
+        Self::Polynomial {
+            base: 100.0,
+            exponent: 1.5,
+        }
+    }
+}
+
+impl XpCurve {
+    pub fn xp_to_next(&self, level: u32) -> f32 {
+        let l = level as f32;
+        match self {
+            Self::Linear { base, per_level } => base + per_level * l,
+            Self::Polynomial { base, exponent } => base * (l + 1.0).powf(*exponent),
+            Self::Exponential { base, growth } => base * growth.powf(l),
+            Self::Table(table) => table
+                .get(level as usize)
+                .or_else(|| table.last())
+                .copied()
+                .unwrap_or(f32::INFINITY),
+        }
+    }
+}
+
+/// What xp was gained for. Crafting and tool use get their own sources once there is crafting and using tools, until
+/// then they can give `Other`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum XpSource {
+    Kill { victim_level: u32 },
+    // Damaged something within the assist window before someone else killed it.
+    Assist { victim_level: u32 },
+    Other(f32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelSettings {
+    pub curve: XpCurve,
+    pub points_per_level: u32,
+    pub max_level: u32,
+
+    pub kill_xp: f32,
+    pub kill_xp_per_level: f32,
+    // Part of the kill xp given for an assist.
+    pub assist_xp: f32,
+}
+
+impl Default for LevelSettings {
+    fn default() -> Self {
+        Self {
+            curve: Default::default(),
+            points_per_level: 3,
+            max_level: 100,
+            kill_xp: 10.0,
+            kill_xp_per_level: 5.0,
+            assist_xp: 0.5,
+        }
+    }
+}
+
+impl LevelSettings {
+    pub fn xp_for(&self, source: XpSource) -> f32 {
+        match source {
+            XpSource::Kill { victim_level } => {
+                self.kill_xp + self.kill_xp_per_level * victim_level as f32
+            }
+            XpSource::Assist { victim_level } => {
+                self.assist_xp * self.xp_for(XpSource::Kill { victim_level })
+            }
+            XpSource::Other(xp) => xp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationError {
+    NoPoints,
+    CannotLevel(BaseStat),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Level {
+    level: u32,
+    xp: f32,
+    unspent_points: u32,
+    allocated: BaseStats,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            level: 0,
+            xp: 0.0,
+            unspent_points: 0,
+            allocated: BaseStats::zeroes(),
+        }
+    }
+}
+
+impl Level {
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn xp(&self) -> f32 {
+        self.xp
+    }
+
+    pub fn unspent_points(&self) -> u32 {
+        self.unspent_points
+    }
+
+    pub fn allocated(&self, stat: BaseStat) -> f32 {
+        self.allocated[stat]
+    }
+
+    /// Adds xp and returns how many levels were gained.
+    pub fn add_xp(&mut self, xp: f32, settings: &LevelSettings) -> u32 {
+        if self.level >= settings.max_level {
+            return 0;
+        }
+        self.xp += xp;
+        let mut gained = 0;
+        while self.level < settings.max_level {
+            let needed = settings.curve.xp_to_next(self.level);
+            if self.xp < needed {
+                break;
+            }
+            self.xp -= needed;
+            self.level += 1;
+            self.unspent_points += settings.points_per_level;
+            gained += 1;
+        }
+        if self.level >= settings.max_level {
+            self.xp = 0.0;
+        }
+        gained
+    }
+}
+
+impl Stats {
+    pub fn add_xp(&mut self, xp: f32, settings: &LevelSettings) -> u32 {
+        self.level_mut().add_xp(xp, settings)
+    }
+
+    pub fn allocate_point(&mut self, stat: BaseStat) -> Result<(), AllocationError> {
+        if !stat.can_level() {
+            return Err(AllocationError::CannotLevel(stat));
+        }
+        let level = self.level_mut();
+        if level.unspent_points == 0 {
+            return Err(AllocationError::NoPoints);
+        }
+        level.unspent_points -= 1;
+        level.allocated[stat] += 1.0;
+        self.add_base(stat, 1.0);
+        Ok(())
+    }
+}
+
+/// Spends level-up points automatically, used for npcs. Points go to the stat that is furthest below its weight.
+pub struct AutoAllocate {
+    pub weights: Vec<(BaseStat, f32)>,
+}
+
+impl AutoAllocate {
+    pub fn allocate(&self, stats: &mut Stats) {
+        while stats.level().unspent_points > 0 {
+            let next = self
+                .weights
+                .iter()
+                .filter(|(stat, weight)| stat.can_level() && *weight > 0.0)
+                .min_by(|(a, wa), (b, wb)| {
+                    let a = stats.level().allocated(*a) / wa;
+                    let b = stats.level().allocated(*b) / wb;
+                    a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+                });
+            match next {
+                Some((stat, _)) => {
+                    if stats.allocate_point(*stat).is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+pub struct GainXp {
+    pub entity: Entity,
+    pub source: XpSource,
+}
+
+pub struct LevelUp {
+    pub entity: Entity,
+    pub level: u32,
+}
+
+fn gain_xp(
+    settings: Res<LevelSettings>,
+    mut events: EventReader<GainXp>,
+    mut level_ups: EventWriter<LevelUp>,
+    mut query: Query<(&mut Stats, Option<&AutoAllocate>)>,
+) {
+    for event in events.iter() {
+        if let Ok((mut stats, auto)) = query.get_mut(event.entity) {
+            let gained = stats.add_xp(settings.xp_for(event.source), &settings);
+            if gained > 0 {
+                if let Some(auto) = auto {
+                    auto.allocate(&mut stats);
+                }
+                level_ups.send(LevelUp {
+                    entity: event.entity,
+                    level: stats.level().level(),
+                });
+            }
+        }
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.init_resource::<LevelSettings>()
+        .add_event::<GainXp>()
+        .add_event::<LevelUp>()
+        .add_system(gain_xp.system());
+}
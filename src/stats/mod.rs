@@ -1,12 +1,66 @@
+use bevy::prelude::*;
+
 mod stat;
 mod base_stat;
 mod resource;
 mod effect;
 mod damage;
 mod stats;
+mod events;
+mod level;
+mod cap;
+mod rng;
+mod ability;
+mod trigger;
+mod combat;
+mod credit;
+mod interaction;
+mod custom;
+mod spatial;
+mod aura;
+mod condition;
+mod check;
+mod regen;
 
-pub use stat::{Stat};
+pub use stat::{Stat, STAT_ITER};
 pub use base_stat::{BaseStat, BaseStats};
-pub use resource::{Resource, ResourceConsumption, ConsumptionType};
-pub use damage::{Dmg, DmgType, DmgResult};
-pub use stats::{StatGain, Stats, StatAccessor};
\ No newline at end of file
+pub use resource::{Resource, ResourceConsumption, ConsumptionType, NORMAL_TEMPERATURE, SAFE_TEMPERATURE_RANGE, COMFORTABLE_AMBIENT_TEMPERATURE};
+pub use damage::{Dmg, DmgType, DmgTypeInfo, DmgTable, Reduction, DmgResult, DmgAmount, DamageReport, DMG_TYPE_ITER};
+pub use stats::{StatGain, Stats, StatAccessor, StatExplanation};
+pub use custom::{ModKey, ModStat, ModResource, StatKey, ResourceKey, ModValues, ModStatInfo, ModResourceInfo, CustomTable, CustomStatTable};
+pub use cap::{SoftCap, StatCap, CapTable, StatCaps};
+pub use rng::{CombatRng, ThreadCombatRng, SeededRng, CombatRandom};
+pub use events::{Environment, WorldClock, StatChanged, ResourceChanged, ResourceDepleted};
+pub use effect::{EffectCategory, EffectAction, Stacking, ActiveEffect, ActiveEffects, SavedEffect, SavedEffects, EffectRemoved, ApplyEffect, DispelEffects, RemoveEffect, EffectApplied, EffectResisted, EffectEnded};
+pub use ability::{Targeting, AbilityEffect, CastTarget, CastError, Abilities, Buffs, validate_cast, CastAbility, CastStarted, CastFailed, AbilityUsed};
+pub use trigger::{Trigger, TriggerEvent, TriggerTarget, TriggerSource, Triggers, Reaction, MAX_TRIGGER_DEPTH};
+pub use combat::{Attack, DamageDealt, DamageSource};
+pub use interaction::{InteractionOutcome, Interacted, interact};
+pub use spatial::SpatialIndex;
+pub use aura::{Faction, AuraFilter, AuraEffect, AuraStacking, Aura, Auras, AuraGains, AURA_PULSE};
+pub use condition::{Equipment, Condition, ConditionContext, ConditionalGain, ConditionalGains};
+pub use check::{CheckFormula, CheckTerm, CheckResult, ContestResult, check, contest, CHECK_DIE, DEGREE_STEP};
+pub use regen::{CombatState, CombatStatus, CombatTracker, RegenRule, RegenTable, RegenRules, COMBAT_TIMEOUT};
+pub use credit::{DamageRecord, DamageHistory, Killed, ASSIST_WINDOW};
+pub use level::{Level, LevelSettings, XpCurve, XpSource, AllocationError, AutoAllocate, GainXp, LevelUp};
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        events::add_systems(app);
+        damage::add_systems(app);
+        level::add_systems(app);
+        cap::add_systems(app);
+        custom::add_systems(app);
+        ability::add_systems(app);
+        combat::add_systems(app);
+        credit::add_systems(app);
+        effect::add_systems(app);
+        spatial::add_systems(app);
+        aura::add_systems(app);
+        condition::add_systems(app);
+        regen::add_systems(app);
+        app.init_resource::<CombatRandom>();
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{Definition, Message, Regenerations};
+use crate::stats::{DamageDealt, ResourceKey, Stats};
+
+/// Seconds after the last damage dealt or taken until an entity is out of combat.
+pub const COMBAT_TIMEOUT: f32 = 5.0;
+
+/// What an entity is doing, regeneration is scaled by it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CombatState {
+    InCombat,
+    OutOfCombat,
+    Resting,
+    Sleeping,
+}
+
+impl Default for CombatState {
+    fn default() -> Self {
+        Self::OutOfCombat
+    }
+}
+
+/// The combat state of an entity as `Stats` sees it when regenerating.
+#[derive(Debug, Clone, Copy)]
+pub struct CombatStatus {
+    pub state: CombatState,
+    pub since_damage_taken: f32,
+}
+
+impl Default for CombatStatus {
+    fn default() -> Self {
+        Self {
+            state: CombatState::OutOfCombat,
+            since_damage_taken: f32::INFINITY,
+        }
+    }
+}
+
+/// Keeps track of when an entity last fought. Resting and sleeping are set by whatever makes the entity rest, and
+/// are interrupted by combat.
+#[derive(Debug, Clone)]
+pub struct CombatTracker {
+    since_damage_dealt: f32,
+    since_damage_taken: f32,
+    resting: bool,
+    sleeping: bool,
+}
+
+impl Default for CombatTracker {
+    fn default() -> Self {
+        Self {
+            since_damage_dealt: f32::INFINITY,
+            since_damage_taken: f32::INFINITY,
+            resting: false,
+            sleeping: false,
+        }
+    }
+}
+
+impl CombatTracker {
+    pub fn state(&self) -> CombatState {
+        if self.since_damage_dealt.min(self.since_damage_taken) < COMBAT_TIMEOUT {
+            CombatState::InCombat
+        } else if self.sleeping {
+            CombatState::Sleeping
+        } else if self.resting {
+            CombatState::Resting
+        } else {
+            CombatState::OutOfCombat
+        }
+    }
+
+    pub fn status(&self) -> CombatStatus {
+        CombatStatus {
+            state: self.state(),
+            since_damage_taken: self.since_damage_taken,
+        }
+    }
+
+    pub fn since_damage_taken(&self) -> f32 {
+        self.since_damage_taken
+    }
+
+    pub fn set_resting(&mut self, resting: bool) {
+        self.resting = resting;
+    }
+
+    pub fn set_sleeping(&mut self, sleeping: bool) {
+        self.sleeping = sleeping;
+    }
+
+    pub fn dealt_damage(&mut self) {
+        self.since_damage_dealt = 0.0;
+        self.resting = false;
+    }
+
+    pub fn took_damage(&mut self) {
+        self.since_damage_taken = 0.0;
+        self.resting = false;
+        self.sleeping = false;
+    }
+
+    fn tick(&mut self, delta: f32) {
+        self.since_damage_dealt += delta;
+        self.since_damage_taken += delta;
+    }
+}
+
+/// How the regeneration of a resource changes with the combat state, from its `Regeneration` definition.
+#[derive(Debug, Clone, Copy)]
+pub struct RegenRule {
+    pub in_combat: f32,
+    pub out_of_combat: f32,
+    pub resting: f32,
+    pub sleeping: f32,
+    // Seconds after taking damage during which the resource doesn't regenerate.
+    pub delay: f32,
+    // Part of the max gained every second while resting or sleeping, on top of the regeneration.
+    pub rest_burst: f32,
+}
+
+impl Default for RegenRule {
+    fn default() -> Self {
+        Self {
+            in_combat: 1.0,
+            out_of_combat: 1.0,
+            resting: 1.0,
+            sleeping: 1.0,
+            delay: 0.0,
+            rest_burst: 0.0,
+        }
+    }
+}
+
+impl RegenRule {
+    pub fn multiplier(&self, state: CombatState) -> f32 {
+        match state {
+            CombatState::InCombat => self.in_combat,
+            CombatState::OutOfCombat => self.out_of_combat,
+            CombatState::Resting => self.resting,
+            CombatState::Sleeping => self.sleeping,
+        }
+    }
+
+    /// Regeneration per second of a resource with `regen` and `max` for an entity in `status`. Only gains are scaled,
+    /// resources that run down, like hunger, keep doing so.
+    pub fn apply(&self, status: CombatStatus, regen: f32, max: f32) -> f32 {
+        if regen <= 0.0 {
+            return regen;
+        }
+        if status.since_damage_taken < self.delay {
+            return 0.0;
+        }
+        let burst = match status.state {
+            CombatState::Resting | CombatState::Sleeping => self.rest_burst * max,
+            _ => 0.0,
+        };
+        regen * self.multiplier(status.state) + burst
+    }
+}
+
+/// Regeneration rules for every resource that has one, resources without one always regenerate in full.
+#[derive(Debug, Clone, Default)]
+pub struct RegenTable {
+    rules: HashMap<ResourceKey, RegenRule>,
+}
+
+impl RegenTable {
+    pub fn insert(&mut self, resource: ResourceKey, rule: RegenRule) {
+        self.rules.insert(resource, rule);
+    }
+
+    pub fn get(&self, resource: ResourceKey) -> Option<&RegenRule> {
+        self.rules.get(&resource)
+    }
+}
+
+/// Regeneration rules given to new `Stats`, `None` if no mod defines any.
+#[derive(Default)]
+pub struct RegenRules(pub Option<Arc<RegenTable>>);
+
+fn build_regen_table(mut commands: Commands, defs: Res<Regenerations>, mut printer: EventWriter<Message>) {
+    let mut table = RegenTable::default();
+    for def in defs.iter() {
+        let resource = match serde_json::from_value::<ResourceKey>(serde_json::Value::String(def.resource.clone())) {
+            Ok(resource) => resource,
+            Err(_) => {
+                printer.send(Message::error(format!(
+                    "Regeneration {} is for unknown resource {}.",
+                    def.get_string_id(),
+                    def.resource
+                )));
+                continue;
+            }
+        };
+        table.insert(
+            resource,
+            RegenRule {
+                in_combat: def.in_combat.unwrap_or(1.0),
+                out_of_combat: def.out_of_combat.unwrap_or(1.0),
+                resting: def.resting.unwrap_or(1.0),
+                sleeping: def.sleeping.unwrap_or(1.0),
+                delay: def.delay,
+                rest_burst: def.rest_burst,
+            },
+        );
+    }
+    let empty = table.rules.is_empty();
+    commands.insert_resource(RegenRules(if empty { None } else { Some(Arc::new(table)) }));
+}
+
+fn apply_regen_table(rules: Res<RegenRules>, mut query: Query<&mut Stats, Added<Stats>>) {
+    if let Some(rules) = &rules.0 {
+        for mut stats in query.iter_mut() {
+            stats.set_regen_table(Some(rules.clone()));
+        }
+    }
+}
+
+fn track_combat(mut commands: Commands, mut dealt: EventReader<DamageDealt>, mut trackers: Query<&mut CombatTracker>) {
+    let mut new_trackers: HashMap<Entity, CombatTracker> = HashMap::new();
+    for event in dealt.iter() {
+        if !event.report.is_hit() || event.report.total <= 0.0 {
+            continue;
+        }
+        let involved = event
+            .attacker
+            .filter(|attacker| *attacker != event.target)
+            .map(|attacker| (attacker, false))
+            .into_iter()
+            .chain(Some((event.target, true)));
+        for (entity, took) in involved {
+            let mut tracker = trackers.get_mut(entity).ok();
+            let tracker = match &mut tracker {
+                Some(tracker) => &mut **tracker,
+                None => new_trackers.entry(entity).or_default(),
+            };
+            if took {
+                tracker.took_damage();
+            } else {
+                tracker.dealt_damage();
+            }
+        }
+    }
+    for (entity, tracker) in new_trackers {
+        commands.entity(entity).insert(tracker);
+    }
+}
+
+fn update_trackers(time: Res<Time>, mut query: Query<&mut CombatTracker>) {
+    let delta = time.delta_seconds();
+    for mut tracker in query.iter_mut() {
+        tracker.tick(delta);
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.init_resource::<RegenRules>()
+        .add_startup_system_to_stage("generate", build_regen_table.system())
+        .add_system_to_stage(CoreStage::PreUpdate, apply_regen_table.system())
+        .add_system(track_combat.system().label("track_combat").after("resolve_attacks"))
+        .add_system(update_trackers.system().before("track_combat"));
+}
@@ -1,14 +1,25 @@
-use crate::stats::{Stat, BaseStat, Stats};
+use std::collections::HashMap;
+use std::ops::Index;
+
+use crate::stats::{ResourceKey, Stat, Stats};
 use crate::count_idents;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 macro_rules! resources {
-    ($($name:ident, max => $max:expr, regen => $regen:expr) *) => {
+    (@start $stats:ident, $max:expr) => {
+        $max($stats)
+    };
+    (@start $stats:ident, $max:expr, $start:expr) => {
+        $start($stats)
+    };
+    ($($name:ident, max => $max:expr, regen => $regen:expr $(, start => $start:expr)?) *) => {
 
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
         pub enum Resource {
             $($name), *
         }
-        const NUM_RESOURCES: usize = count_idents!($($name), *);
+        pub const NUM_RESOURCES: usize = count_idents!($($name), *);
+        pub const RESOURCE_ITER: [Resource; NUM_RESOURCES] = [$(Resource::$name), *];
         const NAMES: [&'static str; NUM_RESOURCES] = [$(stringify!($name)), *];
         impl Resource {
             pub fn name(&self) -> &'static str {
@@ -26,10 +37,23 @@ macro_rules! resources {
                     $(Resource::$name => $max(stats)), *
                 }
             }
+
+            // The value the resource starts at and is restored to, the max unless `start` is given.
+            pub fn start(&self, stats: &Stats) -> f32 {
+                match self {
+                    $(Resource::$name => resources!(@start stats, $max $(, $start)?)), *
+                }
+            }
         }
     }
 }
 
+// Body temperature that is comfortable, and how far it can stray from it before it starts hurting.
+pub const NORMAL_TEMPERATURE: f32 = 37.0;
+pub const SAFE_TEMPERATURE_RANGE: f32 = 2.0;
+// Outside temperature that keeps the body at `NORMAL_TEMPERATURE`.
+pub const COMFORTABLE_AMBIENT_TEMPERATURE: f32 = 20.0;
+
 resources! {
     HP,
     max => |stats: &Stats| {
@@ -45,14 +69,130 @@ resources! {
     regen => |stats: &Stats| {
         stats[Stat::ManaRegen]
     }
+    Stamina,
+    max => |stats: &Stats| {
+        stats[Stat::MaxStamina]
+    },
+    regen => |stats: &Stats| {
+        stats[Stat::StaminaRegen]
+    }
+    // Hunger and Thirst count down, an entity is starving when they are empty.
+    Hunger,
+    max => |stats: &Stats| {
+        stats[Stat::MaxHunger]
+    },
+    regen => |stats: &Stats| {
+        -stats[Stat::HungerRate]
+    }
+    Thirst,
+    max => |stats: &Stats| {
+        stats[Stat::MaxThirst]
+    },
+    regen => |stats: &Stats| {
+        -stats[Stat::ThirstRate]
+    }
+    // In degrees, moves towards a temperature decided by the environment and how well the entity is insulated.
+    Temperature,
+    max => |_: &Stats| {
+        NORMAL_TEMPERATURE * 2.0
+    },
+    regen => |stats: &Stats| {
+        let ambient = stats.ambient_temperature() - COMFORTABLE_AMBIENT_TEMPERATURE;
+        let exposure = if ambient > 0.0 {
+            1.0 - stats[Stat::HeatInsulation]
+        } else {
+            1.0 - stats[Stat::ColdInsulation]
+        };
+        (NORMAL_TEMPERATURE + ambient * exposure - stats[Resource::Temperature]) * 0.05
+    },
+    start => |_: &Stats| {
+        NORMAL_TEMPERATURE
+    }
+}
+
+impl Resource {
+    // Needs don't regenerate on their own, and make other resources regenerate slower when they run out.
+    pub fn is_need(&self) -> bool {
+        matches!(self, Resource::Hunger | Resource::Thirst | Resource::Temperature)
+    }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum ConsumptionType {
     Percent(f32),
     Flat(f32),
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct ResourceConsumption {
-    pub resource: Resource,
+    pub resource: ResourceKey,
     pub ty: ConsumptionType,
-}
\ No newline at end of file
+}
+
+// Dense storage for the resources an entity has, `present` is a bitmask of which ones it has.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceValues {
+    data: [f32; NUM_RESOURCES],
+    present: u32,
+}
+
+// `present` has a bit for every resource.
+const _: () = assert!(NUM_RESOURCES <= 32, "more resources than ResourceValues::present has bits");
+
+impl ResourceValues {
+    pub fn contains(&self, resource: Resource) -> bool {
+        self.present & (1 << resource as u32) != 0
+    }
+
+    pub fn get(&self, resource: Resource) -> Option<f32> {
+        if self.contains(resource) {
+            Some(self.data[resource as usize])
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, resource: Resource) -> Option<&mut f32> {
+        if self.contains(resource) {
+            Some(&mut self.data[resource as usize])
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&mut self, resource: Resource, value: f32) -> Option<f32> {
+        let old = self.get(resource);
+        self.present |= 1 << resource as u32;
+        self.data[resource as usize] = value;
+        old
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Resource, f32)> + '_ {
+        RESOURCE_ITER.iter().filter(move |r| self.contains(**r)).map(move |r| (*r, self.data[*r as usize]))
+    }
+}
+
+impl Index<Resource> for ResourceValues {
+    type Output = f32;
+
+    // Resources the entity doesn't have are always 0.
+    fn index(&self, index: Resource) -> &Self::Output {
+        &self.data[index as usize]
+    }
+}
+
+impl Serialize for ResourceValues {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for ResourceValues {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut values = Self::default();
+        for (resource, value) in HashMap::<Resource, f32>::deserialize(deserializer)? {
+            values.insert(resource, value);
+        }
+        Ok(values)
+    }
+}
@@ -0,0 +1,69 @@
+use rand::{thread_rng, Rng};
+
+/// Source of randomness for combat and stat rolls.
+pub trait CombatRng {
+    /// Uniform value in [0, 1).
+    fn roll(&mut self) -> f32;
+
+    fn chance(&mut self, chance: f32) -> bool {
+        self.roll() < chance
+    }
+}
+
+pub struct ThreadCombatRng;
+
+impl CombatRng for ThreadCombatRng {
+    fn roll(&mut self) -> f32 {
+        thread_rng().gen::<f32>()
+    }
+}
+
+/// Deterministic rng (splitmix64), the same seed always gives the same rolls on every platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Creates an independent rng, useful for giving every duel or entity its own stream.
+    pub fn fork(&mut self) -> Self {
+        Self::new(self.next_u64())
+    }
+}
+
+impl CombatRng for SeededRng {
+    fn roll(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// The rng used by combat systems.
+pub struct CombatRandom(pub Box<dyn CombatRng + Send + Sync>);
+
+impl CombatRandom {
+    pub fn seeded(seed: u64) -> Self {
+        Self(Box::new(SeededRng::new(seed)))
+    }
+
+    pub fn rng(&mut self) -> &mut dyn CombatRng {
+        &mut *self.0
+    }
+}
+
+impl Default for CombatRandom {
+    fn default() -> Self {
+        Self(Box::new(ThreadCombatRng))
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::stats::Stats;
+
+// Side of the cubes entities are bucketed into. Looking up a radius checks every cell it touches, or every cell that
+// is in use if there are fewer of those.
+const CELL_SIZE: f32 = 8.0;
+
+fn cell(position: Vec3) -> (i32, i32, i32) {
+    (
+        (position.x / CELL_SIZE).floor() as i32,
+        (position.y / CELL_SIZE).floor() as i32,
+        (position.z / CELL_SIZE).floor() as i32,
+    )
+}
+
+/// Entities with stats bucketed by position, so the ones close to a point can be found without checking every entity.
+/// Rebuilt at the start of every frame, positions are the ones the entities had then.
+#[derive(Default)]
+pub struct SpatialIndex {
+    cells: HashMap<(i32, i32, i32), Vec<(Entity, Vec3)>>,
+}
+
+impl SpatialIndex {
+    pub fn clear(&mut self) {
+        // Cells that were used are kept so their memory is reused by the next frame, the rest are dropped.
+        self.cells.retain(|_, entities| {
+            let used = !entities.is_empty();
+            entities.clear();
+            used
+        });
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vec3) {
+        self.cells.entry(cell(position)).or_default().push((entity, position));
+    }
+
+    /// Entities within `radius` of `center` together with their distance to it, in no particular order.
+    pub fn within(&self, center: Vec3, radius: f32) -> Vec<(Entity, f32)> {
+        let min = cell(center - Vec3::splat(radius));
+        let max = cell(center + Vec3::splat(radius));
+        let mut found = vec![];
+        let mut check = |entities: &Vec<(Entity, Vec3)>| {
+            for (entity, position) in entities {
+                let distance = position.distance(center);
+                if distance <= radius {
+                    found.push((*entity, distance));
+                }
+            }
+        };
+        let touched = [(min.0, max.0), (min.1, max.1), (min.2, max.2)]
+            .iter()
+            .map(|(min, max)| (*max as i64 - *min as i64 + 1) as u64)
+            .fold(1u64, |cells, side| cells.saturating_mul(side));
+        if touched <= self.cells.len() as u64 {
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    for z in min.2..=max.2 {
+                        if let Some(entities) = self.cells.get(&(x, y, z)) {
+                            check(entities);
+                        }
+                    }
+                }
+            }
+        } else {
+            let inside = |c: &(i32, i32, i32)| {
+                (min.0..=max.0).contains(&c.0) && (min.1..=max.1).contains(&c.1) && (min.2..=max.2).contains(&c.2)
+            };
+            for (_, entities) in self.cells.iter().filter(|(c, _)| inside(c)) {
+                check(entities);
+            }
+        }
+        found
+    }
+}
+
+fn update_spatial_index(mut index: ResMut<SpatialIndex>, query: Query<(Entity, &GlobalTransform), With<Stats>>) {
+    index.clear();
+    for (entity, transform) in query.iter() {
+        index.insert(entity, transform.translation);
+    }
+}
+
+pub fn add_systems(app: &mut AppBuilder) {
+    app.init_resource::<SpatialIndex>()
+        .add_system_to_stage(CoreStage::PreUpdate, update_spatial_index.system());
+}
@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
 use crate::count_idents;
-use crate::stats::{BaseStat, Stats, Resource};
+use crate::stats::{BaseStat, Stats, Resource, StatCap, SoftCap};
 use paste::paste;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 macro_rules! base_stat_check {
     ($m: ident, $($name:ident) *, $code:expr) => {
@@ -16,29 +20,30 @@ macro_rules! base_stat_check {
 }
 
 macro_rules! stat_check {
-    ($m:ident, $($name:ident) *, $($code:expr); *) => {
+    ($m:ident, $($name:ident) *, $code:expr) => {
         match $m {
             $(ty @ Stat::$name => {
                 $code(ty);
             }), *
         }
     };
-    ($m:ident, $stats:ident, $($name1:ident) *, $($name2:ident [$($name3: ident), *]) *) => {
-        stat_check!($m, $($name1) *, $(|ty| { $(if ty == Stat::$name3 { $stats.update_stat(Stat::$name2) } ), *}); *)
+    ($m:ident, $stats:ident, $($name1:ident) *, $($name2:ident [$($name3: ident) *]) *) => {
+        stat_check!($m, $($name1) *, |ty| { $($(if ty == Stat::$name3 { $stats.update_stat(Stat::$name2) }) *) * })
     }
 }
 
 macro_rules! stats {
-    ($([$($name:ident: $($base_stat:ident), *: $($stat:ident), *: $calculate:expr), *,]) *) => {
-        stats!{$($($name: $($base_stat), *: $($stat), *: $calculate), *), *,}
+    ($([$($name:ident $({$($cap:ident: $cap_val:expr), *})?: $($base_stat:ident), *: $($stat:ident), *: $calculate:expr), *,]) *) => {
+        stats!{$($($name $({$($cap: $cap_val), *})?: $($base_stat), *: $($stat), *: $calculate), *), *,}
     };
-    ($($name:ident: $($base_stat:ident), *: $($stat:ident), *: $calculate:expr), *,) => {
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    ($($name:ident $({$($cap:ident: $cap_val:expr), *})?: $($base_stat:ident), *: $($stat:ident), *: $calculate:expr), *,) => {
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
         pub enum Stat {
             $($name), *
         }
 
-        const NUM_STATS: usize = count_idents!($($name), *);
+        pub const NUM_STATS: usize = count_idents!($($name), *);
+        pub const STAT_ITER: [Stat; NUM_STATS] = [$(Stat::$name), *];
 
         paste! {
             const NAMES: [&'static str; NUM_STATS] = [$(stringify!([<$name:lower>])), *];
@@ -64,19 +69,139 @@ macro_rules! stats {
             pub fn on_updated(&self, stats: &mut Stats) {
                 stat_changed(stats, *self);
             }
+
+            pub fn default_cap(&self) -> StatCap {
+                paste! {
+                    match self {
+                        $(Stat::$name => StatCap::none() $($(.[<with_ $cap>]($cap_val)) *)?), *
+                    }
+                }
+            }
         }
 
     }
 }
 
+// Dense storage for a value per stat. Only non-zero values are serialized, keyed by name.
+#[derive(Debug, Clone, Copy)]
+pub struct StatValues {
+    data: [f32; NUM_STATS],
+}
+
+impl StatValues {
+    pub fn zeroes() -> Self {
+        Self {
+            data: [0.0; NUM_STATS],
+        }
+    }
+}
+
+impl Default for StatValues {
+    fn default() -> Self {
+        Self::zeroes()
+    }
+}
+
+impl Index<Stat> for StatValues {
+    type Output = f32;
+
+    fn index(&self, index: Stat) -> &Self::Output {
+        &self.data[index as usize]
+    }
+}
+impl IndexMut<Stat> for StatValues {
+    fn index_mut(&mut self, index: Stat) -> &mut Self::Output {
+        &mut self.data[index as usize]
+    }
+}
+
+impl Serialize for StatValues {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(STAT_ITER.iter().zip(self.data.iter()).filter(|(_, v)| **v != 0.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for StatValues {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut values = Self::zeroes();
+        for (stat, value) in HashMap::<Stat, f32>::deserialize(deserializer)? {
+            values[stat] = value;
+        }
+        Ok(values)
+    }
+}
+
+// What a stat is calculated from, the value before caps is `(base + add) * mul`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StatModifier {
+    pub base: f32,
+    pub add: f32,
+    pub mul: f32,
+}
+
+impl StatModifier {
+    pub const NONE: Self = Self {
+        base: 0.0,
+        add: 0.0,
+        mul: 1.0,
+    };
+
+    pub fn value(&self) -> f32 {
+        (self.base + self.add) * self.mul
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StatModifiers {
+    data: [StatModifier; NUM_STATS],
+}
+
+impl Default for StatModifiers {
+    fn default() -> Self {
+        Self {
+            data: [StatModifier::NONE; NUM_STATS],
+        }
+    }
+}
+
+impl Index<Stat> for StatModifiers {
+    type Output = StatModifier;
+
+    fn index(&self, index: Stat) -> &Self::Output {
+        &self.data[index as usize]
+    }
+}
+impl IndexMut<Stat> for StatModifiers {
+    fn index_mut(&mut self, index: Stat) -> &mut Self::Output {
+        &mut self.data[index as usize]
+    }
+}
+
+impl Serialize for StatModifiers {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(STAT_ITER.iter().zip(self.data.iter()).filter(|(_, m)| **m != StatModifier::NONE))
+    }
+}
+
+impl<'de> Deserialize<'de> for StatModifiers {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut modifiers = Self::default();
+        for (stat, modifier) in HashMap::<Stat, StatModifier>::deserialize(deserializer)? {
+            modifiers[stat] = modifier;
+        }
+        Ok(modifiers)
+    }
+}
+
 pub fn distribution(x: f32, half: f32) -> f32 {
     1.0 - x / (x.abs() + half)
 }
 
 // Usage:
-// NAME_OF_STAT: <Used base stats> : <Used stats>: |stats: &mut Stats| {
+// NAME_OF_STAT optional<{ min: <f32>, max: <f32>, soft: <SoftCap> }>: <Used base stats> : <Used stats>: |stats: &mut Stats| {
 //      Code goes here...
 // }
+// Caps are applied after all modifiers, and can be overridden by mods.
 stats! {
     // Senses
     [
@@ -90,16 +215,16 @@ stats! {
             stats[BaseStat::Vision] * stats[BaseStat::Sense]
         },
 
-        ReactionTime: Sense: Vision: |stats: &mut Stats| {
+        ReactionTime { min: 0.05 }: Sense: Vision: |stats: &mut Stats| {
             1.0 / (stats[BaseStat::Sense] * stats[Stat::Vision])
         },
     ]
     // Movement
     [
-        Speed: Dexterity, Strength, Weight: : |stats: &mut Stats| {
+        Speed { min: 0.0, soft: SoftCap::Distribution { start: 100.0, half: 100.0 } }: Dexterity, Strength, Weight: : |stats: &mut Stats| {
             (stats[BaseStat::Dexterity] * 7.0 + stats[BaseStat::Strength] * 3.0) / stats[BaseStat::Weight].min(1.0)
         },
-        JumpHeight: Dexterity, Strength, Weight: : |stats: &mut Stats| {
+        JumpHeight { min: 0.0, soft: SoftCap::Distribution { start: 100.0, half: 100.0 } }: Dexterity, Strength, Weight: : |stats: &mut Stats| {
             (stats[BaseStat::Dexterity] * 5.0 + stats[BaseStat::Strength] * 5.0) / stats[BaseStat::Weight].min(1.0)
         },
 
@@ -109,15 +234,33 @@ stats! {
     ]
     // Damage
     [
-        PhysicalCritChance: Dexterity, Luck: : |stats: &mut Stats| {
-            1.0 - distribution(stats[BaseStat::Dexterity] * stats[BaseStat::Luck], 500.0).max(1.0)
+        PhysicalCritChance { min: 0.0, max: 1.0 }: Dexterity, Luck: : |stats: &mut Stats| {
+            1.0 - distribution(stats[BaseStat::Dexterity] * stats[BaseStat::Luck], 500.0)
+        },
+        MagicalCritChance { min: 0.0, max: 1.0 }: Wisdom, Luck: : |stats: &mut Stats| {
+            1.0 - distribution(stats[BaseStat::Wisdom] * stats[BaseStat::Luck] * 0.1, 500.0)
+        },
+        ElementalCritChance { min: 0.0, max: 1.0 }: : : |_: &mut Stats| {
+            0.0
+        },
+        CritDamage { min: 1.0 }: Luck: : |stats: &mut Stats| {
+            2.0 + stats[BaseStat::Luck] * 0.01
         },
-        MagicalCritChance: Wisdom, Luck: : |stats: &mut Stats| {
-            1.0 - distribution(stats[BaseStat::Wisdom] * stats[BaseStat::Luck] * 0.1, 500.0).max(1.0)
+        // Chance for an attack that can be dodged to hit at all, only lowered by gains.
+        Accuracy { min: 0.0, max: 1.0 }: : : |_: &mut Stats| {
+            1.0
         },
-        ElementalCritChance: : : |_: &mut Stats| {
+        // Comes from held tools, see `ToolProficiencies::equip`.
+        Blocking { min: 0.0 }: : : |_: &mut Stats| {
             0.0
         },
+        BlockChance { min: 0.0, max: 1.0 }: Dexterity: Blocking: |stats: &mut Stats| {
+            1.0 - distribution(stats[Stat::Blocking] * stats[BaseStat::Dexterity], 50.0)
+        },
+        // Part of the damage of a blocked attack that is stopped.
+        BlockReduction { min: 0.0, max: 1.0 }: Strength: Blocking: |stats: &mut Stats| {
+            1.0 - distribution(stats[Stat::Blocking] * stats[BaseStat::Strength], 20.0)
+        },
 
 
         PhysicalDamage: Strength: : |stats: &mut Stats| {
@@ -191,70 +334,70 @@ stats! {
             stats[BaseStat::Earth] * 5.0
         },
 
-        PhysicalReduction: : PhysicalArmor: |stats: &mut Stats| {
+        PhysicalReduction { min: 0.0 }: : PhysicalArmor: |stats: &mut Stats| {
             distribution(stats[Stat::PhysicalArmor], 500.0)
         },
-        PhysicalReductionFlat: : PhysicalArmor: |stats: &mut Stats| {
+        PhysicalReductionFlat { min: 0.0 }: : PhysicalArmor: |stats: &mut Stats| {
             stats[Stat::PhysicalArmor] * 0.1
         },
-        CuttingReduction: : CuttingArmor: |stats: &mut Stats| {
+        CuttingReduction { min: 0.0 }: : CuttingArmor: |stats: &mut Stats| {
             distribution(stats[Stat::CuttingArmor], 500.0)
         },
-        CuttingReductionFlat: : CuttingArmor: |stats: &mut Stats| {
+        CuttingReductionFlat { min: 0.0 }: : CuttingArmor: |stats: &mut Stats| {
             stats[Stat::CuttingArmor] * 0.1
         },
-        MagicalReduction: : MagicalArmor: |stats: &mut Stats| {
+        MagicalReduction { min: 0.0 }: : MagicalArmor: |stats: &mut Stats| {
             distribution(stats[Stat::MagicalArmor], 500.0)
         },
-        MagicalReductionFlat: : MagicalArmor: |stats: &mut Stats| {
+        MagicalReductionFlat { min: 0.0 }: : MagicalArmor: |stats: &mut Stats| {
             stats[Stat::MagicalArmor] * 0.1
         },
-        MentalReduction: : MentalArmor: |stats: &mut Stats| {
+        MentalReduction { min: 0.0 }: : MentalArmor: |stats: &mut Stats| {
             distribution(stats[Stat::MentalArmor], 500.0)
         },
-        MentalReductionFlat: : MentalArmor: |stats: &mut Stats| {
+        MentalReductionFlat { min: 0.0 }: : MentalArmor: |stats: &mut Stats| {
             stats[Stat::MentalArmor] * 0.1
         },
-        CurseReduction: : CurseArmor: |stats: &mut Stats| {
+        CurseReduction { min: 0.0 }: : CurseArmor: |stats: &mut Stats| {
             distribution(stats[Stat::CurseArmor], 500.0)
         },
-        CurseReductionFlat: : CurseArmor: |stats: &mut Stats| {
+        CurseReductionFlat { min: 0.0 }: : CurseArmor: |stats: &mut Stats| {
             stats[Stat::CurseArmor] * 0.1
         },
-        HolyReduction: : HolyArmor: |stats: &mut Stats| {
+        HolyReduction { min: 0.0 }: : HolyArmor: |stats: &mut Stats| {
             distribution(stats[Stat::HolyArmor], 500.0)
         },
-        HolyReductionFlat: : HolyArmor: |stats: &mut Stats| {
+        HolyReductionFlat { min: 0.0 }: : HolyArmor: |stats: &mut Stats| {
             stats[Stat::HolyArmor] * 0.1
         },
-        FireReduction: : FireArmor: |stats: &mut Stats| {
+        FireReduction { min: 0.0 }: : FireArmor: |stats: &mut Stats| {
             distribution(stats[Stat::FireArmor], 500.0)
         },
-        FireReductionFlat: : FireArmor: |stats: &mut Stats| {
+        FireReductionFlat { min: 0.0 }: : FireArmor: |stats: &mut Stats| {
             stats[Stat::FireArmor] * 0.1
         },
-        IceReduction: : IceArmor: |stats: &mut Stats| {
+        IceReduction { min: 0.0 }: : IceArmor: |stats: &mut Stats| {
             distribution(stats[Stat::IceArmor], 500.0)
         },
-        IceReductionFlat: : IceArmor: |stats: &mut Stats| {
+        IceReductionFlat { min: 0.0 }: : IceArmor: |stats: &mut Stats| {
             stats[Stat::IceArmor] * 0.1
         },
-        WindReduction: : WindArmor: |stats: &mut Stats| {
+        WindReduction { min: 0.0 }: : WindArmor: |stats: &mut Stats| {
             distribution(stats[Stat::WindArmor], 500.0)
         },
-        WindReductionFlat: : WindArmor: |stats: &mut Stats| {
+        WindReductionFlat { min: 0.0 }: : WindArmor: |stats: &mut Stats| {
             stats[Stat::WindArmor] * 0.1
         },
-        ElectricReduction: : ElectricArmor: |stats: &mut Stats| {
+        ElectricReduction { min: 0.0 }: : ElectricArmor: |stats: &mut Stats| {
             distribution(stats[Stat::ElectricArmor], 500.0)
         },
-        ElectricReductionFlat: : ElectricArmor: |stats: &mut Stats| {
+        ElectricReductionFlat { min: 0.0 }: : ElectricArmor: |stats: &mut Stats| {
             stats[Stat::ElectricArmor] * 0.1
         },
-        EarthReduction: : EarthArmor: |stats: &mut Stats| {
+        EarthReduction { min: 0.0 }: : EarthArmor: |stats: &mut Stats| {
             distribution(stats[Stat::EarthArmor], 500.0)
         },
-        EarthReductionFlat: : EarthArmor: |stats: &mut Stats| {
+        EarthReductionFlat { min: 0.0 }: : EarthArmor: |stats: &mut Stats| {
             stats[Stat::EarthArmor] * 0.1
         },
     ]
@@ -267,8 +410,9 @@ stats! {
             }
             t
         },
+        // A hundredth of the max health per second.
         HealthRegen: Vitality, Strength: : |stats: &mut Stats| {
-            stats[BaseStat::Vitality] * 7.0 + stats[BaseStat::Strength] * 3.0
+            stats[BaseStat::Vitality] * 0.07 + stats[BaseStat::Strength] * 0.03
         },
         MaxMana: Wisdom, Intelligence: : |stats: &mut Stats| {
             let t = 0.0f32.max(stats[BaseStat::Wisdom] * 7.0 + (stats[BaseStat::Intelligence] - 20.0) * 3.0);
@@ -280,5 +424,49 @@ stats! {
         ManaRegen: Wisdom, Intelligence: : |stats: &mut Stats| {
             stats[BaseStat::Intelligence] * 7.0 + stats[BaseStat::Wisdom] * 3.0
         },
+        MaxStamina: Vitality, Dexterity: : |stats: &mut Stats| {
+            let t = 0.0f32.max(stats[BaseStat::Vitality] * 5.0 + stats[BaseStat::Dexterity] * 5.0);
+            if t > 0.0 {
+                stats.add_resource(Resource::Stamina, true);
+            }
+            t
+        },
+        StaminaRegen: Vitality, Dexterity: : |stats: &mut Stats| {
+            stats[BaseStat::Vitality] * 2.0 + stats[BaseStat::Dexterity] * 1.0
+        },
+        MaxHunger: Size: : |stats: &mut Stats| {
+            let t = 0.0f32.max(stats[BaseStat::Size] * 100.0);
+            if t > 0.0 {
+                stats.add_resource(Resource::Hunger, true);
+            }
+            t
+        },
+        HungerRate { min: 0.0 }: Size, Weight: : |stats: &mut Stats| {
+            stats[BaseStat::Size] * 0.05 + stats[BaseStat::Weight] * 0.05
+        },
+        MaxThirst: Size: : |stats: &mut Stats| {
+            let t = 0.0f32.max(stats[BaseStat::Size] * 100.0);
+            if t > 0.0 {
+                stats.add_resource(Resource::Thirst, true);
+            }
+            t
+        },
+        ThirstRate { min: 0.0 }: Size: : |stats: &mut Stats| {
+            stats[BaseStat::Size] * 0.15
+        },
+        // How much of the difference to a hot or cold environment is kept out of the body.
+        HeatInsulation { min: 0.0, max: 1.0 }: Fire: : |stats: &mut Stats| {
+            1.0 - distribution(stats[BaseStat::Fire], 10.0)
+        },
+        ColdInsulation { min: 0.0, max: 1.0 }: Ice: : |stats: &mut Stats| {
+            1.0 - distribution(stats[BaseStat::Ice], 10.0)
+        },
+        // Stamina spent per second of sprinting and per jump.
+        SprintCost { min: 0.0 }: : Speed: |stats: &mut Stats| {
+            stats[Stat::Speed] * 0.1
+        },
+        JumpCost { min: 0.0 }: : JumpHeight: |stats: &mut Stats| {
+            stats[Stat::JumpHeight] * 0.2
+        },
     ]
 }
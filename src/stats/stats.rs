@@ -1,27 +1,120 @@
 
-use std::{collections::HashMap, ops::Index};
-use rand::{Rng, thread_rng};
+use std::{fmt::Display, ops::Index, sync::Arc};
+use serde::{Serialize, Deserialize};
 
 use crate::stats::*;
+use crate::stats::stat::{StatModifier, StatModifiers, StatValues, STAT_ITER};
+use crate::stats::resource::ResourceValues;
+use crate::stats::custom::{ModStatInfo, ModResourceInfo};
 
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct StatGain {
     base_stat_add: Vec<(BaseStat, f32)>,
     base_stat_mul: Vec<(BaseStat, f32)>,
     stat_add: Vec<(Stat, f32)>,
     stat_mul: Vec<(Stat, f32)>,
+    mod_stat_add: Vec<(ModStat, f32)>,
+    mod_stat_mul: Vec<(ModStat, f32)>,
 }
 
+impl StatGain {
+    /// Added values are multiplied by `strength` and multipliers raised to the power of it.
+    pub fn scaled(&self, strength: f32) -> Self {
+        Self {
+            base_stat_add: self.base_stat_add.iter().map(|(s, v)| (*s, v * strength)).collect(),
+            base_stat_mul: self.base_stat_mul.iter().map(|(s, v)| (*s, v.powf(strength))).collect(),
+            stat_add: self.stat_add.iter().map(|(s, v)| (*s, v * strength)).collect(),
+            stat_mul: self.stat_mul.iter().map(|(s, v)| (*s, v.powf(strength))).collect(),
+            mod_stat_add: self.mod_stat_add.iter().map(|(s, v)| (*s, v * strength)).collect(),
+            mod_stat_mul: self.mod_stat_mul.iter().map(|(s, v)| (*s, v.powf(strength))).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Stats {
     base_stats_uncalculated: BaseStats,
     base_stats_mul: BaseStats,
     base_stats: BaseStats,
 
-    stats_uncalculated: HashMap<Stat, f32>,
-    stats_add: HashMap<Stat, f32>,
-    stats_mul: HashMap<Stat, f32>,
-    stats: HashMap<Stat, f32>,
+    stat_modifiers: StatModifiers,
+    stats: StatValues,
+
+    resources: ResourceValues,
+
+    // Only the custom stats that have been changed are kept, the rest are at the base from their definition.
+    mod_stat_modifiers: ModValues<ModStat, StatModifier>,
+    mod_resources: ModValues<ModResource, f32>,
+
+    level: Level,
+
+    // Every multiplier that has been applied, so one can be taken off by multiplying the rest again instead of
+    // dividing by it, which doesn't work for 0.
+    #[serde(default)]
+    muls: StatGain,
+
+    #[serde(skip, default = "comfortable_temperature")]
+    ambient_temperature: f32,
+    #[serde(skip)]
+    combat: CombatStatus,
+
+    #[serde(skip)]
+    caps: Option<Arc<CapTable>>,
+    #[serde(skip)]
+    custom: Option<Arc<CustomTable>>,
+    #[serde(skip)]
+    regen: Option<Arc<RegenTable>>,
+    #[serde(skip)]
+    changed_stats: Vec<(Stat, f32)>,
+    #[serde(skip)]
+    changed_resources: Vec<(ResourceKey, f32)>,
+    // Custom stats are calculated when read, so their changes are found by comparing with the values they had when
+    // they were last drained. Only done when something they can be calculated from has changed.
+    #[serde(skip)]
+    mod_stat_values: ModValues<ModStat, f32>,
+    #[serde(skip)]
+    mod_stats_dirty: bool,
+}
+
+/// How the value of a stat was reached, `value` is `uncapped` after the cap has been applied.
+pub struct StatExplanation {
+    pub stat: Stat,
+    pub base: f32,
+    pub add: f32,
+    pub mul: f32,
+    pub uncapped: f32,
+    pub cap: StatCap,
+    pub value: f32,
+}
+
+impl Display for StatExplanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: ({} + {}) * {} = {}", self.stat, self.base, self.add, self.mul, self.uncapped)?;
+        if !self.cap.is_none() {
+            write!(f, " -> {} [{}]", self.value, self.cap)?;
+        }
+        Ok(())
+    }
+}
 
-    resources: HashMap<Resource, f32>,
+fn comfortable_temperature() -> f32 {
+    COMFORTABLE_AMBIENT_TEMPERATURE
+}
+
+// Regeneration is multiplied by this for each need that has run out.
+const NEED_PENALTY: f32 = 0.5;
+// HP lost per second for every degree the body is outside the safe temperature range.
+const TEMPERATURE_DAMAGE: f32 = 2.0;
+// Custom stats calculated from custom stats stop looking further at this depth, in case they refer to each other.
+const MAX_MOD_STAT_DEPTH: u32 = 8;
+
+/// Takes one `value` for `key` out of `factors` and returns the product of the ones left for it.
+fn remove_factor<K: Copy + PartialEq>(factors: &mut Vec<(K, f32)>, key: K, value: f32) -> f32 {
+    if let Some(i) = factors.iter().position(|factor| *factor == (key, value)) {
+        factors.swap_remove(i);
+    }
+    factors.iter().filter(|(k, _)| *k == key).map(|(_, v)| v).product()
 }
 
 pub trait StatAccessor {
@@ -32,62 +125,227 @@ pub trait StatAccessor {
 impl StatAccessor for Stat {
     
         fn get_value(&self, stats: &Stats) -> f32 { stats[*self] }
-        fn get_base_value(&self, stats: &Stats) -> f32 { *stats.stats_uncalculated.get(self).unwrap_or(&0.0) }
+        fn get_base_value(&self, stats: &Stats) -> f32 { stats.stat_modifiers[*self].base }
 }
 impl StatAccessor for BaseStat {
         fn get_value(&self, stats: &Stats) -> f32 { stats[*self] }
         fn get_base_value(&self, stats: &Stats) -> f32 { stats.base_stats_uncalculated[*self] }
 }
+impl StatAccessor for ModStat {
+        fn get_value(&self, stats: &Stats) -> f32 { stats.mod_stat(*self) }
+        fn get_base_value(&self, stats: &Stats) -> f32 { stats.mod_stat_modifier(*self).base }
+}
 
 impl Stats {
-    fn new(base: BaseStats) -> Self {
+    pub fn new(base: BaseStats) -> Self {
         let mut t = Self {
             base_stats_uncalculated: base,
             base_stats_mul: BaseStats::ones(),
             base_stats: Default::default(),
-            stats_uncalculated: Default::default(),
-            stats_add: Default::default(),
-            stats_mul: Default::default(),
+            stat_modifiers: Default::default(),
             stats: Default::default(),
             resources: Default::default(),
+            mod_stat_modifiers: Default::default(),
+            mod_resources: Default::default(),
+            level: Default::default(),
+            muls: Default::default(),
+            ambient_temperature: COMFORTABLE_AMBIENT_TEMPERATURE,
+            combat: Default::default(),
+            caps: None,
+            custom: None,
+            regen: None,
+            changed_stats: Default::default(),
+            changed_resources: Default::default(),
+            mod_stat_values: Default::default(),
+            mod_stats_dirty: true,
         };
         for stat in base_stat::BASE_STAT_ITER {
             t.recalculate_base_stat(stat);
         }
+        // Stats that depend on no base stat, like Accuracy, are never updated by the ones above.
+        for stat in STAT_ITER.iter() {
+            t.update_stat(*stat);
+        }
+        // The other resources are added by the stat of their max, the range of body temperatures doesn't change.
+        t.add_resource(Resource::Temperature, true);
         t
     }
 
     pub fn set_stat(&mut self, stat: Stat, value: f32) {
-        if self.stats_uncalculated.insert(stat, value) != Some(value) { 
-            self.recalculate_stat(stat, value); 
+        if self.stat_modifiers[stat].base != value { 
+            self.stat_modifiers[stat].base = value;
+            self.recalculate_stat(stat); 
         }
     }
 
     pub fn mul_stat(&mut self, stat: Stat, value: f32) {
-        if value == 0.0 { panic!() }
         if value == 1.0 { return; }
-        self.stats_add.insert(stat, self.stats_mul.get(&stat).unwrap_or(&1.0) * value);
-        if self.stats_add[&stat] == 1.0 {
-            self.stats.remove(&stat);
-        }
-        self.recalculate_stat(stat, *self.stats.get(&stat).unwrap_or(&0.0));
+        self.muls.stat_mul.push((stat, value));
+        self.stat_modifiers[stat].mul *= value;
+        self.recalculate_stat(stat);
+    }
+
+    /// Undoes a `mul_stat` with the same value.
+    pub fn unmul_stat(&mut self, stat: Stat, value: f32) {
+        if value == 1.0 { return; }
+        self.stat_modifiers[stat].mul = remove_factor(&mut self.muls.stat_mul, stat, value);
+        self.recalculate_stat(stat);
     }
 
     pub fn add_stat(&mut self, stat: Stat, value: f32) {
         if value == 0.0 { return; }
-        self.stats_add.insert(stat, self.stats_add.get(&stat).unwrap_or(&0.0) + value);
-        if self.stats_add[&stat] == 0.0 { 
-            self.stats.remove(&stat);
-        }
-        self.recalculate_stat(stat, *self.stats.get(&stat).unwrap_or(&0.0));
+        self.stat_modifiers[stat].add += value;
+        self.recalculate_stat(stat);
     }
 
-    fn recalculate_stat(&mut self, stat: Stat, current: f32) {
-        self.stats.insert(stat, (current + self.stats_add.get(&stat).unwrap_or(&0.0)) 
-                                    * self.stats_mul.get(&stat).unwrap_or(&1.0));
+    fn recalculate_stat(&mut self, stat: Stat) {
+        let new = self.cap(stat).apply(self.stat_modifiers[stat].value());
+        let old = std::mem::replace(&mut self.stats[stat], new);
+        if old != new && !self.changed_stats.iter().any(|(s, _)| *s == stat) {
+            self.changed_stats.push((stat, old));
+        }
+        if old != new {
+            self.input_changed(StatKey::Stat(stat));
+        }
         stat.on_updated(self);
     }
 
+    // Custom stats are only recalculated when a key one of them is calculated from has changed.
+    fn input_changed(&mut self, key: StatKey) {
+        if self.mod_stats_dirty {
+            return;
+        }
+        self.mod_stats_dirty = self.custom.as_ref().map_or(false, |custom| {
+            custom.stats.iter().any(|(_, info)| info.from.iter().any(|(from, _)| *from == key))
+        });
+    }
+
+    pub fn cap(&self, stat: Stat) -> StatCap {
+        match &self.caps {
+            Some(caps) => caps.stat(stat),
+            None => stat.default_cap(),
+        }
+    }
+
+    pub fn dmg_cap(&self, ty: DmgType) -> StatCap {
+        match &self.caps {
+            Some(caps) => caps.dmg(ty),
+            None => ty.default_cap(),
+        }
+    }
+
+    /// Replaces the caps, `None` uses the caps declared in `stats!` and `damages!`.
+    pub fn set_caps(&mut self, caps: Option<Arc<CapTable>>) {
+        self.caps = caps;
+        for stat in STAT_ITER {
+            self.recalculate_stat(stat);
+        }
+    }
+
+    pub fn explain(&self, stat: Stat) -> StatExplanation {
+        let modifier = self.stat_modifiers[stat];
+        StatExplanation {
+            stat,
+            base: modifier.base,
+            add: modifier.add,
+            mul: modifier.mul,
+            uncapped: modifier.value(),
+            cap: self.cap(stat),
+            value: self[stat],
+        }
+    }
+
+    /// Replaces the custom stats and resources, every custom resource in the table is given to the entity.
+    pub fn set_custom_table(&mut self, custom: Option<Arc<CustomTable>>) {
+        self.custom = custom;
+        self.mod_stats_dirty = true;
+        let resources: Vec<(ModResource, bool)> = match &self.custom {
+            Some(custom) => custom.resources.iter().map(|(r, info)| (r, info.start_full)).collect(),
+            None => vec![],
+        };
+        for (resource, full) in resources {
+            self.add_resource(resource, full);
+        }
+    }
+
+    pub fn mod_stat_info(&self, stat: ModStat) -> Option<&ModStatInfo> {
+        self.custom.as_ref().and_then(|custom| custom.stats.get(stat))
+    }
+
+    pub fn mod_resource_info(&self, resource: ModResource) -> Option<&ModResourceInfo> {
+        self.custom.as_ref().and_then(|custom| custom.resources.get(resource))
+    }
+
+    fn mod_stat_modifier(&self, stat: ModStat) -> StatModifier {
+        match self.mod_stat_modifiers.get(stat) {
+            Some(modifier) => *modifier,
+            None => StatModifier {
+                base: self.mod_stat_info(stat).map(|info| info.default).unwrap_or(0.0),
+                ..StatModifier::NONE
+            },
+        }
+    }
+
+    fn mod_stat_modifier_mut(&mut self, stat: ModStat) -> &mut StatModifier {
+        self.mod_stats_dirty = true;
+        if !self.mod_stat_modifiers.contains(stat) {
+            let modifier = self.mod_stat_modifier(stat);
+            self.mod_stat_modifiers.insert(stat, modifier);
+        }
+        self.mod_stat_modifiers.get_mut(stat).unwrap()
+    }
+
+    /// Custom stats are calculated when they are read, as `(base + from + add) * mul` with `from` the sum of the
+    /// stats their definition is calculated from.
+    pub fn mod_stat(&self, stat: ModStat) -> f32 {
+        self.mod_stat_at(stat, 0)
+    }
+
+    fn mod_stat_at(&self, stat: ModStat, depth: u32) -> f32 {
+        let modifier = self.mod_stat_modifier(stat);
+        let info = match self.mod_stat_info(stat) {
+            Some(info) => info,
+            None => return modifier.value(),
+        };
+        let from: f32 = if depth < MAX_MOD_STAT_DEPTH {
+            info.from
+                .iter()
+                .map(|(key, factor)| {
+                    factor
+                        * match key {
+                            StatKey::Mod(stat) => self.mod_stat_at(*stat, depth + 1),
+                            key => self.get(*key),
+                        }
+                })
+                .sum()
+        } else {
+            0.0
+        };
+        info.cap.apply((modifier.base + from + modifier.add) * modifier.mul)
+    }
+
+    pub fn set_mod_stat(&mut self, stat: ModStat, value: f32) {
+        self.mod_stat_modifier_mut(stat).base = value;
+    }
+
+    pub fn add_mod_stat(&mut self, stat: ModStat, value: f32) {
+        if value == 0.0 { return; }
+        self.mod_stat_modifier_mut(stat).add += value;
+    }
+
+    pub fn mul_mod_stat(&mut self, stat: ModStat, value: f32) {
+        if value == 1.0 { return; }
+        self.muls.mod_stat_mul.push((stat, value));
+        self.mod_stat_modifier_mut(stat).mul *= value;
+    }
+
+    /// Undoes a `mul_mod_stat` with the same value.
+    pub fn unmul_mod_stat(&mut self, stat: ModStat, value: f32) {
+        if value == 1.0 { return; }
+        let mul = remove_factor(&mut self.muls.mod_stat_mul, stat, value);
+        self.mod_stat_modifier_mut(stat).mul = mul;
+    }
+
     pub fn add_base(&mut self, stat: BaseStat, value: f32) {
         if value == 0.0 { return; }
         self.base_stats_uncalculated[stat] += value;
@@ -95,14 +353,24 @@ impl Stats {
     }
 
     pub fn mul_base(&mut self, stat: BaseStat, value: f32) {
-        if value == 0.0 { panic!() }
         if value == 1.0 { return; }
+        self.muls.base_stat_mul.push((stat, value));
         self.base_stats_mul[stat] *= value;
         self.recalculate_base_stat(stat);
     }
 
+    /// Undoes a `mul_base` with the same value.
+    pub fn unmul_base(&mut self, stat: BaseStat, value: f32) {
+        if value == 1.0 { return; }
+        self.base_stats_mul[stat] = remove_factor(&mut self.muls.base_stat_mul, stat, value);
+        self.recalculate_base_stat(stat);
+    }
+
     pub fn recalculate_base_stat(&mut self, stat: BaseStat) {
-        self.base_stats[stat] = self.base_stats_uncalculated[stat] * self.base_stats_mul[stat];
+        let new = self.base_stats_uncalculated[stat] * self.base_stats_mul[stat];
+        if std::mem::replace(&mut self.base_stats[stat], new) != new {
+            self.input_changed(StatKey::Base(stat));
+        }
         stat::base_stat_changed(self, stat);
     }
 
@@ -110,21 +378,169 @@ impl Stats {
         stat.update(self);
     }
 
-    pub fn update_resources(&mut self, delta: f32) {
-        let t: Vec<_> = self.resources.iter().map(|(&res, &val)| {
-            (res, (val + res.regen(self) * delta).clamp(0.0, res.max(self)))
-        }).collect();
-        self.resources.extend(t);
+    /// Regenerates the resources over `delta` seconds. Returns the HP the surroundings take, e.g. from being too cold,
+    /// which is dealt through an `Attack` so the damage has a source.
+    pub fn update_resources(&mut self, delta: f32) -> f32 {
+        let resources = self.resources;
+        let mut penalty = 1.0;
+        for need in [Resource::Hunger, Resource::Thirst] {
+            if resources.get(need) == Some(0.0) {
+                penalty *= NEED_PENALTY;
+            }
+        }
+        for (res, val) in resources.iter() {
+            self.regen_resource(res.into(), val, penalty, delta);
+        }
+        if !self.mod_resources.is_empty() {
+            let mod_resources = self.mod_resources.clone();
+            for (res, val) in mod_resources.iter() {
+                self.regen_resource(res.into(), *val, penalty, delta);
+            }
+        }
+        match resources.get(Resource::Temperature) {
+            Some(temperature) => {
+                let outside = (temperature - NORMAL_TEMPERATURE).abs() - SAFE_TEMPERATURE_RANGE;
+                outside.max(0.0) * TEMPERATURE_DAMAGE * delta
+            }
+            None => 0.0,
+        }
+    }
+
+    fn regen_resource(&mut self, res: ResourceKey, val: f32, penalty: f32, delta: f32) {
+        let max = res.max(self);
+        let mut regen = res.regen(self);
+        if let Some(rule) = self.regen.as_ref().and_then(|regen| regen.get(res)) {
+            regen = rule.apply(self.combat, regen, max);
+        }
+        if regen > 0.0 && !res.is_need() {
+            regen *= penalty;
+        }
+        let val = (val + regen * delta).clamp(0.0, max);
+        self.set_resource(res, val);
+    }
+
+    /// Replaces the regeneration rules, `None` regenerates every resource in full whatever the entity is doing.
+    pub fn set_regen_table(&mut self, regen: Option<Arc<RegenTable>>) {
+        self.regen = regen;
+    }
+
+    pub fn combat_status(&self) -> CombatStatus {
+        self.combat
+    }
+
+    /// Sets what the entity is doing, which decides how fast its resources regenerate.
+    pub fn set_combat_status(&mut self, status: CombatStatus) {
+        self.combat = status;
+    }
+
+    pub fn ambient_temperature(&self) -> f32 {
+        self.ambient_temperature
     }
 
-    pub fn add_resource(&mut self, resource: Resource, max: bool) {
-        if !self.resources.contains_key(&resource) {
-            self.resources.insert(resource, if max { resource.max(self) } else { 0.0 });
+    /// Sets the temperature of the environment the entity is in, which its body temperature moves towards.
+    pub fn set_ambient_temperature(&mut self, temperature: f32) {
+        self.ambient_temperature = temperature;
+    }
+
+    fn resource_mut(&mut self, resource: ResourceKey) -> Option<&mut f32> {
+        match resource {
+            ResourceKey::Builtin(resource) => self.resources.get_mut(resource),
+            ResourceKey::Mod(resource) => self.mod_resources.get_mut(resource),
+        }
+    }
+
+    /// Sets the value of a resource the entity has, remembering the old value so it can be reported.
+    fn set_resource(&mut self, resource: ResourceKey, value: f32) {
+        if let Some(val) = self.resource_mut(resource) {
+            let old = std::mem::replace(val, value);
+            if old != value && !self.changed_resources.iter().any(|(r, _)| *r == resource) {
+                self.changed_resources.push((resource, old));
+            }
+            if old != value {
+                if let ResourceKey::Builtin(resource) = resource {
+                    self.input_changed(StatKey::Resource(resource));
+                }
+            }
         }
     }
 
+    /// Adds to a resource the entity has, keeping it between 0 and its max.
+    pub fn gain_resource(&mut self, resource: impl Into<ResourceKey>, amount: f32) {
+        let resource = resource.into();
+        if self.has_resource(resource) {
+            let max = resource.max(self);
+            self.set_resource(resource, (self[resource] + amount).clamp(0.0, max));
+        }
+    }
+
+    pub fn has_resource(&self, resource: impl Into<ResourceKey>) -> bool {
+        match resource.into() {
+            ResourceKey::Builtin(resource) => self.resources.contains(resource),
+            ResourceKey::Mod(resource) => self.mod_resources.contains(resource),
+        }
+    }
+
+    pub fn add_resource(&mut self, resource: impl Into<ResourceKey>, max: bool) {
+        let resource = resource.into();
+        if !self.has_resource(resource) {
+            let value = if max { resource.start(self) } else { 0.0 };
+            match resource {
+                ResourceKey::Builtin(resource) => {
+                    self.resources.insert(resource, value);
+                }
+                ResourceKey::Mod(resource) => {
+                    self.mod_resources.insert(resource, value);
+                }
+            }
+        }
+    }
+
+    /// Sets every resource the entity has to its starting value, which is the max for most of them.
+    pub fn restore_resources(&mut self) {
+        let resources = self.resources;
+        let mod_resources: Vec<ModResource> = self.mod_resources.iter().map(|(r, _)| r).collect();
+        let keys = resources.iter().map(|(r, _)| ResourceKey::from(r)).chain(mod_resources.into_iter().map(ResourceKey::from));
+        for res in keys {
+            let start = res.start(self);
+            self.set_resource(res, start);
+        }
+    }
+
+    /// Takes the stats that have changed since last call, together with the value they had before the first change.
+    pub fn drain_stat_changes(&mut self) -> Vec<(Stat, f32)> {
+        std::mem::take(&mut self.changed_stats)
+    }
+
+    /// Takes the custom stats that have changed since last call, together with the value they had before.
+    pub fn drain_mod_stat_changes(&mut self) -> Vec<(ModStat, f32)> {
+        if !std::mem::take(&mut self.mod_stats_dirty) {
+            return vec![];
+        }
+        let mut stats: Vec<ModStat> = self.mod_stat_modifiers.iter().map(|(stat, _)| stat).collect();
+        if let Some(custom) = &self.custom {
+            stats.extend(custom.stats.iter().map(|(stat, _)| stat));
+        }
+        let mut changed = vec![];
+        for stat in stats {
+            let new = self.mod_stat(stat);
+            // The first value of a stat isn't a change.
+            if let Some(old) = self.mod_stat_values.insert(stat, new) {
+                if old != new {
+                    changed.push((stat, old));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Takes the resources that have changed since last call, together with the value they had before the first change.
+    pub fn drain_resource_changes(&mut self) -> Vec<(ResourceKey, f32)> {
+        std::mem::take(&mut self.changed_resources)
+    }
+
     pub fn consume_resource(&mut self, consumption: ResourceConsumption) -> bool {
-        if let Some(val) = self.resources.get_mut(&consumption.resource) {
+        let old = self[consumption.resource];
+        let consumed = if let Some(val) = self.resource_mut(consumption.resource) {
             match consumption.ty {
                 ConsumptionType::Flat(f) => {
                     if *val >= f {
@@ -148,14 +564,52 @@ impl Stats {
         }
         else {
             false
+        };
+        if consumed && !self.changed_resources.iter().any(|(r, _)| *r == consumption.resource) {
+            self.changed_resources.push((consumption.resource, old));
         }
+        consumed
+    }
+
+    fn consume_stamina(&mut self, amount: f32) -> bool {
+        !self.has_resource(Resource::Stamina)
+            || self.consume_resource(ResourceConsumption {
+                resource: Resource::Stamina.into(),
+                ty: ConsumptionType::Flat(amount),
+            })
+    }
+
+    /// Spends the stamina for sprinting `delta` seconds, false if there isn't enough left.
+    pub fn sprint(&mut self, delta: f32) -> bool {
+        self.consume_stamina(self[Stat::SprintCost] * delta)
+    }
+
+    /// Spends the stamina for a jump, false if there isn't enough left.
+    pub fn jump(&mut self) -> bool {
+        self.consume_stamina(self[Stat::JumpCost])
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    pub(super) fn level_mut(&mut self) -> &mut Level {
+        &mut self.level
+    }
+
+    pub fn get<T : StatAccessor>(&self, accessor: T) -> f32 {
+        accessor.get_value(self)
     }
 
     pub fn get_base<T : StatAccessor>(&self, accessor: T) -> f32 {
         accessor.get_base_value(self)
     }
 
-    pub fn apply_damage(&mut self, dmg : &Dmg) -> DmgResult {
+    pub fn apply_damage(&mut self, dmg : &Dmg, types: &DmgTable, rng: &mut dyn CombatRng) -> DamageReport {
+        // Damage that can't be dodged, e.g. from effects, can't miss either.
+        if dmg.can_dodge() && !rng.chance(dmg.accuracy()) {
+            return DamageReport::new(DmgResult::Miss, dmg);
+        }
         let dodge = if dmg.can_dodge() {
             let z = dmg.get_speed() - self[Stat::DodgeTime] + self[Stat::ReactionTime];
             (if dmg.get_speed() < self[Stat::DodgeTime] - self[Stat::ReactionTime] + 0.5 {
@@ -163,18 +617,37 @@ impl Stats {
             }
             else {
                 1.0 - z / (z + 0.5)
-            }) < thread_rng().gen::<f32>()
+            }) < rng.roll()
         }
         else {
             false
         };
         if dodge {
-            DmgResult::Dodge
+            return DamageReport::new(DmgResult::Dodge, dmg);
         }
-        else {
-            *self.resources.get_mut(&Resource::HP).unwrap() -= dmg.calculate_taken(self).sum();
-            DmgResult::Hit
+
+        let mut report = DamageReport::new(DmgResult::Hit, dmg);
+        // Only attacks that can be dodged can be blocked.
+        report.blocked = dmg.can_dodge() && rng.chance(self[Stat::BlockChance]);
+        let block = if report.blocked { 1.0 - self[Stat::BlockReduction] } else { 1.0 };
+        for (ty, raw) in dmg.iter() {
+            let info = types.get(ty);
+            let taken = info.taken(ty, self, raw) * if info.bypass_mitigation { 1.0 } else { block };
+            report.amounts.push(DmgAmount {
+                ty,
+                raw,
+                mitigated: raw - taken,
+                taken,
+            });
+            report.total += taken;
         }
+
+        if let Some(hp) = self.resources.get(Resource::HP) {
+            report.overkill = (report.total - hp.max(0.0)).max(0.0);
+            report.lethal = hp > 0.0 && hp - report.total <= 0.0;
+            self.set_resource(Resource::HP.into(), hp - report.total);
+        }
+        report
     }
 
     pub fn add_gain(&mut self, stat_gain: &StatGain) {
@@ -190,6 +663,34 @@ impl Stats {
         for (stat, value) in &stat_gain.stat_mul {
             self.mul_stat(*stat, *value);
         }
+        for (stat, value) in &stat_gain.mod_stat_add {
+            self.add_mod_stat(*stat, *value);
+        }
+        for (stat, value) in &stat_gain.mod_stat_mul {
+            self.mul_mod_stat(*stat, *value);
+        }
+    }
+
+    /// Undoes a gain that was added with `add_gain`.
+    pub fn remove_gain(&mut self, stat_gain: &StatGain) {
+        for (stat, value) in &stat_gain.base_stat_add {
+            self.add_base(*stat, -*value);
+        }
+        for (stat, value) in &stat_gain.base_stat_mul {
+            self.unmul_base(*stat, *value);
+        }
+        for (stat, value) in &stat_gain.stat_add {
+            self.add_stat(*stat, -*value);
+        }
+        for (stat, value) in &stat_gain.stat_mul {
+            self.unmul_stat(*stat, *value);
+        }
+        for (stat, value) in &stat_gain.mod_stat_add {
+            self.add_mod_stat(*stat, -*value);
+        }
+        for (stat, value) in &stat_gain.mod_stat_mul {
+            self.unmul_mod_stat(*stat, *value);
+        }
     }
 }
 
@@ -204,13 +705,24 @@ impl Index<Stat> for Stats {
     type Output = f32;
 
     fn index(&self, index: Stat) -> &Self::Output {
-        self.stats.get(&index).unwrap_or(&0.0)
+        &self.stats[index]
     }
 }
 impl Index<Resource> for Stats {
     type Output = f32;
 
     fn index(&self, index: Resource) -> &Self::Output {
-        self.resources.get(&index).unwrap_or(&0.0)
+        &self.resources[index]
+    }
+}
+impl Index<ResourceKey> for Stats {
+    type Output = f32;
+
+    // Resources the entity doesn't have are always 0.
+    fn index(&self, index: ResourceKey) -> &Self::Output {
+        match index {
+            ResourceKey::Builtin(resource) => &self.resources[resource],
+            ResourceKey::Mod(resource) => self.mod_resources.get(resource).unwrap_or(&0.0),
+        }
     }
 }
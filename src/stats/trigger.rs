@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+use crate::defs::{Ability, Effect, Species, Tool};
+use crate::stats::StatGain;
+
+/// Triggers fired by reactions, e.g. reflected damage, stop firing new triggers past this depth.
+pub const MAX_TRIGGER_DEPTH: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TriggerEvent {
+    // The owner hit something.
+    OnHit,
+    // The owner hit something with a crit.
+    OnCrit,
+    // The owner took damage.
+    OnDamaged,
+    // The owner dealt a lethal hit.
+    OnKill,
+    // The owner dodged an attack.
+    OnDodge,
+}
+
+/// Who a reaction is applied to, the other entity is the one that was hit or that attacked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerTarget {
+    Owner,
+    Other,
+}
+
+impl Default for TriggerTarget {
+    fn default() -> Self {
+        Self::Owner
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Reaction {
+    // Heals the owner by a part of the damage of the attack.
+    Heal { percent: f32 },
+    // Deals a part of the damage back to the other entity, with the same damage types.
+    Reflect { percent: f32 },
+    // The effect is given by its `namespace:id`.
+    ApplyEffect {
+        effect: String,
+        strength: f32,
+        duration: f32,
+        #[serde(default)]
+        target: TriggerTarget,
+    },
+    Buff {
+        gain: StatGain,
+        duration: f32,
+        #[serde(default)]
+        target: TriggerTarget,
+    },
+}
+
+fn always() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trigger {
+    pub on: TriggerEvent,
+    #[serde(default = "always")]
+    pub chance: f32,
+    pub reaction: Reaction,
+}
+
+/// What gave an entity a trigger, so it can be taken away again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriggerSource {
+    Item(Tool),
+    Ability(Ability),
+    Effect(Effect),
+    Species(Species),
+    Other(u32),
+}
+
+/// Reactions to combat events for an entity.
+#[derive(Default)]
+pub struct Triggers {
+    triggers: Vec<(TriggerSource, Trigger)>,
+}
+
+impl Triggers {
+    pub fn add(&mut self, source: TriggerSource, trigger: Trigger) {
+        self.triggers.push((source, trigger));
+    }
+
+    pub fn add_all(&mut self, source: TriggerSource, triggers: &[Trigger]) {
+        for trigger in triggers {
+            self.add(source, trigger.clone());
+        }
+    }
+
+    /// Removes every trigger given by `source`.
+    pub fn remove(&mut self, source: TriggerSource) {
+        self.triggers.retain(|(s, _)| *s != source);
+    }
+
+    pub fn on(&self, event: TriggerEvent) -> impl Iterator<Item = &Trigger> + '_ {
+        self.triggers
+            .iter()
+            .map(|(_, trigger)| trigger)
+            .filter(move |trigger| trigger.on == event)
+    }
+}
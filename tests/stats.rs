@@ -0,0 +1,23 @@
+use aigame::stats::{BaseStats, Dmg, DmgTable, DmgType, SeededRng, Stat, Stats};
+
+#[test]
+fn new_stats_are_accurate() {
+    let stats = Stats::new(BaseStats::ones());
+    assert_eq!(stats[Stat::Accuracy], 1.0);
+}
+
+#[test]
+fn dodgeable_hits_land() {
+    let attacker = Stats::new(BaseStats::ones());
+    let mut defender = Stats::new(BaseStats::ones());
+    let types = DmgTable::default();
+    let mut rng = SeededRng::new(1);
+    let dmg = Dmg::new(vec![(DmgType::Physical, 10.0)].into_iter().collect(), true, 1.0);
+    let hits = (0..100)
+        .filter(|_| {
+            let dealt = dmg.calculate_dealt(&attacker, &types, &mut rng);
+            defender.apply_damage(&dealt, &types, &mut rng).is_hit()
+        })
+        .count();
+    assert!(hits > 0);
+}